@@ -0,0 +1,359 @@
+/**
+ * Issues signed connect tokens for the game server
+ *
+ * This is meant to run next to the server on a trusted machine.
+ * Clients ask for a token before every connection attempt:
+ *
+ *   POST /token
+ *   account=<account id>&name=<character name>&password=<password>
+ *
+ * Only accounts listed in the accounts file get a token, and only with
+ * their password. The response body is the binary connect token.
+ */
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context};
+use bevy_renet::renet::transport::ConnectToken;
+use clap::Parser;
+use tiled_game::network::{
+    user_data::{
+        is_valid_character_name, parse_private_key, ConnectionUserData, PRIVATE_KEY_BYTES,
+    },
+    PROTOCOL_ID,
+};
+
+#[derive(Parser, Debug)]
+#[command(about = "TiledMMO connect token issuer")]
+struct Args {
+    // Address the HTTP listener binds to
+    #[arg(long, default_value = "127.0.0.1:3388")]
+    listen: SocketAddr,
+
+    // Public address of the game server
+    #[arg(long, default_value = "127.0.0.1:3387")]
+    server_addr: SocketAddr,
+
+    // Hex encoded key, must match the private_key of the server
+    #[arg(long)]
+    private_key: String,
+
+    // Seconds until an unused token expires
+    #[arg(long, default_value_t = 300)]
+    token_lifetime: u64,
+
+    // Seconds without packets until the connection times out
+    #[arg(long, default_value_t = 15)]
+    timeout: i32,
+
+    // TOML file with the password of every account allowed to play
+    #[arg(long, default_value = "accounts.toml")]
+    accounts: PathBuf,
+}
+
+// Longest request body we are willing to read
+const MAX_BODY_BYTES: usize = 1024;
+
+// A client that sends or reads slowly only holds up its own connection for this long
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Connections handled at once, further ones are closed right away
+const MAX_CONNECTIONS: usize = 64;
+
+struct TokenRequest {
+    account_id: u64,
+    character_name: String,
+    password: String,
+}
+
+struct TokenIssuer {
+    private_key: [u8; PRIVATE_KEY_BYTES],
+    server_addr: SocketAddr,
+    token_lifetime: u64,
+    timeout: i32,
+    accounts: HashMap<u64, String>,
+
+    // Every token gets its own client id so reconnects don't collide
+    next_client_id: AtomicU64,
+}
+
+impl TokenIssuer {
+    fn check_password(&self, account_id: u64, password: &str) -> bool {
+        self.accounts
+            .get(&account_id)
+            .is_some_and(|expected| constant_time_eq(expected.as_bytes(), password.as_bytes()))
+    }
+
+    fn issue(&self, account_id: u64, character_name: String) -> anyhow::Result<Vec<u8>> {
+        let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+        let client_id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+
+        let user_data = ConnectionUserData::new(account_id, character_name)
+            .to_bytes()
+            .ok_or_else(|| anyhow!("User data does not fit into the token"))?;
+
+        let token = ConnectToken::generate(
+            current_time,
+            PROTOCOL_ID,
+            self.token_lifetime,
+            client_id,
+            self.timeout,
+            vec![self.server_addr],
+            Some(&user_data),
+            &self.private_key,
+        )
+        .map_err(|e| anyhow!("Could not generate token: {:?}", e))?;
+
+        let mut bytes = Vec::new();
+        token.write(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let private_key = parse_private_key(&args.private_key)
+        .ok_or_else(|| anyhow!("private_key must be 64 hex characters"))?;
+
+    let accounts = load_accounts(&args.accounts)?;
+
+    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+    let issuer = Arc::new(TokenIssuer {
+        private_key,
+        server_addr: args.server_addr,
+        token_lifetime: args.token_lifetime,
+        timeout: args.timeout,
+        accounts,
+        next_client_id: AtomicU64::new(current_time.as_millis() as u64),
+    });
+
+    let listener = TcpListener::bind(args.listen)
+        .with_context(|| format!("Could not bind to {}", args.listen))?;
+
+    println!(
+        "Issuing tokens for {} accounts on {} on http://{}",
+        issuer.accounts.len(),
+        args.server_addr,
+        args.listen
+    );
+
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("Connection failed: {}", e);
+                continue;
+            }
+        };
+
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            println!("Too many connections, closing {:?}", stream.peer_addr());
+            continue;
+        }
+
+        // every connection gets its own thread, a slow client can't hold up the others
+        let issuer = issuer.clone();
+        let connections = connections.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_request(&issuer, stream) {
+                println!("Request failed: {}", e);
+            }
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    Ok(())
+}
+
+// Account ids mapped to their password, e.g. `1 = "secret"`
+fn load_accounts(path: &Path) -> anyhow::Result<HashMap<u64, String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Could not read accounts file {:?}", path))?;
+    let accounts: HashMap<String, String> =
+        toml::from_str(&content).with_context(|| format!("Invalid accounts file {:?}", path))?;
+
+    accounts
+        .into_iter()
+        .map(|(account_id, password)| {
+            let account_id = account_id
+                .parse()
+                .with_context(|| format!("Invalid account id {:?}", account_id))?;
+            Ok((account_id, password))
+        })
+        .collect()
+}
+
+fn handle_request(issuer: &TokenIssuer, mut stream: TcpStream) -> anyhow::Result<()> {
+    let deadline = Instant::now() + CONNECTION_TIMEOUT;
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+
+    let response = match read_token_request(Deadline(&stream, deadline)) {
+        Ok(request) if issuer.check_password(request.account_id, &request.password) => {
+            println!(
+                "Issuing token for account {} ({})",
+                request.account_id, request.character_name
+            );
+
+            match issuer.issue(request.account_id, request.character_name) {
+                Ok(token) => (200, "OK", token),
+                Err(e) => (500, "Internal Server Error", e.to_string().into_bytes()),
+            }
+        }
+        Ok(request) => {
+            println!("Wrong password for account {}", request.account_id);
+            (403, "Forbidden", b"Wrong account or password".to_vec())
+        }
+        Err(e) => (400, "Bad Request", e.to_string().into_bytes()),
+    };
+
+    let (status, reason, body) = response;
+    write!(
+        stream,
+        "HTTP/1.0 {} {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+        status,
+        reason,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+
+    Ok(())
+}
+
+// Stream that fails once the deadline passed, however slowly the bytes trickle in
+struct Deadline<'a>(&'a TcpStream, Instant);
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.1.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        self.0.set_read_timeout(Some(remaining))?;
+        self.0.read(buf)
+    }
+}
+
+// Reads "POST /token HTTP/1.0" with a form encoded body
+fn read_token_request(stream: Deadline) -> anyhow::Result<TokenRequest> {
+    let mut reader = BufReader::new(stream).take(MAX_BODY_BYTES as u64 * 4);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    if parts.next() != Some("POST") {
+        bail!("Only POST is supported");
+    }
+    if parts.next() != Some("/token") {
+        bail!("Unknown path");
+    }
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            bail!("Unexpected end of request");
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>()?;
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_BYTES {
+        bail!("Request body is too large");
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    parse_token_request(std::str::from_utf8(&body)?)
+}
+
+// Reads account, character name and password from "account=1&name=Foo&password=bar"
+fn parse_token_request(form: &str) -> anyhow::Result<TokenRequest> {
+    let mut account_id = None;
+    let mut character_name = None;
+    let mut password = None;
+
+    for pair in form.split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value)?;
+
+        match key {
+            "account" => account_id = Some(value.parse::<u64>()?),
+            "name" => character_name = Some(value),
+            "password" => password = Some(value),
+            _ => {}
+        }
+    }
+
+    let account_id = account_id.ok_or_else(|| anyhow!("Missing account"))?;
+    let character_name = character_name.ok_or_else(|| anyhow!("Missing name"))?;
+    let password = password.ok_or_else(|| anyhow!("Missing password"))?;
+
+    if !is_valid_character_name(&character_name) {
+        bail!("Invalid character name");
+    }
+
+    Ok(TokenRequest {
+        account_id,
+        character_name,
+        password,
+    })
+}
+
+// Takes as long for a wrong first byte as for a wrong last one
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn percent_decode(value: &str) -> anyhow::Result<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut chars = value.bytes();
+
+    while let Some(byte) = chars.next() {
+        match byte {
+            b'%' => {
+                let hex = [
+                    chars.next().ok_or_else(|| anyhow!("Invalid escape"))?,
+                    chars.next().ok_or_else(|| anyhow!("Invalid escape"))?,
+                ];
+                let hex = std::str::from_utf8(&hex)?;
+                bytes.push(u8::from_str_radix(hex, 16)?);
+            }
+            b'+' => bytes.push(b' '),
+            _ => bytes.push(byte),
+        }
+    }
+
+    Ok(String::from_utf8(bytes)?)
+}
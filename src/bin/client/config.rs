@@ -0,0 +1,144 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use bevy::prelude::Resource;
+use clap::Parser;
+use serde::Deserialize;
+
+// Used when no --config argument is given and the file exists
+const DEFAULT_CONFIG_FILE: &str = "client.toml";
+
+#[derive(Resource, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ClientConfig {
+    pub server_addr: SocketAddr,
+
+    // Address of the token issuer
+    // Without it the client connects in unsecure mode
+    pub auth_addr: Option<SocketAddr>,
+
+    pub account_id: u64,
+
+    // Sent to the token issuer, not needed in unsecure mode
+    pub password: String,
+
+    // Character highlighted on the character select screen
+    pub name: String,
+
+    // How often the client tries to reconnect after losing the connection
+    // before it goes back to the connect screen
+    pub reconnect_attempts: u32,
+
+    // How far other entities are shown behind the server, in milliseconds
+    // Higher values hide more network jitter but make everything else lag behind
+    pub interpolation_delay_ms: u64,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            server_addr: "127.0.0.1:3387".parse().unwrap(),
+            auth_addr: None,
+            account_id: 0,
+            password: String::new(),
+            name: "John Doe".to_string(),
+            reconnect_attempts: 5,
+            interpolation_delay_ms: 100,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "TiledMMO game client")]
+struct Args {
+    // Path to the TOML config file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    #[arg(short, long)]
+    server: Option<SocketAddr>,
+
+    #[arg(long)]
+    auth: Option<SocketAddr>,
+
+    #[arg(long)]
+    account: Option<u64>,
+
+    #[arg(long)]
+    password: Option<String>,
+
+    #[arg(short, long)]
+    name: Option<String>,
+
+    #[arg(long)]
+    reconnect_attempts: Option<u32>,
+
+    #[arg(long)]
+    interpolation_delay: Option<u64>,
+}
+
+impl ClientConfig {
+    // Reads the config file and applies the command line overrides
+    pub fn load() -> Self {
+        let args = Args::parse();
+
+        let config_file = args
+            .config
+            .clone()
+            .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()));
+
+        let mut config = match config_file {
+            Some(path) => Self::from_file(&path).expect("Could not load client config"),
+            None => Self::default(),
+        };
+
+        config.apply_args(args);
+        config
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read config file {:?}", path))?;
+
+        toml::from_str(&content).with_context(|| format!("Invalid config file {:?}", path))
+    }
+
+    fn apply_args(&mut self, args: Args) {
+        if let Some(server_addr) = args.server {
+            self.server_addr = server_addr;
+        }
+
+        if let Some(auth_addr) = args.auth {
+            self.auth_addr = Some(auth_addr);
+        }
+
+        if let Some(account_id) = args.account {
+            self.account_id = account_id;
+        }
+
+        if let Some(password) = args.password {
+            self.password = password;
+        }
+
+        if let Some(name) = args.name {
+            self.name = name;
+        }
+
+        if let Some(reconnect_attempts) = args.reconnect_attempts {
+            self.reconnect_attempts = reconnect_attempts;
+        }
+
+        if let Some(interpolation_delay) = args.interpolation_delay {
+            self.interpolation_delay_ms = interpolation_delay;
+        }
+    }
+
+    pub fn interpolation_delay(&self) -> Duration {
+        Duration::from_millis(self.interpolation_delay_ms)
+    }
+}
@@ -0,0 +1,117 @@
+use bevy::prelude::*;
+use bevy_renet::renet::RenetClient;
+use tiled_game::{abilities::Ability, network::messages::client::ClientMessages};
+
+use crate::network::{character_select::GameState, chat::ChatBox, send_message, ServerSideEntity};
+
+use super::player::PlayerTarget;
+
+// Number keys 1 to 9 use the abilities in the order the server sent them
+const ABILITY_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+// Abilities the server lets the player use
+#[derive(Resource, Default)]
+pub struct ActionBar {
+    pub abilities: Vec<Ability>,
+}
+
+#[derive(Component)]
+struct ActionBarText;
+
+pub struct AbilitiesPlugin;
+
+impl Plugin for AbilitiesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActionBar>()
+            .add_systems(OnEnter(GameState::InGame), spawn_action_bar)
+            .add_systems(OnExit(GameState::InGame), despawn_action_bar)
+            .add_systems(
+                Update,
+                (use_abilities, update_action_bar).run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+fn spawn_action_bar(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("OpenSans-Regular.ttf"),
+                font_size: 16.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            right: Val::Px(10.0),
+            bottom: Val::Px(10.0),
+            ..default()
+        }),
+        ActionBarText,
+    ));
+}
+
+fn despawn_action_bar(mut commands: Commands, texts: Query<Entity, With<ActionBarText>>) {
+    for entity in texts.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// The server checks range, mana and cooldowns and tells us when a cast fails
+fn use_abilities(
+    keyboard_input: Res<Input<KeyCode>>,
+    chat: Res<ChatBox>,
+    action_bar: Res<ActionBar>,
+    target: Query<&ServerSideEntity, With<PlayerTarget>>,
+    mut client: ResMut<RenetClient>,
+) {
+    if chat.focused {
+        return;
+    }
+
+    for (key, ability) in ABILITY_KEYS.iter().zip(action_bar.abilities.iter()) {
+        if !keyboard_input.just_pressed(*key) {
+            continue;
+        }
+
+        send_message(
+            &mut client,
+            &ClientMessages::CastAbility {
+                ability: ability.id.clone(),
+                target: target.get_single().ok().map(|target| target.0),
+            },
+        );
+    }
+}
+
+fn update_action_bar(action_bar: Res<ActionBar>, mut texts: Query<&mut Text, With<ActionBarText>>) {
+    if !action_bar.is_changed() {
+        return;
+    }
+
+    let lines: Vec<String> = action_bar
+        .abilities
+        .iter()
+        .take(ABILITY_KEYS.len())
+        .enumerate()
+        .map(|(i, ability)| match ability.mana_cost {
+            0 => format!("{} {}", i + 1, ability.name),
+            cost => format!("{} {} ({} mana)", i + 1, ability.name, cost),
+        })
+        .collect();
+
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
@@ -1,16 +1,17 @@
 use bevy::{
     log,
     prelude::{
-        Added, AssetEvent, Assets, Commands, EventReader, Handle, Name, Query, Res, Transform,
+        Added, AssetEvent, Assets, Commands, EventReader, Handle, Name, Query, Res, ResMut,
+        Transform,
     },
     transform::TransformBundle,
 };
 use bevy_rapier2d::prelude::Collider;
-use tiled::{LayerType, ObjectShape};
+use tiled_game::collision::CollisionMap;
 
 use crate::game::map::MapName;
 
-use super::{tiled::TiledMap, CurrentMap};
+use super::{tiled::TiledMap, CurrentMap, MapCollision, MapLoadingState};
 
 /**
  * Calculate collisions when a new tilemap gets loaded
@@ -18,6 +19,7 @@ use super::{tiled::TiledMap, CurrentMap};
 pub fn load_collision(
     maps: Res<Assets<TiledMap>>,
     map_name: Res<CurrentMap>,
+    mut loading_state: ResMut<MapLoadingState>,
     mut map_events: EventReader<AssetEvent<TiledMap>>,
     loaded_maps: Query<&Handle<TiledMap>, Added<Handle<TiledMap>>>,
     mut commands: Commands,
@@ -37,51 +39,37 @@ pub fn load_collision(
                 changed_maps.retain(|changed_handle| changed_handle == handle);
             }
         }
+    }
 
-        // If we have new map entities add them to the changed_maps list.
-        for new_map_handle in loaded_maps.iter() {
-            changed_maps.push(new_map_handle.clone_weak());
-        }
+    // If we have new map entities add them to the changed_maps list.
+    // The asset might already be loaded, in that case there is no event
+    for new_map_handle in loaded_maps.iter() {
+        changed_maps.push(new_map_handle.clone_weak());
+    }
 
-        for changed_map in changed_maps.iter() {
-            // load tilemap from maps
-            let tilemap = maps.get(changed_map);
-            if tilemap.is_none() {
-                log::info!("No tilemap found");
-                return;
-            }
+    for changed_map in changed_maps.iter() {
+        // load tilemap from maps
+        let Some(tilemap_container) = maps.get(changed_map) else {
+            log::info!("No tilemap found");
+            continue;
+        };
 
-            tilemap.map(|tilemap_container| {
-                let map = &tilemap_container.map;
+        let collision_map = CollisionMap::from_tiled(&tilemap_container.map);
 
-                map.layers()
-                    .find(|layer| layer.name == "collision")
-                    .and_then(|layer| {
-                        // match as ObjectLayer and give back Result
-                        match layer.layer_type() {
-                            LayerType::Objects(layer) => Some(layer),
-                            _ => None,
-                        }
-                    })
-                    .map(|layer| layer.objects())
-                    .expect("No collision layer found")
-                    .for_each(|object| match object.shape {
-                        ObjectShape::Rect { width, height } => {
-                            log::info!("Loading collision object");
-                            // spawn fixed collision box using width and hight
-                            commands
-                                .spawn(Collider::cuboid(width / 2., height / 2.))
-                                .insert(TransformBundle::from(Transform::from_xyz(
-                                    object.x + width / 2.,
-                                    (-object.y - height / 2.)
-                                        + (map.height * map.tile_height) as f32,
-                                    0.0,
-                                )))
-                                .insert((MapName(map_name.name.clone()), Name::new("Collider")));
-                        }
-                        _ => {}
-                    })
-            });
+        for rect in collision_map.rects.iter() {
+            log::info!("Loading collision object");
+            // spawn fixed collision box using width and hight
+            let half_size = rect.half_size();
+            let center = rect.center();
+            commands
+                .spawn(Collider::cuboid(half_size.x, half_size.y))
+                .insert(TransformBundle::from(Transform::from_xyz(
+                    center.x, center.y, 0.0,
+                )))
+                .insert((MapName(map_name.name.clone()), Name::new("Collider")));
         }
+
+        commands.insert_resource(MapCollision(collision_map));
+        loading_state.collision_loaded = true;
     }
 }
@@ -1,6 +1,7 @@
 use bevy::log;
 use bevy::prelude::*;
 use bevy_ecs_tilemap::TilemapPlugin;
+use tiled_game::collision::CollisionMap;
 
 use self::collision::*;
 use self::tiled::*;
@@ -17,6 +18,8 @@ impl Plugin for MapPlugin {
             .add_plugins(TilemapPlugin)
             .add_asset_loader(TiledLoader)
             .init_resource::<CurrentMap>()
+            .init_resource::<MapLoadingState>()
+            .init_resource::<MapCollision>()
             .add_event::<MapChangeEvent>()
             .add_systems(
                 Update,
@@ -32,6 +35,29 @@ pub struct CurrentMap {
     pub player_plane_z: Option<i8>,
 }
 
+/**
+ * Tracks which parts of the current map are loaded.
+ * The server waits for the client to report that everything is loaded
+ * before it sends the entities of the map.
+ */
+#[derive(Resource, Default)]
+pub struct MapLoadingState {
+    pub tiles_loaded: bool,
+    pub collision_loaded: bool,
+}
+
+impl MapLoadingState {
+    pub fn is_loaded(&self) -> bool {
+        self.tiles_loaded && self.collision_loaded
+    }
+}
+
+/**
+ * Blocking areas of the current map, used to predict the player's movement
+ */
+#[derive(Resource, Default)]
+pub struct MapCollision(pub CollisionMap);
+
 /**
  * Insert this component to every entity that should be unloaded on a map change.
  */
@@ -79,6 +105,8 @@ pub fn change_map(
             name: event.map_name.clone(),
             ..default()
         });
+        commands.insert_resource(MapLoadingState::default());
+        commands.insert_resource(MapCollision::default());
 
         let map_handle: Handle<TiledMap> = asset_server.load(format!("{}", event.map_name));
 
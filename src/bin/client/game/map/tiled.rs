@@ -27,7 +27,7 @@ use bevy_ecs_tilemap::prelude::*;
 
 use anyhow::Result;
 
-use super::{CurrentMap, MapName};
+use super::{CurrentMap, MapLoadingState, MapName};
 
 #[derive(Default)]
 pub struct TiledMapPlugin;
@@ -184,6 +184,7 @@ pub fn process_loaded_maps(
     mut commands: Commands,
     mut map_events: EventReader<AssetEvent<TiledMap>>,
     currently_active_map: Res<CurrentMap>,
+    mut loading_state: ResMut<MapLoadingState>,
     maps: Res<Assets<TiledMap>>,
     tile_storage_query: Query<(Entity, &TileStorage)>,
     mut map_query: Query<(&Handle<TiledMap>, &mut TiledLayersStorage)>,
@@ -387,6 +388,8 @@ pub fn process_loaded_maps(
                             .insert(layer_index as u32, layer_entity);
                     }
                 }
+
+                loading_state.tiles_loaded = true;
             }
         }
     }
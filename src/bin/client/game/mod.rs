@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 
+pub mod abilities;
 pub mod components;
 pub mod map;
 pub mod player;
@@ -19,6 +20,7 @@ impl Plugin for GamePlugin {
             .add_system(set_y_to_z_transform)
             .add_system(cursor_system)
             .add_plugin(unit::UnitPlugin)
-            .add_plugin(spritesheet::SpriteSheetPlugin);
+            .add_plugin(spritesheet::SpriteSheetPlugin)
+            .add_plugins(abilities::AbilitiesPlugin);
     }
 }
@@ -1,8 +1,11 @@
 use bevy::prelude::*;
+use tiled_game::movement::INPUT_TICK_SECONDS;
 
-use self::movement::player_movement;
+use self::movement::{
+    predict_player_movement, reconcile_player_position, MoveAckEvent, PredictionState,
+};
 
-mod movement;
+pub mod movement;
 
 #[derive(Component)]
 pub struct Player;
@@ -14,6 +17,11 @@ pub struct PlayerTarget;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(player_movement);
+        // inputs are sampled at the rate the server simulates them
+        app.insert_resource(FixedTime::new_from_secs(INPUT_TICK_SECONDS))
+            .init_resource::<PredictionState>()
+            .add_event::<MoveAckEvent>()
+            .add_systems(FixedUpdate, predict_player_movement)
+            .add_systems(Update, reconcile_player_position);
     }
 }
@@ -1,12 +1,62 @@
-use bevy::prelude::{Input, KeyCode, Query, Res, Transform, Vec3, With, Without};
-use tiled_game::components::Dead;
+use std::collections::VecDeque;
 
-use crate::game::spritesheet::{AnimateState, MovementState};
+use bevy::prelude::*;
+use tiled_game::{
+    components::Dead,
+    movement::{apply_input, MoveInput},
+};
+
+use crate::{
+    game::{
+        map::MapCollision,
+        spritesheet::{AnimateState, MovementState},
+    },
+    network::chat::ChatBox,
+};
 
 use super::Player;
 
-pub fn player_movement(
+// Oldest inputs are forgotten when the server stops acknowledging
+const MAX_PENDING_INPUTS: usize = 120;
+
+// Inputs the server has not acknowledged yet
+// Replayed on top of every acknowledged position
+#[derive(Resource)]
+pub struct PredictionState {
+    next_sequence: u32,
+    // Acknowledgements arrive unreliably and can be out of order
+    acknowledged: u32,
+    pub pending: VecDeque<MoveInput>,
+    // Speed the server reported with the last acknowledgement
+    speed: f32,
+}
+
+impl Default for PredictionState {
+    fn default() -> Self {
+        Self {
+            next_sequence: 1,
+            acknowledged: 0,
+            pending: VecDeque::new(),
+            speed: 1.,
+        }
+    }
+}
+
+// The server applied the inputs up to sequence and the player ended up at position
+#[derive(Event)]
+pub struct MoveAckEvent {
+    pub sequence: u32,
+    pub position: Vec2,
+    pub speed: f32,
+}
+
+// Samples the keyboard once per input tick and moves the player right away
+// The server runs the same step and corrects us if it disagrees
+pub fn predict_player_movement(
     keyboard_input: Res<Input<KeyCode>>,
+    chat: Res<ChatBox>,
+    collision: Res<MapCollision>,
+    mut prediction: ResMut<PredictionState>,
     mut player: Query<(&mut Transform, &mut AnimateState), (With<Player>, Without<Dead>)>,
 ) {
     if player.is_empty() {
@@ -15,34 +65,92 @@ pub fn player_movement(
 
     let (mut transform, mut state) = player.single_mut();
 
-    if !keyboard_input.any_pressed(vec![KeyCode::A, KeyCode::D, KeyCode::W, KeyCode::S]) {
+    // the keys are typing a message
+    if chat.focused {
         state.0 = MovementState::Idle;
         return;
     }
 
-    let mut speed: f32 = 1.;
-
-    if keyboard_input.pressed(KeyCode::ShiftLeft) {
-        speed = 2.;
-    }
-    let mut direction = Vec3::ZERO;
+    let mut input = MoveInput {
+        sequence: prediction.next_sequence,
+        sprint: keyboard_input.pressed(KeyCode::ShiftLeft),
+        ..default()
+    };
 
     if keyboard_input.pressed(KeyCode::A) {
-        direction += Vec3::NEG_X;
+        input.x -= 1;
     }
     if keyboard_input.pressed(KeyCode::D) {
-        direction += Vec3::X;
+        input.x += 1;
     }
     if keyboard_input.pressed(KeyCode::W) {
-        direction += Vec3::Y;
+        input.y += 1;
     }
     if keyboard_input.pressed(KeyCode::S) {
-        direction += Vec3::NEG_Y;
+        input.y -= 1;
+    }
+
+    // standing still is not worth sending
+    if input.is_idle() {
+        state.0 = MovementState::Idle;
+        return;
     }
 
     if state.0 != MovementState::Moving {
         state.0 = MovementState::Moving;
     }
 
-    transform.translation += direction * speed;
+    let position = apply_input(
+        transform.translation.truncate(),
+        &input,
+        prediction.speed,
+        Some(&collision.0),
+    );
+    transform.translation.x = position.x;
+    transform.translation.y = position.y;
+
+    prediction.next_sequence += 1;
+    prediction.pending.push_back(input);
+    if prediction.pending.len() > MAX_PENDING_INPUTS {
+        prediction.pending.pop_front();
+    }
+}
+
+// Moves the player to where the server says it is
+// and replays the inputs the server hasn't seen yet
+pub fn reconcile_player_position(
+    mut move_acks: EventReader<MoveAckEvent>,
+    collision: Res<MapCollision>,
+    mut prediction: ResMut<PredictionState>,
+    mut player: Query<&mut Transform, With<Player>>,
+) {
+    let Some(ack) = move_acks
+        .iter()
+        .filter(|ack| ack.sequence > prediction.acknowledged)
+        .max_by_key(|ack| ack.sequence)
+    else {
+        return;
+    };
+
+    let Ok(mut transform) = player.get_single_mut() else {
+        return;
+    };
+
+    prediction.acknowledged = ack.sequence;
+    prediction.speed = ack.speed;
+    prediction
+        .pending
+        .retain(|input| input.sequence > ack.sequence);
+
+    let position = prediction
+        .pending
+        .iter()
+        .fold(ack.position, |position, input| {
+            apply_input(position, input, ack.speed, Some(&collision.0))
+        });
+
+    if position.distance(transform.translation.truncate()) > 0.01 {
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
 }
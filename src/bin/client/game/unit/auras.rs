@@ -0,0 +1,185 @@
+use bevy::prelude::*;
+use tiled_game::components::{Health, Level, MaxHealth};
+
+use crate::{game::player::PlayerTarget, network::character_select::GameState};
+
+const FRAME_WIDTH: f32 = 240.;
+
+// An aura as the server last described it, the server also tells us when it's gone
+#[derive(Clone)]
+pub struct ClientAura {
+    pub id: String,
+    pub name: String,
+    pub stacks: u32,
+    pub harmful: bool,
+    pub timer: Timer,
+}
+
+// Buffs and debuffs on a unit
+#[derive(Component, Default)]
+pub struct Auras(pub Vec<ClientAura>);
+
+#[derive(Event)]
+pub enum AuraUpdateEvent {
+    // Also sent when an aura is refreshed or gets another stack
+    Applied { entity: Entity, aura: ClientAura },
+    Removed { entity: Entity, aura: String },
+}
+
+// Name, level, health and auras of the targeted unit
+#[derive(Component)]
+struct TargetFrame;
+
+#[derive(Component)]
+struct TargetFrameText;
+
+pub struct AurasPlugin;
+
+impl Plugin for AurasPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<AuraUpdateEvent>()
+            .add_systems(OnEnter(GameState::InGame), spawn_target_frame)
+            .add_systems(OnExit(GameState::InGame), despawn_target_frame)
+            .add_systems(
+                Update,
+                (
+                    tick_auras,
+                    update_target_frame.run_if(in_state(GameState::InGame)),
+                )
+                    .chain(),
+            );
+    }
+}
+
+// Runs after the server messages were handled, so units spawned in the same frame have their Auras
+pub fn apply_aura_updates(
+    mut aura_updates: EventReader<AuraUpdateEvent>,
+    mut units: Query<&mut Auras>,
+) {
+    for update in aura_updates.iter() {
+        match update {
+            AuraUpdateEvent::Applied { entity, aura } => {
+                let Ok(mut auras) = units.get_mut(*entity) else {
+                    continue;
+                };
+
+                let aura = aura.clone();
+                match auras.0.iter_mut().find(|active| active.id == aura.id) {
+                    Some(active) => *active = aura,
+                    None => auras.0.push(aura),
+                }
+            }
+            AuraUpdateEvent::Removed { entity, aura } => {
+                if let Ok(mut auras) = units.get_mut(*entity) {
+                    auras.0.retain(|active| active.id != *aura);
+                }
+            }
+        }
+    }
+}
+
+fn tick_auras(time: Res<Time>, mut units: Query<&mut Auras>) {
+    for mut auras in units.iter_mut() {
+        if auras.0.is_empty() {
+            continue;
+        }
+
+        for aura in auras.0.iter_mut() {
+            aura.timer.tick(time.delta());
+        }
+    }
+}
+
+fn spawn_target_frame(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Percent(50.),
+                    top: Val::Px(10.),
+                    margin: UiRect::left(Val::Px(-FRAME_WIDTH / 2.)),
+                    width: Val::Px(FRAME_WIDTH),
+                    padding: UiRect::all(Val::Px(6.)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            TargetFrame,
+        ))
+        .with_children(|frame| {
+            frame.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("OpenSans-Regular.ttf"),
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                TargetFrameText,
+            ));
+        });
+}
+
+fn despawn_target_frame(mut commands: Commands, frames: Query<Entity, With<TargetFrame>>) {
+    for entity in frames.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn update_target_frame(
+    target: Query<(&Name, &Level, &Health, &MaxHealth, Option<&Auras>), With<PlayerTarget>>,
+    mut frames: Query<&mut Visibility, With<TargetFrame>>,
+    mut texts: Query<&mut Text, With<TargetFrameText>>,
+) {
+    let target = target.get_single().ok();
+
+    for mut visibility in frames.iter_mut() {
+        *visibility = match target {
+            Some(_) => Visibility::Inherited,
+            None => Visibility::Hidden,
+        };
+    }
+
+    let Some((name, level, health, max_health, auras)) = target else {
+        return;
+    };
+
+    for mut text in texts.iter_mut() {
+        let style = text.sections[0].style.clone();
+        let mut sections = vec![TextSection::new(
+            format!("{} ({}) {}/{}", name, level.0, health.0, max_health.0),
+            style.clone(),
+        )];
+
+        // buffs in green and debuffs in red, one per line
+        for aura in auras.iter().flat_map(|auras| auras.0.iter()) {
+            let stacks = match aura.stacks {
+                1 => String::new(),
+                stacks => format!(" x{}", stacks),
+            };
+
+            sections.push(TextSection::new(
+                format!(
+                    "\n{}{} {:.0}s",
+                    aura.name,
+                    stacks,
+                    aura.timer.remaining_secs().ceil()
+                ),
+                TextStyle {
+                    color: if aura.harmful {
+                        Color::RED
+                    } else {
+                        Color::GREEN
+                    },
+                    ..style.clone()
+                },
+            ));
+        }
+
+        text.sections = sections;
+    }
+}
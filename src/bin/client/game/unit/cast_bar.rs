@@ -0,0 +1,229 @@
+use bevy::{prelude::*, sprite::Anchor};
+
+use crate::{game::player::Player, network::character_select::GameState};
+
+const BAR_WIDTH: f32 = 32.;
+const BAR_HEIGHT: f32 = 4.;
+
+// Above the name label of the unit
+const BAR_OFFSET: f32 = 32.;
+
+const PLAYER_BAR_WIDTH: f32 = 200.;
+
+// The unit is casting, set and removed by the cast messages of the server
+#[derive(Component)]
+pub struct Casting {
+    pub name: String,
+    pub timer: Timer,
+}
+
+impl Casting {
+    pub fn new(name: String, duration: f32) -> Self {
+        Self {
+            name,
+            timer: Timer::from_seconds(duration, TimerMode::Once),
+        }
+    }
+}
+
+// Background of the bar over a unit, child of the unit
+#[derive(Component)]
+struct CastBar;
+
+// Grows with the progress of the cast, child of the CastBar
+#[derive(Component)]
+struct CastBarFill;
+
+// Cast bar of the own player in the UI
+#[derive(Component)]
+struct PlayerCastBar;
+
+#[derive(Component)]
+struct PlayerCastBarFill;
+
+#[derive(Component)]
+struct PlayerCastBarText;
+
+pub struct CastBarPlugin;
+
+impl Plugin for CastBarPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::InGame), spawn_player_cast_bar)
+            .add_systems(OnExit(GameState::InGame), despawn_player_cast_bar)
+            .add_systems(
+                Update,
+                (
+                    (
+                        tick_casts,
+                        remove_cast_bars,
+                        spawn_cast_bars,
+                        update_cast_bars,
+                    )
+                        .chain(),
+                    update_player_cast_bar.run_if(in_state(GameState::InGame)),
+                ),
+            );
+    }
+}
+
+// The server ends the cast, the bar just stays full until it does
+fn tick_casts(time: Res<Time>, mut casts: Query<&mut Casting>) {
+    for mut casting in casts.iter_mut() {
+        casting.timer.tick(time.delta());
+    }
+}
+
+// The server always ends a cast before the next one starts
+fn spawn_cast_bars(mut commands: Commands, casts: Query<Entity, Added<Casting>>) {
+    for entity in casts.iter() {
+        let bar = commands
+            .spawn((
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::rgba(0.0, 0.0, 0.0, 0.6),
+                        custom_size: Some(Vec2::new(BAR_WIDTH, BAR_HEIGHT)),
+                        ..default()
+                    },
+                    transform: Transform::from_xyz(0., BAR_OFFSET, 0.1),
+                    ..default()
+                },
+                CastBar,
+            ))
+            .with_children(|bar| {
+                bar.spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            color: Color::GOLD,
+                            custom_size: Some(Vec2::new(0., BAR_HEIGHT)),
+                            anchor: Anchor::CenterLeft,
+                            ..default()
+                        },
+                        transform: Transform::from_xyz(-BAR_WIDTH / 2., 0., 0.1),
+                        ..default()
+                    },
+                    CastBarFill,
+                ));
+            })
+            .id();
+
+        commands.entity(entity).add_child(bar);
+    }
+}
+
+fn update_cast_bars(
+    casts: Query<&Casting>,
+    bars: Query<&Parent, With<CastBar>>,
+    mut fills: Query<(&Parent, &mut Sprite), With<CastBarFill>>,
+) {
+    for (bar, mut sprite) in fills.iter_mut() {
+        let Some(casting) = bars
+            .get(bar.get())
+            .ok()
+            .and_then(|unit| casts.get(unit.get()).ok())
+        else {
+            continue;
+        };
+
+        sprite.custom_size = Some(Vec2::new(BAR_WIDTH * casting.timer.percent(), BAR_HEIGHT));
+    }
+}
+
+fn remove_cast_bars(
+    mut commands: Commands,
+    mut removed: RemovedComponents<Casting>,
+    bars: Query<(Entity, &Parent), With<CastBar>>,
+) {
+    for entity in removed.iter() {
+        for (bar, parent) in bars.iter() {
+            if parent.get() == entity {
+                commands.entity(bar).despawn_recursive();
+            }
+        }
+    }
+}
+
+fn spawn_player_cast_bar(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Percent(50.),
+                    bottom: Val::Px(80.),
+                    margin: UiRect::left(Val::Px(-PLAYER_BAR_WIDTH / 2.)),
+                    width: Val::Px(PLAYER_BAR_WIDTH),
+                    height: Val::Px(20.),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            PlayerCastBar,
+        ))
+        .with_children(|bar| {
+            bar.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        left: Val::Px(0.),
+                        width: Val::Percent(0.),
+                        height: Val::Percent(100.),
+                        ..default()
+                    },
+                    background_color: Color::GOLD.into(),
+                    ..default()
+                },
+                PlayerCastBarFill,
+            ));
+            bar.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("OpenSans-Regular.ttf"),
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                PlayerCastBarText,
+            ));
+        });
+}
+
+fn despawn_player_cast_bar(mut commands: Commands, bars: Query<Entity, With<PlayerCastBar>>) {
+    for entity in bars.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn update_player_cast_bar(
+    player: Query<Option<&Casting>, With<Player>>,
+    mut bars: Query<&mut Visibility, With<PlayerCastBar>>,
+    mut fills: Query<&mut Style, With<PlayerCastBarFill>>,
+    mut texts: Query<&mut Text, With<PlayerCastBarText>>,
+) {
+    let casting = player.get_single().ok().flatten();
+
+    for mut visibility in bars.iter_mut() {
+        *visibility = match casting {
+            Some(_) => Visibility::Inherited,
+            None => Visibility::Hidden,
+        };
+    }
+
+    let Some(casting) = casting else {
+        return;
+    };
+
+    for mut style in fills.iter_mut() {
+        style.width = Val::Percent(casting.timer.percent() * 100.);
+    }
+
+    for mut text in texts.iter_mut() {
+        if text.sections[0].value != casting.name {
+            text.sections[0].value = casting.name.clone();
+        }
+    }
+}
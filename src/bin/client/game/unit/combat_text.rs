@@ -0,0 +1,106 @@
+use bevy::prelude::*;
+use tiled_game::combat::{DamageSchool, HitResult};
+
+// Above the name label, where the text starts to rise
+const TEXT_OFFSET: f32 = 24.;
+
+// Pixels the text rises over its lifetime
+const TEXT_RISE: f32 = 20.;
+
+const TEXT_SECONDS: f32 = 1.;
+
+// A unit took damage, or an attack on it didn't land
+#[derive(Event)]
+pub struct DamageTextEvent {
+    pub entity: Entity,
+    pub amount: i32,
+    pub school: DamageSchool,
+    pub result: HitResult,
+}
+
+// Rises and fades, child of the unit
+#[derive(Component)]
+struct CombatText(Timer);
+
+pub struct CombatTextPlugin;
+
+impl Plugin for CombatTextPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageTextEvent>()
+            .add_systems(Update, (spawn_combat_text, animate_combat_text));
+    }
+}
+
+fn school_color(school: DamageSchool) -> Color {
+    match school {
+        DamageSchool::Physical => Color::WHITE,
+        DamageSchool::Fire => Color::ORANGE,
+        DamageSchool::Frost => Color::CYAN,
+        DamageSchool::Nature => Color::LIME_GREEN,
+        DamageSchool::Holy => Color::GOLD,
+        DamageSchool::Shadow => Color::PURPLE,
+    }
+}
+
+fn spawn_combat_text(
+    mut commands: Commands,
+    mut damage_texts: EventReader<DamageTextEvent>,
+    asset_server: Res<AssetServer>,
+) {
+    for event in damage_texts.iter() {
+        let (text, font_size, color) = match event.result {
+            HitResult::Hit => (event.amount.to_string(), 14., school_color(event.school)),
+            HitResult::Crit => (
+                format!("{}!", event.amount),
+                20.,
+                school_color(event.school),
+            ),
+            HitResult::Miss => ("Miss".to_string(), 14., Color::GRAY),
+            HitResult::Dodge => ("Dodge".to_string(), 14., Color::GRAY),
+            HitResult::Absorb => ("Absorb".to_string(), 14., Color::GRAY),
+        };
+
+        let Some(mut unit) = commands.get_entity(event.entity) else {
+            continue;
+        };
+
+        unit.with_children(|unit| {
+            unit.spawn((
+                Text2dBundle {
+                    transform: Transform::from_xyz(0., TEXT_OFFSET, 0.2),
+                    text: Text::from_section(
+                        text,
+                        TextStyle {
+                            font: asset_server.load("OpenSans-Regular.ttf"),
+                            font_size,
+                            color,
+                        },
+                    )
+                    .with_alignment(TextAlignment::Center),
+                    ..default()
+                },
+                CombatText(Timer::from_seconds(TEXT_SECONDS, TimerMode::Once)),
+            ));
+        });
+    }
+}
+
+fn animate_combat_text(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut texts: Query<(Entity, &mut CombatText, &mut Transform, &mut Text)>,
+) {
+    for (entity, mut combat_text, mut transform, mut text) in texts.iter_mut() {
+        if combat_text.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        let progress = combat_text.0.percent();
+        transform.translation.y = TEXT_OFFSET + TEXT_RISE * progress;
+
+        for section in text.sections.iter_mut() {
+            section.style.color.set_a(1. - progress);
+        }
+    }
+}
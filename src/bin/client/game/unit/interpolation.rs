@@ -0,0 +1,252 @@
+use std::{
+    collections::VecDeque,
+    f32::consts::{PI, TAU},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    config::ClientConfig,
+    game::spritesheet::{deg_to_facing, AnimateDirection, AnimateState, MovementState},
+    network::ServerSideEntity,
+};
+
+// Snapshots beyond this are dropped, oldest first
+const MAX_SNAPSHOTS: usize = 32;
+
+// Entities keep moving for at most this long when snapshots stop arriving
+// and are then eased back to the last known position
+const MAX_EXTRAPOLATION_SECONDS: f64 = 0.1;
+
+// A longer gap between two snapshots means the entity stood still in between
+const MAX_SNAPSHOT_GAP_SECONDS: f64 = 0.25;
+
+// How quickly the estimated server clock follows new measurements
+const CLOCK_SMOOTHING: f64 = 0.1;
+
+// Offsets further off than this are taken as they are, e.g. after a server restart
+const MAX_CLOCK_DRIFT_SECONDS: f64 = 1.;
+
+// Slower entities are shown as standing still
+const MIN_MOVING_SPEED: f32 = 1.;
+
+// Estimate of the server clock, based on the timestamps of received snapshots
+#[derive(Resource, Default)]
+pub struct ServerClock {
+    // server time - local time
+    offset: Option<f64>,
+}
+
+impl ServerClock {
+    pub fn observe(&mut self, server_time: f64, local_time: f64) {
+        let offset = server_time - local_time;
+
+        self.offset = Some(match self.offset {
+            Some(current) if (offset - current).abs() < MAX_CLOCK_DRIFT_SECONDS => {
+                current + (offset - current) * CLOCK_SMOOTHING
+            }
+            _ => offset,
+        });
+    }
+
+    pub fn server_time(&self, local_time: f64) -> Option<f64> {
+        self.offset.map(|offset| local_time + offset)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    // server time the position was sent at
+    pub time: f64,
+    pub position: Vec2,
+    // around the z axis, in radians
+    pub rotation: f32,
+}
+
+// Turns the shorter way round from one angle to the other
+fn lerp_angle(from: f32, to: f32, t: f32) -> f32 {
+    let difference = (to - from + PI).rem_euclid(TAU) - PI;
+    from + difference * t
+}
+
+// Positions the server sent for an entity, ordered by time
+#[derive(Component)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<Snapshot>,
+    // gap between the two most recent snapshots of a moving entity
+    interval: f64,
+    // velocity between the two most recent snapshots, used to extrapolate
+    velocity: Vec2,
+}
+
+impl Default for SnapshotBuffer {
+    fn default() -> Self {
+        Self {
+            snapshots: VecDeque::new(),
+            interval: 1. / 60.,
+            velocity: Vec2::ZERO,
+        }
+    }
+}
+
+impl SnapshotBuffer {
+    pub fn push(&mut self, snapshot: Snapshot) {
+        let Some(last) = self.snapshots.back().copied() else {
+            self.snapshots.push_back(snapshot);
+            return;
+        };
+
+        // movements are sent unordered, late ones are sorted in
+        if snapshot.time < last.time {
+            let index = self
+                .snapshots
+                .iter()
+                .rposition(|other| other.time <= snapshot.time)
+                .map_or(0, |index| index + 1);
+            self.snapshots.insert(index, snapshot);
+            return;
+        }
+
+        let gap = snapshot.time - last.time;
+        if gap > MAX_SNAPSHOT_GAP_SECONDS {
+            // the entity stood at the last position until it started moving again,
+            // without this it would slowly drift over the whole gap
+            self.snapshots.push_back(Snapshot {
+                time: snapshot.time - self.interval,
+                ..last
+            });
+            self.velocity = (snapshot.position - last.position) / self.interval as f32;
+        } else if gap > 0. {
+            self.interval = gap;
+            self.velocity = (snapshot.position - last.position) / gap as f32;
+        }
+
+        self.snapshots.push_back(snapshot);
+
+        while self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    // Position, rotation and velocity of the entity at the given server time
+    fn sample(&mut self, time: f64) -> Option<(Vec2, f32, Vec2)> {
+        // keep the last snapshot before the time, it is the start of the interpolation
+        while self.snapshots.len() > 1 && self.snapshots[1].time <= time {
+            self.snapshots.pop_front();
+        }
+
+        let from = *self.snapshots.front()?;
+
+        // not there yet, the entity appeared less than the delay ago
+        if time <= from.time {
+            return Some((from.position, from.rotation, Vec2::ZERO));
+        }
+
+        if let Some(to) = self.snapshots.get(1) {
+            let duration = to.time - from.time;
+            let t = ((time - from.time) / duration) as f32;
+
+            return Some((
+                from.position.lerp(to.position, t),
+                lerp_angle(from.rotation, to.rotation, t),
+                (to.position - from.position) / duration as f32,
+            ));
+        }
+
+        // no newer snapshot, continue the last known movement for a short while
+        let elapsed = time - from.time;
+        if elapsed < MAX_EXTRAPOLATION_SECONDS {
+            return Some((
+                from.position + self.velocity * elapsed as f32,
+                from.rotation,
+                self.velocity,
+            ));
+        }
+
+        // the entity most likely stopped, ease back to where it was last seen
+        let remaining = (2. * MAX_EXTRAPOLATION_SECONDS - elapsed).max(0.);
+        Some((
+            from.position + self.velocity * remaining as f32,
+            from.rotation,
+            Vec2::ZERO,
+        ))
+    }
+}
+
+// A movement of a server side entity arrived
+#[derive(Event)]
+pub struct SnapshotEvent {
+    pub entity: Entity,
+    pub snapshot: Snapshot,
+}
+
+pub fn buffer_snapshots(
+    time: Res<Time>,
+    mut clock: ResMut<ServerClock>,
+    mut snapshot_events: EventReader<SnapshotEvent>,
+    mut buffers: Query<&mut SnapshotBuffer>,
+) {
+    for event in snapshot_events.iter() {
+        clock.observe(event.snapshot.time, time.elapsed_seconds_f64());
+
+        if let Ok(mut buffer) = buffers.get_mut(event.entity) {
+            buffer.push(event.snapshot);
+        }
+    }
+}
+
+// Shows remote entities a fixed delay behind the server,
+// so there is almost always a newer snapshot to move towards
+pub fn interpolate_remote_entities(
+    time: Res<Time>,
+    config: Res<ClientConfig>,
+    clock: Res<ServerClock>,
+    mut entities: Query<
+        (
+            &mut SnapshotBuffer,
+            &mut Transform,
+            &mut AnimateDirection,
+            &mut AnimateState,
+        ),
+        With<ServerSideEntity>,
+    >,
+) {
+    let Some(server_time) = clock.server_time(time.elapsed_seconds_f64()) else {
+        return;
+    };
+    let render_time = server_time - config.interpolation_delay().as_secs_f64();
+
+    for (mut buffer, mut transform, mut direction, mut state) in entities.iter_mut() {
+        let Some((position, rotation, velocity)) = buffer.sample(render_time) else {
+            continue;
+        };
+
+        // z is set from y by set_y_to_z_transform
+        if position != transform.translation.truncate() {
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+        }
+
+        let rotation = Quat::from_rotation_z(rotation);
+        if rotation != transform.rotation {
+            transform.rotation = rotation;
+        }
+
+        if velocity.length() < MIN_MOVING_SPEED {
+            if state.0 != MovementState::Idle {
+                state.0 = MovementState::Idle;
+            }
+            continue;
+        }
+
+        if state.0 != MovementState::Moving {
+            state.0 = MovementState::Moving;
+        }
+
+        let angle = velocity.y.atan2(velocity.x).to_degrees().rem_euclid(360.);
+        let facing = deg_to_facing(angle);
+        if direction.0 != facing {
+            direction.0 = facing;
+        }
+    }
+}
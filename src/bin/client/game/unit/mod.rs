@@ -1,8 +1,15 @@
 use bevy::{log, prelude::*};
-use bevy_renet::renet::{DefaultChannel, RenetClient};
+use bevy_renet::renet::RenetClient;
 use tiled_game::network::messages::client::ClientMessages;
 
-use crate::network::ServerSideEntity;
+use crate::network::{send_message, ServerSideEntity};
+
+use self::auras::AurasPlugin;
+use self::cast_bar::CastBarPlugin;
+use self::combat_text::CombatTextPlugin;
+use self::interpolation::{
+    buffer_snapshots, interpolate_remote_entities, ServerClock, SnapshotBuffer, SnapshotEvent,
+};
 
 use super::{
     components::{Highlighted, MousePointerTarget},
@@ -10,6 +17,11 @@ use super::{
     spritesheet::{deg_to_facing, AnimateDirection},
 };
 
+pub mod auras;
+pub mod cast_bar;
+pub mod combat_text;
+pub mod interpolation;
+
 pub struct UnitPlugin;
 
 impl Plugin for UnitPlugin {
@@ -21,7 +33,16 @@ impl Plugin for UnitPlugin {
             .add_system(handle_mouse_leftclick)
             .add_system(target_name)
             .add_system(untarget_name)
-            .add_system(set_unit_previous_pos);
+            .add_system(set_unit_previous_pos)
+            .init_resource::<ServerClock>()
+            .add_event::<SnapshotEvent>()
+            .add_plugins(CastBarPlugin)
+            .add_plugins(AurasPlugin)
+            .add_plugins(CombatTextPlugin)
+            .add_systems(
+                Update,
+                (buffer_snapshots, interpolate_remote_entities).chain(),
+            );
     }
 }
 
@@ -162,9 +183,7 @@ pub fn handle_mouse_rightclick(
         let entity = highlighted_entities.iter().next().unwrap();
         let msg = ClientMessages::Interact { entity: entity.0 };
 
-        let msg = bincode::serialize(&msg).unwrap();
-
-        client.send_message(DefaultChannel::ReliableUnordered, msg);
+        send_message(&mut client, &msg);
     }
 }
 
@@ -192,11 +211,15 @@ pub fn handle_mouse_leftclick(
     cmd.entity(client_side_entity).insert(PlayerTarget);
 }
 
+// Remote entities get their direction from the interpolated velocity instead
 #[derive(Component)]
 pub struct PreviousPos(pub Vec3);
 
 pub fn set_unit_previous_pos(
-    mut units: Query<(&mut AnimateDirection, Ref<Transform>, &mut PreviousPos), Changed<Transform>>,
+    mut units: Query<
+        (&mut AnimateDirection, Ref<Transform>, &mut PreviousPos),
+        (Changed<Transform>, Without<SnapshotBuffer>),
+    >,
 ) {
     for (mut direction, transform, mut previous_pos) in units.iter_mut() {
         // measure angle between previous position and current position
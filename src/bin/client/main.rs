@@ -1,3 +1,4 @@
+mod config;
 mod game;
 mod helpers;
 mod network;
@@ -8,12 +9,15 @@ use bevy::{asset::ChangeWatcher, prelude::*};
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_rapier2d::prelude::*;
 
+use config::ClientConfig;
 use network::NetworkPlugin;
 use tiled_game::components::Threat;
 
 fn main() {
+    let config = ClientConfig::load();
     let mut app = App::new();
     // .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_millis(120)))
+    app.insert_resource(config);
     app.add_plugins(
         DefaultPlugins
             .set(ImagePlugin::default_nearest())
@@ -0,0 +1,98 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use bevy::{
+    prelude::*,
+    tasks::{futures_lite::future, AsyncComputeTaskPool, Task},
+};
+use bevy_renet::renet::transport::ConnectToken;
+
+use crate::config::ClientConfig;
+
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Token request running in the background, the client keeps drawing while it waits
+#[derive(Resource)]
+pub struct PendingConnectToken(Task<anyhow::Result<ConnectToken>>);
+
+impl PendingConnectToken {
+    pub fn request(auth_addr: SocketAddr, config: &ClientConfig) -> Self {
+        let account_id = config.account_id;
+        let character_name = config.name.clone();
+        let password = config.password.clone();
+
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            fetch_connect_token(auth_addr, account_id, &character_name, &password)
+        });
+
+        Self(task)
+    }
+
+    // The token once the issuer answered
+    pub fn poll(&mut self) -> Option<anyhow::Result<ConnectToken>> {
+        future::block_on(future::poll_once(&mut self.0))
+    }
+}
+
+// Asks the token issuer for a signed connect token
+// A fresh token is needed for every connection attempt
+fn fetch_connect_token(
+    auth_addr: SocketAddr,
+    account_id: u64,
+    character_name: &str,
+    password: &str,
+) -> anyhow::Result<ConnectToken> {
+    let mut stream = TcpStream::connect_timeout(&auth_addr, AUTH_TIMEOUT)?;
+    stream.set_read_timeout(Some(AUTH_TIMEOUT))?;
+
+    let body = format!(
+        "account={}&name={}&password={}",
+        account_id,
+        percent_encode(character_name),
+        percent_encode(password)
+    );
+
+    write!(
+        stream,
+        "POST /token HTTP/1.0\r\nHost: {}\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+        auth_addr,
+        body.len(),
+        body
+    )?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("Invalid response from token issuer"))?;
+
+    let (head, body) = response.split_at(header_end + 4);
+    let status_line = String::from_utf8_lossy(head);
+
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        bail!(
+            "Token issuer refused: {}",
+            String::from_utf8_lossy(body).trim()
+        );
+    }
+
+    ConnectToken::read(&mut &body[..]).map_err(|e| anyhow!("Invalid connect token: {:?}", e))
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
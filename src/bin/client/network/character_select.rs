@@ -0,0 +1,298 @@
+use bevy::{log, prelude::*};
+use bevy_renet::renet::RenetClient;
+use tiled_game::network::{
+    messages::{
+        client::ClientMessages,
+        server::{CharacterErrorMessage, CharacterSummary},
+    },
+    user_data::{is_valid_character_name, MAX_CHARACTER_NAME_LENGTH},
+};
+
+use crate::config::ClientConfig;
+
+use super::{connection::ConnectionState, send_message};
+
+// What the client shows while connected
+#[derive(States, Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum GameState {
+    #[default]
+    CharacterSelect,
+    // The server sent the player info, the character is in the world
+    InGame,
+}
+
+// Everything the character select screen knows
+#[derive(Resource, Default)]
+pub struct CharacterSelection {
+    pub characters: Vec<CharacterSummary>,
+    pub classes: Vec<String>,
+
+    // Index into characters
+    highlighted: usize,
+    // Index into classes for new characters
+    class: usize,
+    // Name typed for a new character
+    new_name: String,
+    // Set after the first press of delete
+    confirm_delete: bool,
+
+    pub error: Option<String>,
+}
+
+impl CharacterSelection {
+    pub fn set_list(
+        &mut self,
+        characters: Vec<CharacterSummary>,
+        classes: Vec<String>,
+        preferred: &str,
+    ) {
+        // keep the highlight on the same character if it still exists
+        let highlighted_name = self
+            .characters
+            .get(self.highlighted)
+            .map_or(preferred.to_string(), |character| character.name.clone());
+
+        self.highlighted = characters
+            .iter()
+            .position(|character| character.name == highlighted_name)
+            .unwrap_or(0);
+        self.class = self.class.min(classes.len().saturating_sub(1));
+        self.characters = characters;
+        self.classes = classes;
+        self.new_name.clear();
+        self.confirm_delete = false;
+        self.error = None;
+    }
+
+    pub fn set_error(&mut self, error: CharacterErrorMessage) {
+        let message = match error {
+            CharacterErrorMessage::InvalidName => "That name is not allowed",
+            CharacterErrorMessage::NameTaken => "That name is already taken",
+            CharacterErrorMessage::UnknownClass => "Unknown class",
+            CharacterErrorMessage::TooManyCharacters => "You can't create more characters",
+            CharacterErrorMessage::NotFound => "Character not found",
+            CharacterErrorMessage::AlreadyInGame => "That character is already playing",
+            CharacterErrorMessage::StorageFailure => "Server error, try again later",
+        };
+
+        self.error = Some(message.to_string());
+    }
+}
+
+// Root node of the character select screen
+#[derive(Component)]
+struct CharacterSelectScreen;
+
+#[derive(Component)]
+struct CharacterSelectText;
+
+pub struct CharacterSelectPlugin;
+
+impl Plugin for CharacterSelectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<GameState>()
+            .init_resource::<CharacterSelection>()
+            .add_systems(
+                OnEnter(ConnectionState::Connected),
+                spawn_character_select_screen,
+            )
+            .add_systems(
+                OnExit(ConnectionState::Connected),
+                (despawn_character_select_screen, reset_character_select),
+            )
+            .add_systems(OnEnter(GameState::InGame), despawn_character_select_screen)
+            .add_systems(
+                Update,
+                (character_select_input, update_character_select_screen)
+                    .chain()
+                    .run_if(in_state(ConnectionState::Connected))
+                    .run_if(in_state(GameState::CharacterSelect)),
+            );
+    }
+}
+
+fn reset_character_select(
+    mut selection: ResMut<CharacterSelection>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    *selection = CharacterSelection::default();
+    next_state.set(GameState::CharacterSelect);
+}
+
+fn spawn_character_select_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("OpenSans-Regular.ttf");
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                background_color: Color::rgb(0.1, 0.1, 0.1).into(),
+                ..default()
+            },
+            CharacterSelectScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font,
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                    },
+                ),
+                CharacterSelectText,
+            ));
+        });
+}
+
+fn despawn_character_select_screen(
+    mut commands: Commands,
+    screens: Query<Entity, With<CharacterSelectScreen>>,
+) {
+    for entity in screens.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Up/Down pick a character, Enter plays it or creates the typed one,
+// Tab cycles the class of the new character, Delete (twice) removes one
+fn character_select_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut received_characters: EventReader<ReceivedCharacter>,
+    mut client: ResMut<RenetClient>,
+    mut selection: ResMut<CharacterSelection>,
+) {
+    for event in received_characters.iter() {
+        if (event.char.is_alphanumeric() || event.char == ' ')
+            && selection.new_name.chars().count() < MAX_CHARACTER_NAME_LENGTH
+        {
+            selection.new_name.push(event.char);
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        selection.new_name.pop();
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Up) {
+        selection.highlighted = selection.highlighted.saturating_sub(1);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Down) && !selection.characters.is_empty() {
+        selection.highlighted = (selection.highlighted + 1).min(selection.characters.len() - 1);
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Tab) && !selection.classes.is_empty() {
+        selection.class = (selection.class + 1) % selection.classes.len();
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Delete) {
+        let Some(character) = selection.characters.get(selection.highlighted) else {
+            return;
+        };
+
+        if !selection.confirm_delete {
+            selection.confirm_delete = true;
+            return;
+        }
+
+        let name = character.name.clone();
+        log::info!("Deleting character {:?}", name);
+        send_message(&mut client, &ClientMessages::DeleteCharacter { name });
+        selection.confirm_delete = false;
+        return;
+    }
+
+    if keyboard_input.get_just_pressed().next().is_some() {
+        selection.confirm_delete = false;
+    }
+
+    if !keyboard_input.just_pressed(KeyCode::Return) {
+        return;
+    }
+
+    if selection.new_name.is_empty() {
+        let Some(character) = selection.characters.get(selection.highlighted) else {
+            return;
+        };
+
+        let name = character.name.clone();
+        log::info!("Selecting character {:?}", name);
+        send_message(&mut client, &ClientMessages::SelectCharacter { name });
+        return;
+    }
+
+    let name = selection.new_name.clone();
+    if !is_valid_character_name(&name) {
+        selection.set_error(CharacterErrorMessage::InvalidName);
+        return;
+    }
+
+    let Some(class) = selection.classes.get(selection.class).cloned() else {
+        return;
+    };
+
+    log::info!("Creating character {:?} ({})", name, class);
+    send_message(
+        &mut client,
+        &ClientMessages::CreateCharacter { name, class },
+    );
+}
+
+fn update_character_select_screen(
+    config: Res<ClientConfig>,
+    selection: Res<CharacterSelection>,
+    mut texts: Query<&mut Text, With<CharacterSelectText>>,
+) {
+    if !selection.is_changed() {
+        return;
+    }
+
+    let mut lines = vec![format!("Characters of account {}", config.account_id)];
+
+    if selection.characters.is_empty() {
+        lines.push("  (none yet)".to_string());
+    }
+
+    for (i, character) in selection.characters.iter().enumerate() {
+        let marker = if i == selection.highlighted { ">" } else { " " };
+        lines.push(format!(
+            "{} {} (level {}) - {}",
+            marker, character.name, character.level, character.map
+        ));
+    }
+
+    lines.push(String::new());
+    lines.push(format!("New character: {}_", selection.new_name));
+    lines.push(format!(
+        "Class: {}",
+        selection
+            .classes
+            .get(selection.class)
+            .map_or("", String::as_str)
+    ));
+    lines.push(String::new());
+
+    if selection.confirm_delete {
+        lines.push("Press Delete again to delete the character".to_string());
+    } else {
+        lines
+            .push("Enter: play or create, Tab: change class, Delete: delete character".to_string());
+    }
+
+    if let Some(error) = &selection.error {
+        lines.push(error.clone());
+    }
+
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
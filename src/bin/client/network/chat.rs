@@ -0,0 +1,284 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_renet::renet::RenetClient;
+use tiled_game::network::{
+    chat::{ChatChannel, MAX_CHAT_LENGTH},
+    messages::client::ClientMessages,
+};
+
+use crate::game::player::Player;
+
+use super::{character_select::GameState, send_message};
+
+// Older lines scroll out of the chat box
+const MAX_CHAT_LINES: usize = 8;
+
+// Enter opens the input, Enter again sends it, Escape throws it away
+// While it is open the keyboard belongs to the chat and not to the player
+#[derive(Resource, Default)]
+pub struct ChatBox {
+    pub focused: bool,
+    input: String,
+    lines: VecDeque<(String, Color)>,
+}
+
+impl ChatBox {
+    fn push(&mut self, line: String, color: Color) {
+        self.lines.push_back((line, color));
+        while self.lines.len() > MAX_CHAT_LINES {
+            self.lines.pop_front();
+        }
+    }
+}
+
+// A chat message from the server
+#[derive(Event)]
+pub struct ChatMessageEvent {
+    pub channel: ChatChannel,
+    pub sender: Option<String>,
+    pub message: String,
+}
+
+impl ChatMessageEvent {
+    // A line only this client sees, like the reason an action failed
+    pub fn system(message: impl Into<String>) -> Self {
+        Self {
+            channel: ChatChannel::System,
+            sender: None,
+            message: message.into(),
+        }
+    }
+}
+
+// Root node of the chat box
+#[derive(Component)]
+struct ChatBoxNode;
+
+#[derive(Component)]
+struct ChatLogText;
+
+#[derive(Component)]
+struct ChatInputText;
+
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChatBox>()
+            .add_event::<ChatMessageEvent>()
+            .add_systems(OnEnter(GameState::InGame), spawn_chat_box)
+            .add_systems(OnExit(GameState::InGame), despawn_chat_box)
+            .add_systems(
+                Update,
+                (receive_chat_messages, chat_input, update_chat_box)
+                    .chain()
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+fn spawn_chat_box(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("OpenSans-Regular.ttf");
+    let text_style = TextStyle {
+        font,
+        font_size: 16.0,
+        color: Color::WHITE,
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(10.0),
+                    bottom: Val::Px(10.0),
+                    width: Val::Px(400.0),
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(5.0)),
+                    row_gap: Val::Px(5.0),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.4).into(),
+                ..default()
+            },
+            ChatBoxNode,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_sections(Vec::<TextSection>::new()),
+                ChatLogText,
+            ));
+            parent.spawn((TextBundle::from_section("", text_style), ChatInputText));
+        });
+}
+
+fn despawn_chat_box(
+    mut commands: Commands,
+    mut chat: ResMut<ChatBox>,
+    nodes: Query<Entity, With<ChatBoxNode>>,
+) {
+    for entity in nodes.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    *chat = ChatBox::default();
+}
+
+fn receive_chat_messages(
+    mut chat: ResMut<ChatBox>,
+    mut messages: EventReader<ChatMessageEvent>,
+    player: Query<&Name, With<Player>>,
+) {
+    let own_name = player.get_single().ok().map(|name| name.as_str());
+
+    for message in messages.iter() {
+        let sender = message.sender.clone().unwrap_or_default();
+
+        let (line, color) = match &message.channel {
+            ChatChannel::Say => (format!("{}: {}", sender, message.message), Color::WHITE),
+            ChatChannel::Yell => (
+                format!("{} yells: {}", sender, message.message),
+                Color::ORANGE_RED,
+            ),
+            // we get our own whispers back from the server
+            ChatChannel::Whisper { to } if own_name != Some(to.as_str()) => {
+                (format!("To {}: {}", to, message.message), Color::PINK)
+            }
+            ChatChannel::Whisper { .. } => (
+                format!("{} whispers: {}", sender, message.message),
+                Color::PINK,
+            ),
+            ChatChannel::Party => (
+                format!("[Party] {}: {}", sender, message.message),
+                Color::CYAN,
+            ),
+            ChatChannel::Emote => (format!("* {} {}", sender, message.message), Color::ORANGE),
+            ChatChannel::System => (message.message.clone(), Color::YELLOW),
+        };
+
+        chat.push(line, color);
+    }
+}
+
+// "/s", "/y", "/p" and "/w <name>" pick the channel, everything else is said
+// Other slash commands are left to the server
+fn parse_chat_input(input: &str) -> Result<ClientMessages, String> {
+    let say = |channel, message: &str| ClientMessages::Chat {
+        channel,
+        message: message.to_string(),
+    };
+
+    let Some(command) = input.strip_prefix('/') else {
+        return Ok(say(ChatChannel::Say, input));
+    };
+
+    let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+    let channel = match name {
+        "s" | "say" => ChatChannel::Say,
+        "y" | "yell" => ChatChannel::Yell,
+        "p" | "party" => ChatChannel::Party,
+        "w" | "whisper" => {
+            let Some((to, message)) = rest.split_once(' ') else {
+                return Err("Usage: /w <name> <message>".to_string());
+            };
+            return Ok(say(ChatChannel::Whisper { to: to.to_string() }, message));
+        }
+        _ => {
+            return Ok(ClientMessages::Command {
+                text: command.to_string(),
+            })
+        }
+    };
+
+    Ok(say(channel, rest))
+}
+
+fn chat_input(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut received_characters: EventReader<ReceivedCharacter>,
+    mut client: ResMut<RenetClient>,
+    mut chat: ResMut<ChatBox>,
+) {
+    // characters typed while the chat is closed were meant for the game
+    let typed: Vec<char> = received_characters.iter().map(|event| event.char).collect();
+
+    if !chat.focused {
+        if keyboard_input.just_pressed(KeyCode::Return) {
+            chat.focused = true;
+        }
+        return;
+    }
+
+    for c in typed {
+        if !c.is_control() && chat.input.chars().count() < MAX_CHAT_LENGTH {
+            chat.input.push(c);
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        chat.input.pop();
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        chat.input.clear();
+        chat.focused = false;
+        return;
+    }
+
+    if !keyboard_input.just_pressed(KeyCode::Return) {
+        return;
+    }
+
+    let input = std::mem::take(&mut chat.input);
+    chat.focused = false;
+
+    let input = input.trim();
+    if input.is_empty() {
+        return;
+    }
+
+    match parse_chat_input(input) {
+        Ok(ClientMessages::Chat { message, .. }) if message.trim().is_empty() => {}
+        Ok(message) => send_message(&mut client, &message),
+        Err(error) => chat.push(error, Color::YELLOW),
+    }
+}
+
+fn update_chat_box(
+    chat: Res<ChatBox>,
+    asset_server: Res<AssetServer>,
+    mut log: Query<&mut Text, (With<ChatLogText>, Without<ChatInputText>)>,
+    mut input: Query<&mut Text, (With<ChatInputText>, Without<ChatLogText>)>,
+) {
+    if !chat.is_changed() {
+        return;
+    }
+
+    let font = asset_server.load("OpenSans-Regular.ttf");
+
+    for mut text in log.iter_mut() {
+        text.sections = chat
+            .lines
+            .iter()
+            .map(|(line, color)| {
+                TextSection::new(
+                    format!("{}\n", line),
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 16.0,
+                        color: *color,
+                    },
+                )
+            })
+            .collect();
+    }
+
+    for mut text in input.iter_mut() {
+        text.sections[0].value = if chat.focused {
+            format!("> {}_", chat.input)
+        } else {
+            "Press Enter to chat".to_string()
+        };
+    }
+}
@@ -0,0 +1,126 @@
+use bevy::prelude::*;
+
+use crate::config::ClientConfig;
+
+use super::connection::{ConnectionState, ConnectionStatus};
+
+// Root node of the connect screen
+#[derive(Component)]
+struct ConnectScreen;
+
+// Text that shows what the connection is currently doing
+#[derive(Component)]
+struct ConnectScreenStatus;
+
+pub struct ConnectScreenPlugin;
+
+impl Plugin for ConnectScreenPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_connect_screen)
+            .add_systems(OnExit(ConnectionState::Connected), spawn_connect_screen)
+            .add_systems(OnEnter(ConnectionState::Connected), despawn_connect_screen)
+            .add_systems(
+                Update,
+                (
+                    update_connect_screen_status,
+                    connect_on_enter.run_if(in_state(ConnectionState::Disconnected)),
+                ),
+            );
+    }
+}
+
+fn spawn_connect_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<ClientConfig>,
+) {
+    let font = asset_server.load("OpenSans-Regular.ttf");
+    let text_style = TextStyle {
+        font,
+        font_size: 20.0,
+        color: Color::WHITE,
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(10.0),
+                    ..default()
+                },
+                background_color: Color::rgb(0.1, 0.1, 0.1).into(),
+                ..default()
+            },
+            ConnectScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "TiledMMO",
+                TextStyle {
+                    font_size: 40.0,
+                    ..text_style.clone()
+                },
+            ));
+            parent.spawn(TextBundle::from_section(
+                format!("Server: {}", config.server_addr),
+                text_style.clone(),
+            ));
+            parent.spawn(TextBundle::from_section(
+                format!("Account: {}", config.account_id),
+                text_style.clone(),
+            ));
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                ConnectScreenStatus,
+            ));
+        });
+}
+
+fn despawn_connect_screen(mut commands: Commands, screens: Query<Entity, With<ConnectScreen>>) {
+    for entity in screens.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn update_connect_screen_status(
+    state: Res<State<ConnectionState>>,
+    status: Res<ConnectionStatus>,
+    config: Res<ClientConfig>,
+    mut texts: Query<&mut Text, With<ConnectScreenStatus>>,
+) {
+    let message = match state.get() {
+        ConnectionState::Connecting => "Connecting...".to_string(),
+        ConnectionState::Connected | ConnectionState::Lost => return,
+        ConnectionState::Retrying => format!(
+            "{}\nRetrying in {}s (attempt {}/{})",
+            status.last_error.clone().unwrap_or_default(),
+            status.retry_timer.remaining().as_secs() + 1,
+            status.attempts,
+            config.reconnect_attempts
+        ),
+        ConnectionState::Disconnected => format!(
+            "{}\nPress Enter to connect",
+            status.last_error.clone().unwrap_or_default()
+        ),
+    };
+
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = message.clone();
+    }
+}
+
+fn connect_on_enter(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut status: ResMut<ConnectionStatus>,
+    mut next_state: ResMut<NextState<ConnectionState>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Return) {
+        status.attempts = 0;
+        next_state.set(ConnectionState::Connecting);
+    }
+}
@@ -0,0 +1,254 @@
+use std::time::Duration;
+
+use bevy::{log, prelude::*};
+use bevy_renet::renet::{
+    transport::{NetcodeClientTransport, NetcodeTransportError},
+    RenetClient,
+};
+use tiled_game::network::{messages::client::ClientMessages, version::PROTOCOL_VERSION};
+
+use crate::{
+    config::ClientConfig,
+    game::{map::CurrentMap, player::movement::PredictionState, unit::interpolation::ServerClock},
+};
+
+use super::{
+    auth::PendingConnectToken, new_renet_client, send_message, snapshot::ReceivedSnapshots,
+    ClientState, ServerSideEntity,
+};
+
+// Longest wait between two reconnect attempts
+const MAX_BACKOFF_SECS: u64 = 30;
+
+#[derive(States, Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum ConnectionState {
+    // Shows the connect screen and waits for the user
+    Disconnected,
+    // Waiting for the server to accept the connection
+    #[default]
+    Connecting,
+    Connected,
+    // The connection was lost, the game world gets cleaned up
+    Lost,
+    // Waiting for the backoff timer before connecting again
+    Retrying,
+}
+
+#[derive(Resource, Default)]
+pub struct ConnectionStatus {
+    pub attempts: u32,
+    pub retry_timer: Timer,
+
+    // Shown on the connect screen
+    pub last_error: Option<String>,
+
+    // Kicked or banned players should not reconnect on their own
+    pub retry_allowed: bool,
+}
+
+impl ConnectionStatus {
+    pub fn lost(&mut self, error: String, retry_allowed: bool) {
+        self.last_error = Some(error);
+        self.retry_allowed = retry_allowed;
+    }
+}
+
+pub struct ConnectionPlugin;
+
+impl Plugin for ConnectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<ConnectionState>()
+            .init_resource::<ConnectionStatus>()
+            .add_systems(OnEnter(ConnectionState::Connecting), connect)
+            .add_systems(OnEnter(ConnectionState::Connected), connection_established)
+            .add_systems(OnEnter(ConnectionState::Lost), cleanup_lost_connection)
+            .add_systems(
+                Update,
+                (
+                    wait_for_connect_token.run_if(in_state(ConnectionState::Connecting)),
+                    wait_for_connection.run_if(in_state(ConnectionState::Connecting)),
+                    check_connection.run_if(in_state(ConnectionState::Connected)),
+                    retry_connection.run_if(in_state(ConnectionState::Retrying)),
+                    handle_transport_errors.run_if(
+                        in_state(ConnectionState::Connecting)
+                            .or_else(in_state(ConnectionState::Connected)),
+                    ),
+                ),
+            );
+    }
+}
+
+fn connect(
+    mut commands: Commands,
+    config: Res<ClientConfig>,
+    mut status: ResMut<ConnectionStatus>,
+    mut next_state: ResMut<NextState<ConnectionState>>,
+) {
+    log::info!(
+        "Connecting to {} as {:?} (attempt {})",
+        config.server_addr,
+        config.name,
+        status.attempts + 1
+    );
+
+    // secure servers need a token first, the transport is created once it arrived
+    if let Some(auth_addr) = config.auth_addr {
+        commands.insert_resource(PendingConnectToken::request(auth_addr, &config));
+        return;
+    }
+
+    match new_renet_client(&config, None) {
+        Ok((client, transport)) => {
+            commands.insert_resource(client);
+            commands.insert_resource(transport);
+        }
+        Err(e) => {
+            log::error!("Could not connect: {}", e);
+            status.lost(e.to_string(), true);
+            next_state.set(ConnectionState::Lost);
+        }
+    }
+}
+
+fn wait_for_connect_token(
+    mut commands: Commands,
+    config: Res<ClientConfig>,
+    pending: Option<ResMut<PendingConnectToken>>,
+    mut status: ResMut<ConnectionStatus>,
+    mut next_state: ResMut<NextState<ConnectionState>>,
+) {
+    let Some(mut pending) = pending else {
+        return;
+    };
+
+    let Some(connect_token) = pending.poll() else {
+        return;
+    };
+
+    commands.remove_resource::<PendingConnectToken>();
+
+    match connect_token.and_then(|token| new_renet_client(&config, Some(token))) {
+        Ok((client, transport)) => {
+            commands.insert_resource(client);
+            commands.insert_resource(transport);
+        }
+        Err(e) => {
+            log::error!("Could not connect: {}", e);
+            status.lost(e.to_string(), true);
+            next_state.set(ConnectionState::Lost);
+        }
+    }
+}
+
+fn wait_for_connection(
+    transport: Option<Res<NetcodeClientTransport>>,
+    mut status: ResMut<ConnectionStatus>,
+    mut next_state: ResMut<NextState<ConnectionState>>,
+) {
+    let Some(transport) = transport else {
+        return;
+    };
+
+    if transport.is_connected() {
+        next_state.set(ConnectionState::Connected);
+        return;
+    }
+
+    if transport.is_disconnected() {
+        let reason = transport
+            .disconnect_reason()
+            .map(|reason| format!("Could not connect: {:?}", reason))
+            .unwrap_or_else(|| "Could not connect".to_string());
+
+        status.lost(reason, true);
+        next_state.set(ConnectionState::Lost);
+    }
+}
+
+// The server ignores everything until it knows we speak the same protocol
+fn connection_established(mut status: ResMut<ConnectionStatus>, mut client: ResMut<RenetClient>) {
+    log::info!("Connected to server");
+    status.attempts = 0;
+    status.last_error = None;
+
+    send_message(
+        &mut client,
+        &ClientMessages::Hello {
+            version: PROTOCOL_VERSION,
+        },
+    );
+}
+
+fn check_connection(
+    transport: Option<Res<NetcodeClientTransport>>,
+    mut status: ResMut<ConnectionStatus>,
+    mut next_state: ResMut<NextState<ConnectionState>>,
+) {
+    let disconnected = transport.map_or(true, |transport| transport.is_disconnected());
+
+    if disconnected {
+        status.lost("Connection to the server was lost".to_string(), true);
+        next_state.set(ConnectionState::Lost);
+    }
+}
+
+fn handle_transport_errors(
+    mut renet_error: EventReader<NetcodeTransportError>,
+    mut status: ResMut<ConnectionStatus>,
+    mut next_state: ResMut<NextState<ConnectionState>>,
+) {
+    for e in renet_error.iter() {
+        log::error!("Connection error: {}", e);
+        status.lost(e.to_string(), true);
+        next_state.set(ConnectionState::Lost);
+    }
+}
+
+// Removes everything the server told us about
+// and decides whether to try again
+fn cleanup_lost_connection(
+    mut commands: Commands,
+    config: Res<ClientConfig>,
+    mut client: ResMut<RenetClient>,
+    mut client_state: ResMut<ClientState>,
+    mut status: ResMut<ConnectionStatus>,
+    mut next_state: ResMut<NextState<ConnectionState>>,
+    server_entities: Query<Entity, With<ServerSideEntity>>,
+) {
+    client.disconnect();
+    commands.remove_resource::<NetcodeClientTransport>();
+    commands.remove_resource::<PendingConnectToken>();
+
+    for entity in server_entities.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    // unloads all map entities
+    commands.insert_resource(CurrentMap::default());
+    commands.insert_resource(PredictionState::default());
+    commands.insert_resource(ServerClock::default());
+    commands.insert_resource(ReceivedSnapshots::default());
+    *client_state = ClientState::default();
+
+    if !status.retry_allowed || status.attempts >= config.reconnect_attempts {
+        next_state.set(ConnectionState::Disconnected);
+        return;
+    }
+
+    status.attempts += 1;
+    let backoff = Duration::from_secs((1 << status.attempts.min(5)).min(MAX_BACKOFF_SECS));
+    log::info!("Reconnecting in {:?}", backoff);
+
+    status.retry_timer = Timer::new(backoff, TimerMode::Once);
+    next_state.set(ConnectionState::Retrying);
+}
+
+fn retry_connection(
+    time: Res<Time>,
+    mut status: ResMut<ConnectionStatus>,
+    mut next_state: ResMut<NextState<ConnectionState>>,
+) {
+    if status.retry_timer.tick(time.delta()).finished() {
+        next_state.set(ConnectionState::Connecting);
+    }
+}
@@ -1,81 +1,134 @@
 use std::{collections::HashMap, net::UdpSocket, time::SystemTime};
 
-use bevy::{app::AppExit, core::Name, log, prelude::*};
+use anyhow::anyhow;
+use bevy::{app::AppExit, core::Name, ecs::system::SystemParam, log, prelude::*};
 use bevy_rapier2d::prelude::*;
 use bevy_renet::{
     renet::{
-        transport::{ClientAuthentication, NetcodeClientTransport, NetcodeTransportError},
-        ConnectionConfig, DefaultChannel, RenetClient,
+        transport::{ClientAuthentication, ConnectToken, NetcodeClientTransport},
+        RenetClient,
     },
-    transport::{client_connected, NetcodeClientPlugin},
+    transport::NetcodeClientPlugin,
     RenetClientPlugin,
 };
+use tiled_game::collision::PLAYER_HALF_SIZE;
 use tiled_game::network::{
+    channels::{connection_config, Channel},
     messages::{
         client::ClientMessages,
-        server::{ServerMessages, Vitals},
+        decode_message, encode_message,
+        server::{DisconnectionReason, NetThreatMap, PlayerErrorMessage, ServerMessages, Vitals},
     },
+    net_id::NetId,
+    user_data::ConnectionUserData,
     PROTOCOL_ID,
 };
 
 use tiled_game::components::*;
 
 use crate::{
+    config::ClientConfig,
     game::{
+        abilities::ActionBar,
         components::PlayerEntity,
         map::MapChangeEvent,
-        player::Player,
+        player::{
+            movement::{predict_player_movement, MoveAckEvent},
+            Player,
+        },
         spritesheet::{
             AnimateDirection, AnimateState, AnimationIndices, AnimationTimer, Facing, MovementState,
         },
-        unit::PreviousPos,
+        unit::{
+            auras::{apply_aura_updates, AuraUpdateEvent, Auras, ClientAura},
+            cast_bar::Casting,
+            combat_text::DamageTextEvent,
+            interpolation::{Snapshot, SnapshotBuffer, SnapshotEvent},
+            PreviousPos,
+        },
     },
     helpers::camera::CameraTarget,
 };
 
-use self::sync::*;
+use self::{
+    character_select::{CharacterSelectPlugin, CharacterSelection, GameState},
+    chat::{ChatMessageEvent, ChatPlugin},
+    connect_screen::ConnectScreenPlugin,
+    connection::{ConnectionPlugin, ConnectionState, ConnectionStatus},
+    snapshot::ReceivedSnapshots,
+    sync::*,
+};
 
+mod auth;
+pub mod character_select;
+pub mod chat;
+mod connect_screen;
+pub mod connection;
+pub mod snapshot;
 mod sync;
 
-fn new_renet_client() -> (RenetClient, NetcodeClientTransport) {
-    let client = RenetClient::new(ConnectionConfig::default());
-
-    let server_addr = "127.0.0.1:3387".parse().expect("Invalid server address");
-    let socket = UdpSocket::bind("0.0.0.0:0").expect("Failed to bind socket");
-    let current_time = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap();
-    let client_id = current_time.as_millis() as u64;
-    let authentication = ClientAuthentication::Unsecure {
-        client_id,
-        protocol_id: PROTOCOL_ID,
-        server_addr,
-        user_data: None,
+// Without a connect token the client connects in unsecure mode
+fn new_renet_client(
+    config: &ClientConfig,
+    connect_token: Option<ConnectToken>,
+) -> anyhow::Result<(RenetClient, NetcodeClientTransport)> {
+    let client = RenetClient::new(connection_config());
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+
+    let authentication = match connect_token {
+        Some(connect_token) => ClientAuthentication::Secure { connect_token },
+        None => {
+            let client_id = current_time.as_millis() as u64;
+            let user_data = ConnectionUserData::new(config.account_id, config.name.clone())
+                .to_bytes()
+                .ok_or_else(|| anyhow!("Character name is too long"))?;
+
+            ClientAuthentication::Unsecure {
+                client_id,
+                protocol_id: PROTOCOL_ID,
+                server_addr: config.server_addr,
+                user_data: Some(user_data),
+            }
+        }
     };
 
-    let transport = NetcodeClientTransport::new(current_time, authentication, socket).unwrap();
+    let transport = NetcodeClientTransport::new(current_time, authentication, socket)?;
 
-    (client, transport)
+    Ok((client, transport))
 }
 
-// If any error is found we just panic
-fn panic_on_error_system(mut renet_error: EventReader<NetcodeTransportError>) {
-    for e in renet_error.iter() {
-        panic!("{}", e);
-    }
+// Sends the message on the channel its type belongs to
+pub fn send_message(client: &mut RenetClient, message: &ClientMessages) {
+    let channel = message.channel();
+    let message = encode_message(message).unwrap();
+    client.send_message(channel, message);
 }
 
 // Every entity with this component
 // can be controlled by the server
 #[derive(Debug, Component)]
-pub struct ServerSideEntity(pub Entity);
+pub struct ServerSideEntity(pub NetId);
 
 #[derive(Resource, Default)]
 struct ClientState {
-    player_entity: Option<Entity>,
+    player_entity: Option<NetId>,
 
     server_client_entity_mapping:
-        HashMap<Entity /* Server side */, Entity /* client side */>,
+        HashMap<NetId /* Server side */, Entity /* client side */>,
+}
+
+impl ClientState {
+    // Threat towards entities we don't know about is left out
+    fn threat_map(&self, threat: NetThreatMap) -> ThreatMap {
+        threat
+            .into_iter()
+            .filter_map(|(net_id, value)| {
+                Some((*self.server_client_entity_mapping.get(&net_id)?, value))
+            })
+            .collect()
+    }
 }
 
 pub struct NetworkPlugin;
@@ -85,47 +138,83 @@ struct Connected;
 
 impl Plugin for NetworkPlugin {
     fn build(&self, app: &mut App) {
-        let (client, transport) = new_renet_client();
-
-        app.configure_set(Update, Connected.run_if(client_connected()));
+        app.configure_set(
+            Update,
+            Connected.run_if(in_state(ConnectionState::Connected)),
+        );
 
+        // The transport is only inserted while connecting,
+        // until then the client just sits idle
         app.add_plugins(RenetClientPlugin)
             .add_plugins(NetcodeClientPlugin)
-            .insert_resource(client)
-            .insert_resource(transport)
+            .add_plugins(ConnectionPlugin)
+            .add_plugins(ConnectScreenPlugin)
+            .add_plugins(CharacterSelectPlugin)
+            .add_plugins(ChatPlugin)
+            .insert_resource(RenetClient::new(connection_config()))
             .init_resource::<ClientState>()
+            .init_resource::<ReceivedSnapshots>()
             .add_systems(
                 Update,
                 (
                     handle_server_messages,
-                    sync_movement,
                     sync_target,
                     sync_target_deselect,
+                    sync_map_loaded,
+                    apply_aura_updates.after(handle_server_messages),
                 )
                     .in_set(Connected),
             )
-            .add_systems(PostUpdate, (disconnect_on_exit, panic_on_error_system));
+            .add_systems(
+                FixedUpdate,
+                sync_inputs
+                    .after(predict_player_movement)
+                    .run_if(in_state(ConnectionState::Connected)),
+            )
+            .add_systems(PostUpdate, disconnect_on_exit);
     }
 }
 
+// Where server messages are handed over to the game systems
+#[derive(SystemParam)]
+struct GameUpdates<'w> {
+    move_acks: EventWriter<'w, MoveAckEvent>,
+    snapshots: EventWriter<'w, SnapshotEvent>,
+    chat_messages: EventWriter<'w, ChatMessageEvent>,
+    aura_updates: EventWriter<'w, AuraUpdateEvent>,
+    damage_texts: EventWriter<'w, DamageTextEvent>,
+    action_bar: ResMut<'w, ActionBar>,
+}
+
 fn handle_server_messages(
     mut client: ResMut<RenetClient>,
     mut client_state: ResMut<ClientState>,
+    config: Res<ClientConfig>,
+    mut character_selection: ResMut<CharacterSelection>,
+    mut next_game_state: ResMut<NextState<GameState>>,
+    mut connection_status: ResMut<ConnectionStatus>,
+    mut next_connection_state: ResMut<NextState<ConnectionState>>,
     asset_server: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut commands: Commands,
     mut send_map_change: EventWriter<MapChangeEvent>,
+    mut received_snapshots: ResMut<ReceivedSnapshots>,
+    mut updates: GameUpdates,
     mut player: Query<(&mut Transform, With<Player>)>,
 ) {
     // let client_id = client.client_id();
-    while let Some(message) = client.receive_message(DefaultChannel::ReliableUnordered) {
-        let server_message: Option<ServerMessages> = bincode::deserialize(&message).ok();
-        if server_message.is_none() {
-            log::error!("Failed to deserialize server message");
-            continue;
-        }
+    while let Some(message) = Channel::ALL
+        .iter()
+        .find_map(|channel| client.receive_message(*channel))
+    {
+        let server_message: ServerMessages = match decode_message(&message) {
+            Ok(server_message) => server_message,
+            Err(e) => {
+                log::error!("Failed to deserialize server message: {}", e);
+                continue;
+            }
+        };
 
-        let server_message = server_message.unwrap();
         log::debug!("Received message from server: {:?}", server_message);
         match server_message {
             ServerMessages::Despawn { entity } => {
@@ -140,17 +229,20 @@ fn handle_server_messages(
                 entity: server_entity,
             } => {
                 log::debug!("Spawn entity: {:?}", server_entity);
-                let client_side_entity = commands.spawn(ServerSideEntity(server_entity)).id();
+                let client_side_entity = commands
+                    .spawn((ServerSideEntity(server_entity), SnapshotBuffer::default()))
+                    .id();
                 client_state
                     .server_client_entity_mapping
                     .insert(server_entity, client_side_entity);
 
                 // request info about the entity
-                let msg = ClientMessages::RequestEntityInfo {
-                    entity: server_entity,
-                };
-                let msg = bincode::serialize(&msg).unwrap();
-                client.send_message(DefaultChannel::ReliableUnordered, msg);
+                send_message(
+                    &mut client,
+                    &ClientMessages::RequestEntityInfo {
+                        entity: server_entity,
+                    },
+                );
             }
             ServerMessages::EntityInfo {
                 entity: server_entity,
@@ -162,6 +254,7 @@ fn handle_server_messages(
                 max_health,
                 mana,
                 max_mana,
+                level,
                 threat,
                 interactable,
                 unit,
@@ -176,7 +269,9 @@ fn handle_server_messages(
                     continue;
                 }
 
-                let mut cmd = commands.entity(*client_entity.unwrap());
+                let client_entity = *client_entity.unwrap();
+                let threat = threat.map(|threat| client_state.threat_map(threat));
+                let mut cmd = commands.entity(client_entity);
 
                 let texture: Handle<Image> = asset_server.load(format!("images/{}.png", unit));
                 let texture_atlas =
@@ -203,6 +298,8 @@ fn handle_server_messages(
                     AnimateDirection(Facing::Down),
                     AnimateState(MovementState::Idle),
                     PreviousPos(pos),
+                    Auras::default(),
+                    Level(level),
                 ));
 
                 let font = asset_server.load("OpenSans-Regular.ttf");
@@ -229,30 +326,76 @@ fn handle_server_messages(
                     cmd.insert(Interactable);
                 }
             }
-            ServerMessages::Move {
-                entity: server_entity,
-                pos,
-                rotation,
-            } => {
-                let client_side_entity = client_state
-                    .server_client_entity_mapping
-                    .get(&server_entity);
+            ServerMessages::Snapshot { snapshot } => {
+                let Some(changes) = received_snapshots.receive(&snapshot) else {
+                    log::debug!("Dropping snapshot {} without baseline", snapshot.tick);
+                    continue;
+                };
 
-                if let Some(client_side_entity) = client_side_entity {
-                    log::debug!("Move entity: {:?}", server_entity);
-                    commands
-                        .entity(*client_side_entity)
-                        // todo: entities bounce between z index 0 and their correct position
-                        // causing them to flicker when the player is behind them
-                        .insert(Transform::from_translation(pos).with_rotation(rotation));
+                send_message(
+                    &mut client,
+                    &ClientMessages::SnapshotAck {
+                        tick: snapshot.tick,
+                    },
+                );
+
+                for (server_entity, state) in changes {
+                    let Some(client_entity) = client_state
+                        .server_client_entity_mapping
+                        .get(&server_entity)
+                    else {
+                        continue;
+                    };
+
+                    if client_state.player_entity == Some(server_entity) {
+                        // our own position is only part of it while it is charmed, show it right away
+                        if let Ok((mut transform, _)) = player.get_single_mut() {
+                            if let Some(position) = state.position {
+                                let position = position.to_vec2();
+                                transform.translation.x = position.x;
+                                transform.translation.y = position.y;
+                            }
+
+                            if let Some(rotation) = state.rotation {
+                                transform.rotation = Quat::from_rotation_z(rotation.to_radians());
+                            }
+                        }
+                    } else if state.position.is_some() || state.rotation.is_some() {
+                        // a delta may only carry one of them, interpolation needs both
+                        let latest = received_snapshots.latest(&server_entity);
+                        if let Some(position) = latest.and_then(|latest| latest.position) {
+                            let rotation = latest
+                                .and_then(|latest| latest.rotation)
+                                .map_or(0., |rotation| rotation.to_radians());
+
+                            updates.snapshots.send(SnapshotEvent {
+                                entity: *client_entity,
+                                snapshot: Snapshot {
+                                    time: snapshot.time,
+                                    position: position.to_vec2(),
+                                    rotation,
+                                },
+                            });
+                        }
+                    }
+
+                    if let Some(health) = state.health {
+                        commands.entity(*client_entity).insert(Health(health));
+                    }
+
+                    if let Some(mana) = state.mana {
+                        commands.entity(*client_entity).insert(Mana(mana));
+                    }
                 }
             }
             ServerMessages::PlayerInfo {
                 entity: server_entity,
                 pos,
+                name,
                 img,
             } => {
                 client_state.player_entity = Some(server_entity);
+                next_game_state.set(GameState::InGame);
 
                 let texture: Handle<Image> = asset_server.load(format!("images/{}.png", img));
                 let texture_atlas =
@@ -260,26 +403,20 @@ fn handle_server_messages(
                 let texture_atlas_handle = texture_atlases.add(texture_atlas);
                 // Use only the subset of sprites in the sheet that make up the run animation
                 let animation_indices = AnimationIndices { rows: 4, cols: 3 };
-                let name = Name::new("player");
+                let name = Name::new(name);
 
                 let mut cmd = commands.spawn((
                     Player,
                     ServerSideEntity(server_entity),
-                    RigidBody::Dynamic,
-                    GravityScale(0.0),
-                    Damping {
-                        linear_damping: 1.,
-                        angular_damping: 1.,
-                    },
-                    Ccd::enabled(),
-                    LockedAxes::ROTATION_LOCKED,
+                    // moved by the shared movement step, not by physics
+                    RigidBody::KinematicPositionBased,
                     SpriteSheetBundle {
                         transform: Transform::from_translation(pos),
                         texture_atlas: texture_atlas_handle,
                         sprite: TextureAtlasSprite::new(0),
                         ..default()
                     },
-                    Collider::cuboid(8., 8.),
+                    Collider::cuboid(PLAYER_HALF_SIZE, PLAYER_HALF_SIZE),
                     name.clone(),
                     CameraTarget,
                     AnimationTimer(Timer::from_seconds(0.5, TimerMode::Repeating)),
@@ -310,24 +447,6 @@ fn handle_server_messages(
                     .server_client_entity_mapping
                     .insert(server_entity, cmd.id());
             }
-            ServerMessages::EntityList {
-                entities: server_entities,
-            } => {
-                for server_entity in server_entities {
-                    // use get_or_spawn to mirror entity IDs on the server
-                    let client_entity = commands.spawn(ServerSideEntity(server_entity)).id();
-                    client_state
-                        .server_client_entity_mapping
-                        .insert(server_entity, client_entity);
-
-                    // request info about the entity
-                    let msg = ClientMessages::RequestEntityInfo {
-                        entity: server_entity,
-                    };
-                    let msg = bincode::serialize(&msg).unwrap();
-                    client.send_message(DefaultChannel::ReliableUnordered, msg);
-                }
-            }
             ServerMessages::Map { name, position } => {
                 send_map_change.send(MapChangeEvent { map_name: name });
 
@@ -346,12 +465,6 @@ fn handle_server_messages(
 
                 if let Some(client_entity) = client_entity {
                     match vital {
-                        Vitals::Health(health) => {
-                            commands.entity(*client_entity).insert(Health(health));
-                        }
-                        Vitals::Mana(mana) => {
-                            commands.entity(*client_entity).insert(Mana(mana));
-                        }
                         Vitals::Dead(is_dead) => {
                             if is_dead {
                                 commands.entity(*client_entity).insert(Dead);
@@ -371,6 +484,7 @@ fn handle_server_messages(
                     .get(&server_entity);
 
                 if let Some(client_entity) = client_entity {
+                    let threat = client_state.threat_map(threat);
                     commands.entity(*client_entity).insert(Threat(threat));
                 }
             }
@@ -392,24 +506,191 @@ fn handle_server_messages(
                 }
             }
             ServerMessages::Disconnect { reason } => {
-                panic!("Disconnected from server: {:?}", reason)
+                log::warn!("Disconnected from server: {:?}", reason);
+                let message = match reason {
+                    DisconnectionReason::VersionMismatch => {
+                        "The server runs a different version of the game".to_string()
+                    }
+                    DisconnectionReason::LoggedOut => "Logged out".to_string(),
+                    _ => format!("Disconnected from server: {:?}", reason),
+                };
+                connection_status.lost(message, reason.allows_reconnect());
+                next_connection_state.set(ConnectionState::Lost);
+
+                // everything after the disconnect is meaningless
+                return;
+            }
+            ServerMessages::MoveAck {
+                sequence,
+                pos,
+                speed,
+            } => {
+                updates.move_acks.send(MoveAckEvent {
+                    sequence,
+                    position: pos.truncate(),
+                    speed,
+                });
+            }
+            ServerMessages::Welcome => {
+                log::info!("Handshake complete");
+                send_message(&mut client, &ClientMessages::ListCharacters);
+            }
+            ServerMessages::CharacterList {
+                characters,
+                classes,
+            } => {
+                character_selection.set_list(characters, classes, &config.name);
+            }
+            ServerMessages::CharacterError { error } => {
+                log::warn!("Character request failed: {:?}", error);
+                character_selection.set_error(error);
+            }
+            ServerMessages::PlayerError { error } => {
+                let message = match error {
+                    PlayerErrorMessage::TooFarAway => "You are too far away",
+                    PlayerErrorMessage::ManaTooLow => "Not enough mana",
+                    PlayerErrorMessage::Unusable => "You can't do that",
+                    PlayerErrorMessage::OnCooldown => "That isn't ready yet",
+                    PlayerErrorMessage::InvalidTarget => "Invalid target",
+                    PlayerErrorMessage::AlreadyCasting => "You are already casting",
+                    PlayerErrorMessage::Stunned => "You are stunned",
+                };
+                updates
+                    .chat_messages
+                    .send(ChatMessageEvent::system(message));
+            }
+            ServerMessages::CastStart {
+                entity,
+                name,
+                duration,
+            } => {
+                if let Some(entity) = client_state.server_client_entity_mapping.get(&entity) {
+                    commands
+                        .entity(*entity)
+                        .insert(Casting::new(name, duration));
+                }
+            }
+            ServerMessages::CastFinish { entity } => {
+                if let Some(entity) = client_state.server_client_entity_mapping.get(&entity) {
+                    commands.entity(*entity).remove::<Casting>();
+                }
+            }
+            ServerMessages::CastInterrupt {
+                entity: server_entity,
+            } => {
+                if let Some(entity) = client_state
+                    .server_client_entity_mapping
+                    .get(&server_entity)
+                {
+                    commands.entity(*entity).remove::<Casting>();
+                }
+
+                if client_state.player_entity == Some(server_entity) {
+                    updates
+                        .chat_messages
+                        .send(ChatMessageEvent::system("Interrupted"));
+                }
+            }
+            ServerMessages::AuraApplied {
+                entity,
+                aura,
+                name,
+                stacks,
+                duration,
+                harmful,
+            } => {
+                if let Some(entity) = client_state.server_client_entity_mapping.get(&entity) {
+                    updates.aura_updates.send(AuraUpdateEvent::Applied {
+                        entity: *entity,
+                        aura: ClientAura {
+                            id: aura,
+                            name,
+                            stacks,
+                            harmful,
+                            timer: Timer::from_seconds(duration, TimerMode::Once),
+                        },
+                    });
+                }
+            }
+            ServerMessages::AuraRemoved { entity, aura } => {
+                if let Some(entity) = client_state.server_client_entity_mapping.get(&entity) {
+                    updates.aura_updates.send(AuraUpdateEvent::Removed {
+                        entity: *entity,
+                        aura,
+                    });
+                }
             }
-            ServerMessages::PlayerError { error } => match error {
-                tiled_game::network::messages::server::PlayerErrorMessage::TooFarAway => {
-                    println!("Too far away");
+            ServerMessages::LevelUp {
+                entity: server_entity,
+                level,
+                max_health,
+                max_mana,
+            } => {
+                if let Some(entity) = client_state
+                    .server_client_entity_mapping
+                    .get(&server_entity)
+                {
+                    commands.entity(*entity).insert((
+                        Level(level),
+                        MaxHealth(max_health),
+                        MaxMana(max_mana),
+                    ));
                 }
-                tiled_game::network::messages::server::PlayerErrorMessage::ManaTooLow => todo!(),
-                tiled_game::network::messages::server::PlayerErrorMessage::Unusable => todo!(),
-            },
+
+                if client_state.player_entity == Some(server_entity) {
+                    updates.chat_messages.send(ChatMessageEvent::system(format!(
+                        "You have reached level {}!",
+                        level
+                    )));
+                }
+            }
+            ServerMessages::Experience {
+                gained,
+                experience,
+                next_level,
+            } => {
+                updates.chat_messages.send(ChatMessageEvent::system(format!(
+                    "You gain {} experience ({}/{})",
+                    gained, experience, next_level
+                )));
+            }
+            ServerMessages::Damage {
+                target,
+                amount,
+                school,
+                result,
+                ..
+            } => {
+                if let Some(entity) = client_state.server_client_entity_mapping.get(&target) {
+                    updates.damage_texts.send(DamageTextEvent {
+                        entity: *entity,
+                        amount,
+                        school,
+                        result,
+                    });
+                }
+            }
+            ServerMessages::Abilities { abilities } => {
+                updates.action_bar.abilities = abilities;
+            }
+            ServerMessages::Chat {
+                channel,
+                sender,
+                message,
+            } => {
+                updates.chat_messages.send(ChatMessageEvent {
+                    channel,
+                    sender,
+                    message,
+                });
+            }
         }
     }
 }
 
 fn disconnect_on_exit(exit: EventReader<AppExit>, mut client: ResMut<RenetClient>) {
     if !exit.is_empty() {
-        let msg = ClientMessages::Disconnect;
-        let msg = bincode::serialize(&msg).unwrap();
-        client.send_message(DefaultChannel::ReliableUnordered, msg);
+        send_message(&mut client, &ClientMessages::Disconnect);
         client.disconnect();
     }
 }
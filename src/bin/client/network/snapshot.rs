@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use tiled_game::network::{
+    net_id::NetId,
+    snapshot::{EntityState, WorldSnapshot, WorldState},
+};
+
+// The server never encodes against snapshots older than the ones it still waits for
+const MAX_RECEIVED_SNAPSHOTS: usize = 64;
+
+// World states rebuilt from the received snapshots, ordered by tick
+// Kept around as baselines for the snapshots that follow
+#[derive(Resource, Default)]
+pub struct ReceivedSnapshots {
+    states: VecDeque<(u32, WorldState)>,
+}
+
+impl ReceivedSnapshots {
+    // Stores the snapshot and returns what changed compared to the newest known state
+    // None when it can't be decoded and must not be acknowledged
+    pub fn receive(&mut self, snapshot: &WorldSnapshot) -> Option<Vec<(NetId, EntityState)>> {
+        if self.find(snapshot.tick).is_some() {
+            return None;
+        }
+
+        let empty = WorldState::new();
+        let baseline = match snapshot.baseline {
+            Some(tick) => self.find(tick)?,
+            None => &empty,
+        };
+        let state = snapshot.apply(baseline);
+
+        // snapshots are sent unreliably and can arrive late,
+        // an older one is still a valid baseline but changes nothing
+        let late = self
+            .states
+            .back()
+            .is_some_and(|(tick, _)| *tick > snapshot.tick);
+        if late {
+            let index = self
+                .states
+                .iter()
+                .position(|(tick, _)| *tick > snapshot.tick)
+                .unwrap_or(self.states.len());
+            self.states.insert(index, (snapshot.tick, state));
+            self.truncate();
+            return Some(Vec::new());
+        }
+
+        let previous = self.states.back().map_or(&empty, |(_, state)| state);
+        let changes = state
+            .iter()
+            .filter_map(|(entity, entity_state)| {
+                let delta = match previous.get(entity) {
+                    Some(previous) => entity_state.delta(previous),
+                    None => Some(*entity_state),
+                };
+
+                delta.map(|delta| (*entity, delta))
+            })
+            .collect();
+
+        self.states.push_back((snapshot.tick, state));
+        self.truncate();
+
+        Some(changes)
+    }
+
+    // Everything known about the entity as of the newest snapshot
+    pub fn latest(&self, entity: &NetId) -> Option<&EntityState> {
+        self.states.back()?.1.get(entity)
+    }
+
+    fn find(&self, tick: u32) -> Option<&WorldState> {
+        self.states
+            .iter()
+            .find(|(state_tick, _)| *state_tick == tick)
+            .map(|(_, state)| state)
+    }
+
+    fn truncate(&mut self) {
+        while self.states.len() > MAX_RECEIVED_SNAPSHOTS {
+            self.states.pop_front();
+        }
+    }
+}
@@ -1,30 +1,29 @@
 use bevy::prelude::*;
-use bevy_renet::renet::{DefaultChannel, RenetClient};
+use bevy_renet::renet::RenetClient;
 use tiled_game::{components::Target, network::messages::client::ClientMessages};
 
-use crate::game::player::Player;
+use crate::game::{
+    map::MapLoadingState,
+    player::{movement::PredictionState, Player},
+};
 
-use super::ServerSideEntity;
+use super::{send_message, ServerSideEntity};
 
-// Sync player movement to the server
-pub fn sync_movement(
-    movement: Query<&Transform, (With<Player>, Changed<Transform>)>,
-    mut client: ResMut<RenetClient>,
-) {
-    if movement.is_empty() {
+// Inputs resent with every message so a lost packet doesn't lose them
+const REDUNDANT_INPUTS: usize = 8;
+
+// Sends the newest inputs the server hasn't acknowledged yet
+pub fn sync_inputs(prediction: Res<PredictionState>, mut client: ResMut<RenetClient>) {
+    if prediction.pending.is_empty() {
         return;
     }
 
-    let transform = movement.single();
-
-    let msg = ClientMessages::Move {
-        x: transform.translation.x,
-        y: transform.translation.y,
+    let skip = prediction.pending.len().saturating_sub(REDUNDANT_INPUTS);
+    let msg = ClientMessages::Input {
+        inputs: prediction.pending.iter().skip(skip).copied().collect(),
     };
 
-    let msg = bincode::serialize(&msg).unwrap();
-
-    client.send_message(DefaultChannel::ReliableUnordered, msg);
+    send_message(&mut client, &msg);
 }
 
 pub fn sync_target(
@@ -44,9 +43,7 @@ pub fn sync_target(
         target: Some(target_server_side.0),
     };
 
-    let msg = bincode::serialize(&msg).unwrap();
-
-    client.send_message(DefaultChannel::ReliableUnordered, msg);
+    send_message(&mut client, &msg);
 }
 
 pub fn sync_target_deselect(
@@ -65,8 +62,16 @@ pub fn sync_target_deselect(
 
         let msg = ClientMessages::Target { target: None };
 
-        let msg = bincode::serialize(&msg).unwrap();
-
-        client.send_message(DefaultChannel::ReliableUnordered, msg);
+        send_message(&mut client, &msg);
     });
 }
+
+// Tells the server the current map is loaded
+// so it starts sending the entities on it
+pub fn sync_map_loaded(loading_state: Res<MapLoadingState>, mut client: ResMut<RenetClient>) {
+    if !loading_state.is_changed() || !loading_state.is_loaded() {
+        return;
+    }
+
+    send_message(&mut client, &ClientMessages::Ready);
+}
@@ -0,0 +1,368 @@
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use bevy::prelude::Resource;
+use clap::Parser;
+use serde::{Deserialize, Deserializer};
+
+use crate::network::rate_limit::RateLimit;
+
+// Used when no --config argument is given and the file exists
+const DEFAULT_CONFIG_FILE: &str = "server.toml";
+
+// Name of the rate limit for message types without their own
+const DEFAULT_RATE_LIMIT: &str = "default";
+
+// Everything that can be changed per server instance.
+// Values are read from a TOML file first, CLI arguments override them.
+#[derive(Resource, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    // Address the UDP socket binds to
+    pub bind_addr: IpAddr,
+
+    // Address clients use to reach the server
+    // Defaults to the bind address
+    pub public_addr: Option<IpAddr>,
+
+    pub port: u16,
+
+    // Server updates per second
+    pub tick_rate: u32,
+
+    pub max_clients: usize,
+
+    // Directory containing the .tmx maps
+    pub maps_dir: PathBuf,
+
+    // TOML file with the abilities players can use
+    pub abilities: PathBuf,
+
+    // SQLite database for characters, use ":memory:" to not persist anything
+    pub database: PathBuf,
+
+    // Seconds between saving all online characters
+    pub autosave_interval: u64,
+
+    // Hex encoded key shared with the token issuer
+    // Without a key the server accepts unsecure connections
+    pub private_key: Option<String>,
+
+    // Sprite sheets players can choose from when creating a character
+    pub classes: Vec<String>,
+
+    // Characters a single account may have
+    pub max_characters: usize,
+
+    // Distance in pixels within which players are told about other entities
+    pub view_radius: f32,
+
+    // Token buckets per client message type, keyed by the name of the message
+    // Entries in the config file replace the built in ones of the same name, the others stay
+    // Messages over a limit are dropped, clients that keep flooding get kicked
+    #[serde(deserialize_with = "with_default_rate_limits")]
+    pub rate_limits: HashMap<String, RateLimit>,
+
+    // Seconds between printing the network metrics, 0 disables them
+    pub metrics_interval: u64,
+
+    // Distance in pixels within which players hear what others say
+    pub say_radius: f32,
+
+    // Words that are replaced with asterisks in chat messages
+    pub chat_filter: Vec<String>,
+
+    // Accounts allowed to use the admin slash commands
+    pub admins: Vec<u64>,
+
+    // Seconds players are warned before the server shuts down
+    pub shutdown_countdown: u64,
+
+    // Seconds to wait for clients to leave before exiting anyway
+    pub shutdown_drain_timeout: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            public_addr: None,
+            port: 3387,
+            tick_rate: 60,
+            max_clients: 64,
+            maps_dir: PathBuf::from("maps"),
+            abilities: PathBuf::from("abilities.toml"),
+            database: PathBuf::from("world.db"),
+            autosave_interval: 60,
+            private_key: None,
+            classes: (1..=4)
+                .map(|i| format!("Fantasy Dreamland/Characters/Character_{:03}", i))
+                .collect(),
+            max_characters: 5,
+            view_radius: 400.,
+            rate_limits: default_rate_limits(),
+            metrics_interval: 60,
+            say_radius: 300.,
+            chat_filter: Vec::new(),
+            admins: Vec::new(),
+            shutdown_countdown: 10,
+            shutdown_drain_timeout: 5,
+        }
+    }
+}
+
+fn default_rate_limits() -> HashMap<String, RateLimit> {
+    HashMap::from([
+        (DEFAULT_RATE_LIMIT.to_string(), RateLimit::new(5., 20.)),
+        // sent every frame while moving
+        ("Input".to_string(), RateLimit::new(240., 480.)),
+        ("SnapshotAck".to_string(), RateLimit::new(120., 240.)),
+        // a whole screen of entities comes into view at once after a map change
+        ("RequestEntityInfo".to_string(), RateLimit::new(20., 200.)),
+        ("Target".to_string(), RateLimit::new(10., 20.)),
+    ])
+}
+
+// Setting the limit of one message type must not drop the limits of all others
+fn with_default_rate_limits<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, RateLimit>, D::Error> {
+    let mut rate_limits = default_rate_limits();
+    rate_limits.extend(HashMap::<String, RateLimit>::deserialize(deserializer)?);
+    Ok(rate_limits)
+}
+
+#[derive(Parser, Debug)]
+#[command(about = "TiledMMO game server")]
+struct Args {
+    // Path to the TOML config file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    #[arg(long)]
+    bind_addr: Option<IpAddr>,
+
+    #[arg(long)]
+    public_addr: Option<IpAddr>,
+
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    #[arg(long)]
+    tick_rate: Option<u32>,
+
+    #[arg(long)]
+    max_clients: Option<usize>,
+
+    #[arg(long)]
+    maps_dir: Option<PathBuf>,
+
+    #[arg(long)]
+    abilities: Option<PathBuf>,
+
+    #[arg(long)]
+    database: Option<PathBuf>,
+
+    #[arg(long)]
+    autosave_interval: Option<u64>,
+
+    #[arg(long)]
+    private_key: Option<String>,
+
+    // Can be given multiple times, replaces the configured classes
+    #[arg(long = "class")]
+    classes: Vec<String>,
+
+    #[arg(long)]
+    max_characters: Option<usize>,
+
+    #[arg(long)]
+    view_radius: Option<f32>,
+
+    #[arg(long)]
+    metrics_interval: Option<u64>,
+
+    #[arg(long)]
+    say_radius: Option<f32>,
+
+    // Can be given multiple times, adds to the configured admins
+    #[arg(long = "admin")]
+    admins: Vec<u64>,
+
+    #[arg(long)]
+    shutdown_countdown: Option<u64>,
+
+    #[arg(long)]
+    shutdown_drain_timeout: Option<u64>,
+}
+
+impl ServerConfig {
+    // Reads the config file and applies the command line overrides
+    pub fn load() -> Self {
+        let args = Args::parse();
+
+        let config_file = args
+            .config
+            .clone()
+            .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()));
+
+        let mut config = match config_file {
+            Some(path) => Self::from_file(&path).expect("Could not load server config"),
+            None => Self::default(),
+        };
+
+        config.apply_args(args);
+        config
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        println!("Loading server config from {:?}", path);
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read config file {:?}", path))?;
+
+        toml::from_str(&content).with_context(|| format!("Invalid config file {:?}", path))
+    }
+
+    fn apply_args(&mut self, args: Args) {
+        if let Some(bind_addr) = args.bind_addr {
+            self.bind_addr = bind_addr;
+        }
+
+        if let Some(public_addr) = args.public_addr {
+            self.public_addr = Some(public_addr);
+        }
+
+        if let Some(port) = args.port {
+            self.port = port;
+        }
+
+        if let Some(tick_rate) = args.tick_rate {
+            self.tick_rate = tick_rate;
+        }
+
+        if let Some(max_clients) = args.max_clients {
+            self.max_clients = max_clients;
+        }
+
+        if let Some(maps_dir) = args.maps_dir {
+            self.maps_dir = maps_dir;
+        }
+
+        if let Some(abilities) = args.abilities {
+            self.abilities = abilities;
+        }
+
+        if let Some(database) = args.database {
+            self.database = database;
+        }
+
+        if let Some(autosave_interval) = args.autosave_interval {
+            self.autosave_interval = autosave_interval;
+        }
+
+        if let Some(private_key) = args.private_key {
+            self.private_key = Some(private_key);
+        }
+
+        if !args.classes.is_empty() {
+            self.classes = args.classes;
+        }
+
+        if let Some(max_characters) = args.max_characters {
+            self.max_characters = max_characters;
+        }
+
+        if let Some(view_radius) = args.view_radius {
+            self.view_radius = view_radius;
+        }
+
+        if let Some(metrics_interval) = args.metrics_interval {
+            self.metrics_interval = metrics_interval;
+        }
+
+        if let Some(say_radius) = args.say_radius {
+            self.say_radius = say_radius;
+        }
+
+        self.admins.extend(args.admins);
+
+        if let Some(shutdown_countdown) = args.shutdown_countdown {
+            self.shutdown_countdown = shutdown_countdown;
+        }
+
+        if let Some(shutdown_drain_timeout) = args.shutdown_drain_timeout {
+            self.shutdown_drain_timeout = shutdown_drain_timeout;
+        }
+    }
+
+    pub fn bind_address(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_addr, self.port)
+    }
+
+    pub fn public_address(&self) -> SocketAddr {
+        SocketAddr::new(self.public_addr.unwrap_or(self.bind_addr), self.port)
+    }
+
+    pub fn tick_duration(&self) -> Duration {
+        Duration::from_secs_f64(1. / self.tick_rate.max(1) as f64)
+    }
+
+    pub fn autosave_duration(&self) -> Duration {
+        Duration::from_secs(self.autosave_interval.max(1))
+    }
+
+    pub fn metrics_duration(&self) -> Duration {
+        Duration::from_secs(self.metrics_interval.max(1))
+    }
+
+    pub fn shutdown_countdown_duration(&self) -> Duration {
+        Duration::from_secs(self.shutdown_countdown)
+    }
+
+    pub fn shutdown_drain_duration(&self) -> Duration {
+        Duration::from_secs(self.shutdown_drain_timeout)
+    }
+
+    // Falls back to the default limit, or the built in one if the config has none
+    pub fn rate_limit(&self, message: &str) -> RateLimit {
+        self.rate_limits
+            .get(message)
+            .or_else(|| self.rate_limits.get(DEFAULT_RATE_LIMIT))
+            .copied()
+            .unwrap_or(RateLimit::new(5., 20.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limits_are_merged_into_the_defaults() {
+        let config: ServerConfig = toml::from_str(
+            "[rate_limits]\n\
+             Chat = { per_second = 1.0, burst = 3.0 }\n\
+             Input = { per_second = 60.0, burst = 120.0 }\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.rate_limit("Chat").burst, 3.);
+        assert_eq!(config.rate_limit("Input").per_second, 60.);
+        assert_eq!(config.rate_limit("SnapshotAck").per_second, 120.);
+        assert_eq!(config.rate_limit("Interact").burst, 20.);
+    }
+
+    #[test]
+    fn rate_limits_default_without_a_table() {
+        let config: ServerConfig = toml::from_str("port = 4000").unwrap();
+
+        assert_eq!(config.rate_limits.len(), default_rate_limits().len());
+    }
+}
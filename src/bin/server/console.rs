@@ -0,0 +1,290 @@
+/**
+ * Admin console on the server's stdin
+ *
+ * Lines are read on their own thread, so a blocking read never stalls a tick,
+ * and run as commands once per frame.
+ */
+use std::{
+    io::{self, BufRead},
+    sync::{
+        mpsc::{self, Receiver},
+        Mutex,
+    },
+    thread,
+};
+
+use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use tiled_game::network::messages::server::{DisconnectionReason, ServerMessages};
+
+use crate::{
+    game::{
+        map::{MapInstance, MapManager, MapName, Teleport},
+        player::Player,
+    },
+    network::{
+        disconnect_client, AccountId, NetworkClientId, PendingDisconnects, SendServerMessageEvent,
+    },
+    shutdown::ShutdownEvent,
+    storage::Storage,
+};
+
+const HELP: &str = "Commands:
+  players                          list online players
+  maps                             list map instances
+  kick <player>                    disconnect a player
+  ban <player|account id>          disconnect a player and keep the account out
+  unban <account id>               let a banned account back in
+  teleport <player> <map> <x> <y>  move a player to a position on a map
+  broadcast <message>              tell everyone online
+  shutdown                         save, disconnect everyone and stop the server after a countdown
+  restart                          like shutdown, but clients reconnect on their own";
+
+// Lines typed into stdin
+// Receivers can't be shared between threads, bevy resources have to be
+#[derive(Resource)]
+struct ConsoleInput(Mutex<Receiver<String>>);
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ConsoleInput(Mutex::new(spawn_stdin_reader())))
+            .add_systems(Update, run_console_commands);
+    }
+}
+
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            // stdin closed or the server is gone
+            let Ok(line) = line else {
+                break;
+            };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+#[derive(Debug)]
+enum ConsoleCommand {
+    Help,
+    Players,
+    Maps,
+    Kick {
+        player: String,
+    },
+    Ban {
+        player: String,
+    },
+    Unban {
+        account_id: u64,
+    },
+    Teleport {
+        player: String,
+        map: String,
+        x: f32,
+        y: f32,
+    },
+    Broadcast {
+        message: String,
+    },
+    Shutdown,
+    Restart,
+}
+
+impl ConsoleCommand {
+    // None for empty lines
+    fn parse(line: &str) -> Result<Option<Self>, String> {
+        let mut args = line.split_whitespace();
+        let Some(command) = args.next() else {
+            return Ok(None);
+        };
+        let args: Vec<&str> = args.collect();
+
+        let command = match (command, args.as_slice()) {
+            ("help", []) => ConsoleCommand::Help,
+            ("players", []) => ConsoleCommand::Players,
+            ("maps", []) => ConsoleCommand::Maps,
+            ("kick", [player]) => ConsoleCommand::Kick {
+                player: player.to_string(),
+            },
+            ("ban", [player]) => ConsoleCommand::Ban {
+                player: player.to_string(),
+            },
+            ("unban", [account_id]) => ConsoleCommand::Unban {
+                account_id: account_id
+                    .parse()
+                    .map_err(|_| format!("Invalid account id {:?}", account_id))?,
+            },
+            ("teleport", [player, map, x, y]) => ConsoleCommand::Teleport {
+                player: player.to_string(),
+                map: map.to_string(),
+                x: x.parse().map_err(|_| format!("Invalid x {:?}", x))?,
+                y: y.parse().map_err(|_| format!("Invalid y {:?}", y))?,
+            },
+            ("broadcast", [_, ..]) => ConsoleCommand::Broadcast {
+                message: args.join(" "),
+            },
+            ("shutdown", []) => ConsoleCommand::Shutdown,
+            ("restart", []) => ConsoleCommand::Restart,
+            _ => return Err(format!("Unknown command {:?}, try help", line.trim())),
+        };
+
+        Ok(Some(command))
+    }
+}
+
+fn run_console_commands(
+    input: Res<ConsoleInput>,
+    mut server: ResMut<RenetServer>,
+    mut pending_disconnects: ResMut<PendingDisconnects>,
+    storage: Res<Storage>,
+    map_manager: Res<MapManager>,
+    players: Query<
+        (
+            Entity,
+            &Name,
+            &NetworkClientId,
+            &AccountId,
+            &Transform,
+            Option<&Parent>,
+        ),
+        With<Player>,
+    >,
+    map_instances: Query<(Entity, &MapName), With<MapInstance>>,
+    mut teleports: EventWriter<Teleport>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    mut shutdown: EventWriter<ShutdownEvent>,
+) {
+    let lines: Vec<String> = input.0.lock().unwrap().try_iter().collect();
+
+    for line in lines {
+        let command = match ConsoleCommand::parse(&line) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+
+        let find_player =
+            |player: &str| players.iter().find(|(_, name, ..)| name.as_str() == player);
+
+        match command {
+            ConsoleCommand::Help => println!("{}", HELP),
+            ConsoleCommand::Players => {
+                println!("{} players online", players.iter().count());
+
+                for (_, name, client_id, account_id, transform, map_instance) in players.iter() {
+                    let map = map_instance
+                        .and_then(|map_instance| map_instances.get(map_instance.get()).ok())
+                        .map_or("-", |(_, map_name)| map_name.0.as_str());
+
+                    println!(
+                        "  {} (client {}, account {}) on {} at {:.0},{:.0}",
+                        name,
+                        client_id.0,
+                        account_id.0,
+                        map,
+                        transform.translation.x,
+                        transform.translation.y
+                    );
+                }
+            }
+            ConsoleCommand::Maps => {
+                for (map_instance, map_name) in map_instances.iter() {
+                    let player_count = players
+                        .iter()
+                        .filter(|(.., parent)| parent.is_some_and(|p| p.get() == map_instance))
+                        .count();
+
+                    println!(
+                        "  {} ({:?}) with {} players",
+                        map_name.0, map_instance, player_count
+                    );
+                }
+            }
+            ConsoleCommand::Kick { player } => match find_player(&player) {
+                Some((_, _, client_id, ..)) => {
+                    disconnect_client(
+                        &mut server,
+                        &mut pending_disconnects,
+                        client_id.0,
+                        DisconnectionReason::Kicked,
+                    );
+                }
+                None => println!("No player named {:?} online", player),
+            },
+            ConsoleCommand::Ban { player } => {
+                let online = find_player(&player);
+                let account_id = match online {
+                    Some((_, _, _, account_id, ..)) => account_id.0,
+                    None => match player.parse() {
+                        Ok(account_id) => account_id,
+                        Err(_) => {
+                            println!("No player named {:?} online", player);
+                            continue;
+                        }
+                    },
+                };
+
+                if let Err(e) = storage.0.ban_account(account_id) {
+                    println!("Could not ban account {}: {}", account_id, e);
+                    continue;
+                }
+                println!("Banned account {}", account_id);
+
+                if let Some((_, _, client_id, ..)) = online {
+                    disconnect_client(
+                        &mut server,
+                        &mut pending_disconnects,
+                        client_id.0,
+                        DisconnectionReason::Banned,
+                    );
+                }
+            }
+            ConsoleCommand::Unban { account_id } => match storage.0.unban_account(account_id) {
+                Ok(true) => println!("Unbanned account {}", account_id),
+                Ok(false) => println!("Account {} is not banned", account_id),
+                Err(e) => println!("Could not unban account {}: {}", account_id, e),
+            },
+            ConsoleCommand::Teleport { player, map, x, y } => {
+                if !map_manager.atlas.contains_key(&map) {
+                    println!("Unknown map {:?}", map);
+                    continue;
+                }
+
+                match find_player(&player) {
+                    Some((entity, .., map_instance)) => teleports.send(Teleport {
+                        entity,
+                        map,
+                        map_instance: None,
+                        prev_map_instance: map_instance.map(|parent| parent.get()),
+                        position: Transform::from_xyz(x, y, 0.),
+                    }),
+                    None => println!("No player named {:?} online", player),
+                }
+            }
+            ConsoleCommand::Broadcast { message } => {
+                server_messages.send(SendServerMessageEvent {
+                    client_id: None,
+                    message: ServerMessages::system_chat(message),
+                });
+            }
+            ConsoleCommand::Shutdown => shutdown.send(ShutdownEvent {
+                reason: DisconnectionReason::ServerShutdown,
+            }),
+            ConsoleCommand::Restart => shutdown.send(ShutdownEvent {
+                reason: DisconnectionReason::ServerRestart,
+            }),
+        }
+    }
+}
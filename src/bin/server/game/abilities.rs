@@ -0,0 +1,610 @@
+/**
+ * Abilities units cast on themselves or their target
+ *
+ * The abilities that exist are read from a TOML file at startup.
+ * A cast is checked when it starts and again when it goes off,
+ * players are told why theirs failed. Casts that take time are shown to
+ * everyone around and get interrupted when the caster moves or takes damage.
+ */
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use bevy::prelude::*;
+use serde::Deserialize;
+use tiled_game::{
+    abilities::{Ability, AbilityEffect, AbilityTarget, Aura},
+    components::*,
+    network::messages::server::{PlayerErrorMessage, ServerMessages},
+};
+
+use crate::{
+    config::ServerConfig,
+    network::{NetworkClientId, SendServerMessageEvent},
+};
+
+use super::{
+    auras::{ApplyAuraEvent, DispelEvent, Stunned},
+    combat::{DoDamageEvent, HealEvent},
+    npc::Enemy,
+    player::Player,
+    stats::{roll_attack, Stats},
+};
+
+#[derive(Deserialize)]
+struct AbilityFile {
+    abilities: Vec<Ability>,
+    #[serde(default)]
+    auras: Vec<Aura>,
+}
+
+// Every ability in the order of the abilities file, and the auras they apply
+#[derive(Resource)]
+pub struct Abilities {
+    abilities: Vec<Ability>,
+    auras: Vec<Aura>,
+}
+
+impl Abilities {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        println!("Loading abilities from {:?}", path);
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read abilities file {:?}", path))?;
+
+        Self::parse(&content).with_context(|| format!("Invalid abilities file {:?}", path))
+    }
+
+    // Everything that would only fail once the ability is used is checked here
+    pub fn parse(content: &str) -> anyhow::Result<Self> {
+        let file: AbilityFile = toml::from_str(content)?;
+
+        let abilities = Self {
+            abilities: file.abilities,
+            auras: file.auras,
+        };
+
+        for ability in abilities.abilities.iter() {
+            if let AbilityEffect::ApplyAura(aura) = &ability.effect {
+                if abilities.aura(aura).is_none() {
+                    bail!("Ability {:?} applies unknown aura {:?}", ability.id, aura);
+                }
+            }
+
+            if !is_duration(ability.cast_time) {
+                bail!(
+                    "Ability {:?} needs a cast_time of 0 or more seconds",
+                    ability.id
+                );
+            }
+            if !is_duration(ability.cooldown) {
+                bail!(
+                    "Ability {:?} needs a cooldown of 0 or more seconds",
+                    ability.id
+                );
+            }
+            if ability.mana_cost < 0 {
+                bail!("Ability {:?} has a negative mana_cost", ability.id);
+            }
+        }
+
+        for aura in abilities.auras.iter() {
+            if !is_duration(aura.duration) {
+                bail!("Aura {:?} needs a duration of 0 or more seconds", aura.id);
+            }
+            // a repeating timer of 0 seconds finishes on every frame
+            if !is_duration(aura.tick_interval) || aura.tick_interval == 0. {
+                bail!(
+                    "Aura {:?} needs a tick_interval of more than 0 seconds",
+                    aura.id
+                );
+            }
+        }
+
+        Ok(abilities)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Ability> {
+        self.abilities.iter().find(|ability| ability.id == id)
+    }
+
+    pub fn aura(&self, id: &str) -> Option<&Aura> {
+        self.auras.iter().find(|aura| aura.id == id)
+    }
+}
+
+// Timers panic on negative, infinite or NaN seconds
+fn is_duration(seconds: f32) -> bool {
+    seconds.is_finite() && seconds >= 0.
+}
+
+// A unit wants to use an ability
+#[derive(Event)]
+pub struct CastAbilityEvent {
+    pub caster: Entity,
+    pub ability: String,
+    // Whatever the caster has selected, the ability decides what it hits
+    pub target: Option<Entity>,
+}
+
+// Abilities the unit used recently, removed once they can be used again
+#[derive(Component, Default)]
+pub struct Cooldowns(HashMap<String, Timer>);
+
+// Pixels a caster may be pushed around without losing its cast
+const MOVE_TOLERANCE: f32 = 1.;
+
+// The ability goes off on the target once the timer finishes
+#[derive(Component)]
+pub struct Casting {
+    pub ability: String,
+    pub target: Entity,
+    pub timer: Timer,
+    // Where the caster stood when the cast started
+    pub origin: Vec3,
+}
+
+impl Casting {
+    // Instant abilities can't be interrupted and nobody sees a cast bar for them
+    pub fn takes_time(&self) -> bool {
+        !self.timer.duration().is_zero()
+    }
+}
+
+// A cast that takes time started or ended
+#[derive(Event)]
+pub enum CastEvent {
+    Started {
+        caster: Entity,
+        name: String,
+        duration: f32,
+    },
+    Finished {
+        caster: Entity,
+    },
+    Interrupted {
+        caster: Entity,
+    },
+}
+
+// What the target rules and range checks need to know about a unit
+type CastUnit<'a> = (&'a Transform, &'a Parent, Option<&'a Enemy>);
+
+pub struct AbilitiesPlugin;
+
+impl Plugin for AbilitiesPlugin {
+    fn build(&self, app: &mut App) {
+        let config = app.world.resource::<ServerConfig>();
+        let abilities = Abilities::load(&config.abilities).expect("Could not load abilities");
+
+        app.insert_resource(abilities)
+            .add_event::<CastAbilityEvent>()
+            .add_event::<CastEvent>()
+            .add_systems(
+                Update,
+                (
+                    send_abilities,
+                    (
+                        tick_cooldowns,
+                        start_casts,
+                        interrupt_casts,
+                        // interrupted casts must not go off anymore
+                        apply_deferred,
+                        finish_casts,
+                    )
+                        .chain(),
+                ),
+            );
+    }
+}
+
+pub fn player_error(
+    client_id: &NetworkClientId,
+    error: PlayerErrorMessage,
+) -> SendServerMessageEvent {
+    SendServerMessageEvent {
+        client_id: Some(client_id.0),
+        message: ServerMessages::PlayerError { error },
+    }
+}
+
+// Players and friendly NPCs on one side, enemies on the other
+fn hostile(a: Option<&Enemy>, b: Option<&Enemy>) -> bool {
+    a.is_some() != b.is_some()
+}
+
+// Picks the entity the ability hits according to its target rule
+fn select_target(
+    ability: &Ability,
+    caster: Entity,
+    target: Option<Entity>,
+    units: &Query<CastUnit, (With<Unit>, Without<Dead>)>,
+) -> Result<Entity, PlayerErrorMessage> {
+    let caster_enemy = units
+        .get(caster)
+        .map_err(|_| PlayerErrorMessage::Unusable)?
+        .2;
+    let is_hostile = |target: &Entity| {
+        units
+            .get(*target)
+            .is_ok_and(|(.., enemy)| hostile(caster_enemy, enemy))
+    };
+
+    match ability.target {
+        AbilityTarget::Caster => Ok(caster),
+        AbilityTarget::Enemy => target
+            .filter(|target| *target != caster && is_hostile(target))
+            .ok_or(PlayerErrorMessage::InvalidTarget),
+        AbilityTarget::Friendly => Ok(target
+            .filter(|target| units.contains(*target) && !is_hostile(target))
+            .unwrap_or(caster)),
+    }
+}
+
+// The target has to be alive, on the same map instance and within range
+fn check_target(
+    ability: &Ability,
+    caster: Entity,
+    target: Entity,
+    units: &Query<CastUnit, (With<Unit>, Without<Dead>)>,
+) -> Result<(), PlayerErrorMessage> {
+    let (Ok((caster_transform, caster_map, _)), Ok((target_transform, target_map, _))) =
+        (units.get(caster), units.get(target))
+    else {
+        return Err(PlayerErrorMessage::InvalidTarget);
+    };
+
+    if caster_map.get() != target_map.get() {
+        return Err(PlayerErrorMessage::InvalidTarget);
+    }
+
+    if target != caster
+        && caster_transform
+            .translation
+            .truncate()
+            .distance(target_transform.translation.truncate())
+            > ability.range
+    {
+        return Err(PlayerErrorMessage::TooFarAway);
+    }
+
+    Ok(())
+}
+
+// Tells new players which abilities they can use
+fn send_abilities(
+    abilities: Res<Abilities>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    players: Query<&NetworkClientId, Added<Player>>,
+) {
+    for client_id in players.iter() {
+        server_messages.send(SendServerMessageEvent {
+            client_id: Some(client_id.0),
+            message: ServerMessages::Abilities {
+                abilities: abilities.abilities.clone(),
+            },
+        });
+    }
+}
+
+fn tick_cooldowns(time: Res<Time>, mut cooldowns: Query<&mut Cooldowns>) {
+    for mut cooldowns in cooldowns.iter_mut() {
+        cooldowns
+            .0
+            .retain(|_, timer| !timer.tick(time.delta()).finished());
+    }
+}
+
+fn start_casts(
+    mut commands: Commands,
+    abilities: Res<Abilities>,
+    mut cast_requests: EventReader<CastAbilityEvent>,
+    mut cast_events: EventWriter<CastEvent>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    casters: Query<(
+        &Mana,
+        Option<&Cooldowns>,
+        Option<&Casting>,
+        Option<&NetworkClientId>,
+        Option<&Stunned>,
+    )>,
+    units: Query<CastUnit, (With<Unit>, Without<Dead>)>,
+) {
+    for event in cast_requests.iter() {
+        let Ok((mana, cooldowns, casting, client_id, stunned)) = casters.get(event.caster) else {
+            continue;
+        };
+
+        let cast = abilities
+            .get(&event.ability)
+            .ok_or(PlayerErrorMessage::Unusable)
+            .and_then(|ability| {
+                if stunned.is_some() {
+                    return Err(PlayerErrorMessage::Stunned);
+                }
+
+                if casting.is_some() {
+                    return Err(PlayerErrorMessage::AlreadyCasting);
+                }
+
+                if cooldowns.is_some_and(|cooldowns| cooldowns.0.contains_key(&ability.id)) {
+                    return Err(PlayerErrorMessage::OnCooldown);
+                }
+
+                if mana.0 < ability.mana_cost {
+                    return Err(PlayerErrorMessage::ManaTooLow);
+                }
+
+                let target = select_target(ability, event.caster, event.target, &units)?;
+                check_target(ability, event.caster, target, &units)?;
+
+                Ok((ability, target))
+            });
+
+        match cast {
+            Ok((ability, target)) => {
+                let Ok((transform, ..)) = units.get(event.caster) else {
+                    continue;
+                };
+
+                // instant abilities go off in the next frame
+                let casting = Casting {
+                    ability: ability.id.clone(),
+                    target,
+                    timer: Timer::from_seconds(ability.cast_time, TimerMode::Once),
+                    origin: transform.translation,
+                };
+
+                if casting.takes_time() {
+                    cast_events.send(CastEvent::Started {
+                        caster: event.caster,
+                        name: ability.name.clone(),
+                        duration: ability.cast_time,
+                    });
+                }
+
+                commands.entity(event.caster).insert(casting);
+            }
+            Err(error) => {
+                if let Some(client_id) = client_id {
+                    server_messages.send(player_error(client_id, error));
+                }
+            }
+        }
+    }
+}
+
+// Dying or getting stunned ends every cast, moving or taking damage those that take time
+fn interrupt_casts(
+    mut commands: Commands,
+    mut damage_events: EventReader<DoDamageEvent>,
+    mut cast_events: EventWriter<CastEvent>,
+    casters: Query<(
+        Entity,
+        &Casting,
+        &Transform,
+        Option<&Dead>,
+        Option<&Stunned>,
+    )>,
+) {
+    // a miss or dodge doesn't break the concentration of the caster
+    let damaged: HashSet<Entity> = damage_events
+        .iter()
+        .filter(|evt| evt.damage > 0)
+        .map(|evt| evt.receiver)
+        .collect();
+
+    for (caster, casting, transform, dead, stunned) in casters.iter() {
+        let moved = transform
+            .translation
+            .truncate()
+            .distance(casting.origin.truncate())
+            > MOVE_TOLERANCE;
+
+        let ended = dead.is_some() || stunned.is_some();
+        if !ended && !(casting.takes_time() && (moved || damaged.contains(&caster))) {
+            continue;
+        }
+
+        commands.entity(caster).remove::<Casting>();
+        if casting.takes_time() {
+            cast_events.send(CastEvent::Interrupted { caster });
+        }
+    }
+}
+
+fn finish_casts(
+    mut commands: Commands,
+    time: Res<Time>,
+    abilities: Res<Abilities>,
+    mut casters: Query<(
+        Entity,
+        &mut Casting,
+        &mut Mana,
+        Option<&mut Cooldowns>,
+        Option<&NetworkClientId>,
+    )>,
+    units: Query<CastUnit, (With<Unit>, Without<Dead>)>,
+    stats: Query<&Stats>,
+    mut damage_events: EventWriter<DoDamageEvent>,
+    mut heal_events: EventWriter<HealEvent>,
+    mut aura_events: EventWriter<ApplyAuraEvent>,
+    mut dispel_events: EventWriter<DispelEvent>,
+    mut cast_events: EventWriter<CastEvent>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+) {
+    for (caster, mut casting, mut mana, cooldowns, client_id) in casters.iter_mut() {
+        if !casting.timer.tick(time.delta()).finished() {
+            continue;
+        }
+
+        commands.entity(caster).remove::<Casting>();
+
+        let Some(ability) = abilities.get(&casting.ability) else {
+            continue;
+        };
+
+        // the target might have died or walked away during the cast
+        let result = check_target(ability, caster, casting.target, &units)
+            .and_then(|_| {
+                if mana.0 < ability.mana_cost {
+                    return Err(PlayerErrorMessage::ManaTooLow);
+                }
+                Ok(())
+            })
+            // everything the effect needs is resolved before anything is paid
+            .and_then(|_| match &ability.effect {
+                AbilityEffect::Damage(damage) => {
+                    let [caster_stats, target_stats] = stats
+                        .get_many([caster, casting.target])
+                        .map_err(|_| PlayerErrorMessage::InvalidTarget)?;
+
+                    let damage = damage + caster_stats.bonus_damage(ability.school);
+                    Ok(Some(roll_attack(
+                        caster_stats,
+                        target_stats,
+                        ability.school,
+                        damage,
+                    )))
+                }
+                _ => Ok(None),
+            });
+
+        let attack = match result {
+            Ok(attack) => attack,
+            Err(error) => {
+                if casting.takes_time() {
+                    cast_events.send(CastEvent::Interrupted { caster });
+                }
+                if let Some(client_id) = client_id {
+                    server_messages.send(player_error(client_id, error));
+                }
+                continue;
+            }
+        };
+
+        if casting.takes_time() {
+            cast_events.send(CastEvent::Finished { caster });
+        }
+
+        mana.0 -= ability.mana_cost;
+
+        if ability.cooldown > 0. {
+            let timer = Timer::from_seconds(ability.cooldown, TimerMode::Once);
+            match cooldowns {
+                Some(mut cooldowns) => {
+                    cooldowns.0.insert(ability.id.clone(), timer);
+                }
+                None => {
+                    commands
+                        .entity(caster)
+                        .insert(Cooldowns(HashMap::from([(ability.id.clone(), timer)])));
+                }
+            }
+        }
+
+        println!("{:?} used {} on {:?}", caster, ability.name, casting.target);
+
+        match &ability.effect {
+            AbilityEffect::Damage(_) => {
+                let Some((damage, result)) = attack else {
+                    continue;
+                };
+
+                damage_events.send(DoDamageEvent::new(
+                    caster,
+                    casting.target,
+                    damage,
+                    ability.school,
+                    result,
+                ));
+            }
+            AbilityEffect::Heal(amount) => heal_events.send(HealEvent {
+                origin: caster,
+                receiver: casting.target,
+                amount: *amount,
+            }),
+            AbilityEffect::ApplyAura(aura) => aura_events.send(ApplyAuraEvent {
+                caster,
+                target: casting.target,
+                aura: aura.clone(),
+            }),
+            AbilityEffect::Dispel(dispel) => dispel_events.send(DispelEvent {
+                target: casting.target,
+                dispel: *dispel,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ability(fields: &str) -> String {
+        format!(
+            "[[abilities]]\n\
+             id = \"bolt\"\n\
+             name = \"Bolt\"\n\
+             target = \"enemy\"\n\
+             effect = {{ damage = 10 }}\n\
+             {}\n",
+            fields
+        )
+    }
+
+    fn aura(fields: &str) -> String {
+        format!(
+            "abilities = []\n\
+             [[auras]]\n\
+             id = \"burn\"\n\
+             name = \"Burn\"\n\
+             effects = [{{ damage_over_time = 2 }}]\n\
+             {}\n",
+            fields
+        )
+    }
+
+    #[test]
+    fn the_shipped_abilities_load() {
+        Abilities::parse(include_str!("../../../../abilities.toml")).unwrap();
+    }
+
+    #[test]
+    fn valid_numbers_load() {
+        let abilities =
+            Abilities::parse(&ability("cast_time = 1.5\ncooldown = 0\nmana_cost = 5")).unwrap();
+
+        assert_eq!(abilities.get("bolt").unwrap().cast_time, 1.5);
+    }
+
+    #[test]
+    fn negative_numbers_are_rejected() {
+        for fields in [
+            "cast_time = -1.0",
+            "cooldown = -0.5",
+            "mana_cost = -10",
+            "cooldown = nan",
+            "cast_time = inf",
+        ] {
+            assert!(Abilities::parse(&ability(fields)).is_err(), "{}", fields);
+        }
+    }
+
+    #[test]
+    fn auras_need_a_duration_and_a_tick_interval() {
+        assert!(Abilities::parse(&aura("duration = 6.0\ntick_interval = 2.0")).is_ok());
+        assert!(Abilities::parse(&aura("duration = 0.0")).is_ok());
+
+        for fields in [
+            "duration = -1.0",
+            "duration = 6.0\ntick_interval = 0.0",
+            "duration = 6.0\ntick_interval = -1.0",
+        ] {
+            assert!(Abilities::parse(&aura(fields)).is_err(), "{}", fields);
+        }
+    }
+}
@@ -0,0 +1,586 @@
+/**
+ * Timed effects on units, like poisons, slows and stuns
+ *
+ * Auras are put on units by abilities. What they change about a unit is
+ * computed again from its base values whenever its auras change,
+ * so nothing lingers once they expire or get dispelled.
+ */
+use std::time::Duration;
+
+use bevy::prelude::*;
+use tiled_game::{
+    abilities::{Aura, AuraEffect, AuraStacking, DispelType},
+    combat::HitResult,
+    components::*,
+};
+
+use super::{
+    abilities::Abilities,
+    combat::{DoDamageEvent, HealEvent},
+    stats::Stats,
+    unit::{AttackSpeed, Speed},
+};
+
+// Puts an aura from the abilities file on the target
+#[derive(Event)]
+pub struct ApplyAuraEvent {
+    pub caster: Entity,
+    pub target: Entity,
+    pub aura: String,
+}
+
+// Removes the auras of a category from the target
+#[derive(Event)]
+pub struct DispelEvent {
+    pub target: Entity,
+    pub dispel: DispelType,
+}
+
+// An aura changed on a unit, everyone who sees it is told
+#[derive(Event)]
+pub enum AuraEvent {
+    Applied {
+        entity: Entity,
+        aura: String,
+        name: String,
+        stacks: u32,
+        // Seconds left
+        duration: f32,
+        harmful: bool,
+    },
+    Removed {
+        entity: Entity,
+        aura: String,
+    },
+}
+
+impl AuraEvent {
+    fn applied(entity: Entity, aura: &Aura, active: &ActiveAura) -> Self {
+        AuraEvent::Applied {
+            entity,
+            aura: aura.id.clone(),
+            name: aura.name.clone(),
+            stacks: active.stacks,
+            duration: active.remaining_secs(),
+            harmful: aura.harmful,
+        }
+    }
+}
+
+pub struct ActiveAura {
+    pub id: String,
+    // Damage and heals over time are done in its name
+    pub caster: Entity,
+    pub stacks: u32,
+    // Damage the shield of the aura can still take
+    absorb: i32,
+    duration: Timer,
+    tick: Timer,
+}
+
+impl ActiveAura {
+    fn new(aura: &Aura, caster: Entity) -> Self {
+        Self {
+            id: aura.id.clone(),
+            caster,
+            stacks: 1,
+            absorb: shield(aura, 1),
+            duration: Timer::from_seconds(aura.duration, TimerMode::Once),
+            tick: Timer::from_seconds(aura.tick_interval, TimerMode::Repeating),
+        }
+    }
+
+    pub fn remaining_secs(&self) -> f32 {
+        self.duration.remaining_secs()
+    }
+}
+
+fn shield(aura: &Aura, stacks: u32) -> i32 {
+    aura.effects
+        .iter()
+        .map(|effect| match effect {
+            AuraEffect::Absorb(amount) => amount * stacks as i32,
+            _ => 0,
+        })
+        .sum()
+}
+
+// Every unit has one, most of the time it's empty
+#[derive(Component, Default)]
+pub struct Auras(pub Vec<ActiveAura>);
+
+impl Auras {
+    // Shields take the damage in the order they were applied and break when used up
+    // Returns how much of the damage they took
+    pub fn absorb(
+        &mut self,
+        entity: Entity,
+        damage: i32,
+        aura_events: &mut EventWriter<AuraEvent>,
+    ) -> i32 {
+        let mut absorbed = 0;
+
+        self.0.retain_mut(|active| {
+            if active.absorb <= 0 || absorbed >= damage {
+                return true;
+            }
+
+            let taken = active.absorb.min(damage - absorbed);
+            active.absorb -= taken;
+            absorbed += taken;
+
+            if active.absorb > 0 {
+                return true;
+            }
+
+            aura_events.send(AuraEvent::Removed {
+                entity,
+                aura: active.id.clone(),
+            });
+            false
+        });
+
+        absorbed
+    }
+}
+
+// Values of the unit without any auras, taken when the first aura changes something
+#[derive(Component, Clone, Copy)]
+struct BaseStats {
+    speed: f32,
+    attack_speed: Duration,
+}
+
+// Can't move, attack or cast
+#[derive(Component)]
+pub struct Stunned;
+
+// Can't move
+#[derive(Component)]
+pub struct Rooted;
+
+pub struct AurasPlugin;
+
+impl Plugin for AurasPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ApplyAuraEvent>()
+            .add_event::<DispelEvent>()
+            .add_event::<AuraEvent>()
+            .add_systems(
+                Update,
+                (apply_auras, dispel_auras, tick_auras, update_aura_effects).chain(),
+            );
+    }
+}
+
+fn apply_auras(
+    abilities: Res<Abilities>,
+    mut apply_events: EventReader<ApplyAuraEvent>,
+    mut aura_events: EventWriter<AuraEvent>,
+    mut units: Query<&mut Auras, Without<Dead>>,
+) {
+    for event in apply_events.iter() {
+        let (Some(aura), Ok(mut auras)) =
+            (abilities.aura(&event.aura), units.get_mut(event.target))
+        else {
+            continue;
+        };
+
+        let index = match auras.0.iter().position(|active| active.id == aura.id) {
+            None => {
+                auras.0.push(ActiveAura::new(aura, event.caster));
+                auras.0.len() - 1
+            }
+            Some(index) => {
+                let active = &mut auras.0[index];
+                match aura.stacking {
+                    AuraStacking::Keep => continue,
+                    AuraStacking::Refresh => {}
+                    AuraStacking::Stack => {
+                        active.stacks = (active.stacks + 1).min(aura.max_stacks);
+                    }
+                }
+
+                active.caster = event.caster;
+                active.absorb = shield(aura, active.stacks);
+                active.duration.reset();
+                index
+            }
+        };
+
+        println!(
+            "{:?} applied {} to {:?}",
+            event.caster, aura.name, event.target
+        );
+        aura_events.send(AuraEvent::applied(event.target, aura, &auras.0[index]));
+    }
+}
+
+fn dispel_auras(
+    abilities: Res<Abilities>,
+    mut dispel_events: EventReader<DispelEvent>,
+    mut aura_events: EventWriter<AuraEvent>,
+    mut units: Query<&mut Auras>,
+) {
+    for event in dispel_events.iter() {
+        let Ok(mut auras) = units.get_mut(event.target) else {
+            continue;
+        };
+
+        auras.0.retain(|active| {
+            let dispelled = abilities
+                .aura(&active.id)
+                .is_some_and(|aura| aura.dispel == Some(event.dispel));
+
+            if dispelled {
+                aura_events.send(AuraEvent::Removed {
+                    entity: event.target,
+                    aura: active.id.clone(),
+                });
+            }
+            !dispelled
+        });
+    }
+}
+
+// Damage and heals over time, and removes what ran out
+fn tick_auras(
+    time: Res<Time>,
+    abilities: Res<Abilities>,
+    mut units: Query<(Entity, &mut Auras, &Stats, Option<&Dead>)>,
+    mut damage_events: EventWriter<DoDamageEvent>,
+    mut heal_events: EventWriter<HealEvent>,
+    mut aura_events: EventWriter<AuraEvent>,
+) {
+    for (entity, mut auras, stats, dead) in units.iter_mut() {
+        if auras.0.is_empty() {
+            continue;
+        }
+
+        // only a removed aura changes the effects on the unit
+        let count = auras.0.len();
+
+        auras.bypass_change_detection().0.retain_mut(|active| {
+            let aura = abilities.aura(&active.id);
+            let expired =
+                dead.is_some() || aura.is_none() || active.duration.tick(time.delta()).finished();
+
+            if expired {
+                aura_events.send(AuraEvent::Removed {
+                    entity,
+                    aura: active.id.clone(),
+                });
+                return false;
+            }
+
+            active.tick.tick(time.delta());
+            let aura = aura.unwrap();
+            for _ in 0..active.tick.times_finished_this_tick() {
+                for effect in aura.effects.iter() {
+                    match effect {
+                        // ticks always hit, only armor helps against them
+                        AuraEffect::DamageOverTime(damage) => {
+                            damage_events.send(DoDamageEvent::new(
+                                active.caster,
+                                entity,
+                                stats.mitigate(aura.school, damage * active.stacks as i32),
+                                aura.school,
+                                HitResult::Hit,
+                            ))
+                        }
+                        AuraEffect::HealOverTime(amount) => heal_events.send(HealEvent {
+                            origin: active.caster,
+                            receiver: entity,
+                            amount: amount * active.stacks as i32,
+                        }),
+                        _ => {}
+                    }
+                }
+            }
+
+            true
+        });
+
+        if auras.0.len() != count {
+            auras.set_changed();
+        }
+    }
+}
+
+// Computes speed, attack speed, stuns and roots from the auras on the unit
+fn update_aura_effects(
+    mut commands: Commands,
+    abilities: Res<Abilities>,
+    mut units: Query<
+        (
+            Entity,
+            &Auras,
+            &mut Speed,
+            &mut AttackSpeed,
+            Option<&BaseStats>,
+        ),
+        Changed<Auras>,
+    >,
+) {
+    for (entity, auras, mut speed, mut attack_speed, base) in units.iter_mut() {
+        let base = match base {
+            Some(base) => *base,
+            None => {
+                let base = BaseStats {
+                    speed: speed.0,
+                    attack_speed: attack_speed.0.duration(),
+                };
+                commands.entity(entity).insert(base);
+                base
+            }
+        };
+
+        let mut speed_modifier = 1.;
+        let mut attack_speed_modifier = 1.;
+        let mut stunned = false;
+        let mut rooted = false;
+
+        for active in auras.0.iter() {
+            let Some(aura) = abilities.aura(&active.id) else {
+                continue;
+            };
+
+            for effect in aura.effects.iter() {
+                match effect {
+                    AuraEffect::Speed(modifier) => {
+                        speed_modifier *= modifier.powi(active.stacks as i32);
+                    }
+                    AuraEffect::AttackSpeed(modifier) => {
+                        attack_speed_modifier *= modifier.powi(active.stacks as i32);
+                    }
+                    AuraEffect::Stun => stunned = true,
+                    AuraEffect::Root => rooted = true,
+                    AuraEffect::DamageOverTime(_)
+                    | AuraEffect::HealOverTime(_)
+                    | AuraEffect::Absorb(_) => {}
+                }
+            }
+        }
+
+        // players get their speed with every move ack, so a root stops their prediction too
+        speed.0 = if stunned || rooted {
+            0.
+        } else {
+            base.speed * speed_modifier
+        };
+        attack_speed
+            .0
+            .set_duration(base.attack_speed.mul_f32(attack_speed_modifier.max(0.1)));
+
+        let mut unit = commands.entity(entity);
+        if stunned {
+            unit.insert(Stunned);
+        } else {
+            unit.remove::<Stunned>();
+        }
+        if rooted {
+            unit.insert(Rooted);
+        } else {
+            unit.remove::<Rooted>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+
+    const AURAS: &str = r#"
+        abilities = []
+
+        [[auras]]
+        id = "venom"
+        name = "Venom"
+        duration = 6.0
+        effects = [{ damage_over_time = 1 }]
+        stacking = "stack"
+        max_stacks = 3
+        school = "nature"
+        dispel = "poison"
+
+        [[auras]]
+        id = "curse"
+        name = "Curse"
+        duration = 6.0
+        effects = [{ damage_over_time = 1 }]
+        dispel = "curse"
+
+        [[auras]]
+        id = "shield"
+        name = "Shield"
+        duration = 10.0
+        effects = [{ absorb = 10 }]
+
+        [[auras]]
+        id = "slow"
+        name = "Slow"
+        duration = 2.0
+        effects = [{ speed = 0.5 }, { attack_speed = 2.0 }]
+        dispel = "magic"
+
+        [[auras]]
+        id = "root"
+        name = "Root"
+        duration = 1.0
+        effects = ["root"]
+    "#;
+
+    fn setup() -> (App, Entity) {
+        let mut app = App::new();
+        app.add_plugins(AurasPlugin)
+            .add_event::<DoDamageEvent>()
+            .add_event::<HealEvent>()
+            .insert_resource(Abilities::parse(AURAS).unwrap())
+            .init_resource::<Time>();
+        app.world
+            .resource_mut::<Time>()
+            .update_with_instant(Instant::now());
+
+        let unit = app
+            .world
+            .spawn((
+                Auras::default(),
+                Stats::default(),
+                Speed(2.),
+                AttackSpeed(Timer::from_seconds(1., TimerMode::Repeating)),
+            ))
+            .id();
+
+        (app, unit)
+    }
+
+    fn advance(app: &mut App, seconds: f32) {
+        let mut time = app.world.resource_mut::<Time>();
+        let last = time.last_update().unwrap();
+        time.update_with_instant(last + Duration::from_secs_f32(seconds));
+        app.update();
+    }
+
+    fn apply(app: &mut App, unit: Entity, aura: &str) {
+        app.world.send_event(ApplyAuraEvent {
+            caster: unit,
+            target: unit,
+            aura: aura.to_string(),
+        });
+        advance(app, 0.);
+    }
+
+    fn active<'a>(app: &'a App, unit: Entity, aura: &str) -> Option<&'a ActiveAura> {
+        let auras = app.world.get::<Auras>(unit).unwrap();
+        auras.0.iter().find(|active| active.id == aura)
+    }
+
+    #[test]
+    fn stacks_up_to_max_stacks() {
+        let (mut app, unit) = setup();
+
+        for stacks in [1, 2, 3, 3, 3] {
+            apply(&mut app, unit, "venom");
+            assert_eq!(active(&app, unit, "venom").unwrap().stacks, stacks);
+        }
+    }
+
+    #[test]
+    fn reapplying_refreshes_the_duration() {
+        let (mut app, unit) = setup();
+
+        apply(&mut app, unit, "curse");
+        advance(&mut app, 4.);
+        assert!((active(&app, unit, "curse").unwrap().remaining_secs() - 2.).abs() < 0.01);
+
+        apply(&mut app, unit, "curse");
+        let curse = active(&app, unit, "curse").unwrap();
+        assert_eq!(curse.stacks, 1);
+        assert!((curse.remaining_secs() - 6.).abs() < 0.01);
+
+        advance(&mut app, 6.5);
+        assert!(active(&app, unit, "curse").is_none());
+    }
+
+    #[test]
+    fn ticks_deal_damage_over_time() {
+        let (mut app, unit) = setup();
+
+        apply(&mut app, unit, "venom");
+        apply(&mut app, unit, "venom");
+        advance(&mut app, 1.);
+
+        let events = app.world.resource::<Events<DoDamageEvent>>();
+        let damage: Vec<i32> = events
+            .get_reader()
+            .iter(events)
+            .map(|event| event.damage)
+            .collect();
+        assert_eq!(damage, [2]);
+    }
+
+    #[test]
+    fn shields_take_damage_before_health() {
+        let (mut app, unit) = setup();
+        apply(&mut app, unit, "shield");
+
+        let mut state: SystemState<(Query<&mut Auras>, EventWriter<AuraEvent>)> =
+            SystemState::new(&mut app.world);
+        let (mut auras, mut aura_events) = state.get_mut(&mut app.world);
+        let mut auras = auras.get_mut(unit).unwrap();
+
+        assert_eq!(auras.absorb(unit, 4, &mut aura_events), 4);
+        assert_eq!(auras.0[0].absorb, 6);
+
+        // the rest of the hit goes through once the shield breaks
+        assert_eq!(auras.absorb(unit, 10, &mut aura_events), 6);
+        assert!(auras.0.is_empty());
+        assert_eq!(auras.absorb(unit, 10, &mut aura_events), 0);
+    }
+
+    #[test]
+    fn dispel_only_removes_its_category() {
+        let (mut app, unit) = setup();
+        apply(&mut app, unit, "venom");
+        apply(&mut app, unit, "curse");
+        apply(&mut app, unit, "shield");
+
+        app.world.send_event(DispelEvent {
+            target: unit,
+            dispel: DispelType::Poison,
+        });
+        advance(&mut app, 0.);
+
+        assert!(active(&app, unit, "venom").is_none());
+        assert!(active(&app, unit, "curse").is_some());
+        assert!(active(&app, unit, "shield").is_some());
+    }
+
+    #[test]
+    fn modifiers_end_with_the_aura() {
+        let (mut app, unit) = setup();
+
+        apply(&mut app, unit, "slow");
+        assert_eq!(app.world.get::<Speed>(unit).unwrap().0, 1.);
+        let attack_speed = app.world.get::<AttackSpeed>(unit).unwrap();
+        assert_eq!(attack_speed.0.duration(), Duration::from_secs(2));
+
+        apply(&mut app, unit, "root");
+        assert_eq!(app.world.get::<Speed>(unit).unwrap().0, 0.);
+        assert!(app.world.get::<Rooted>(unit).is_some());
+
+        // the root ran out, the slow is still there
+        advance(&mut app, 1.5);
+        assert_eq!(app.world.get::<Speed>(unit).unwrap().0, 1.);
+        assert!(app.world.get::<Rooted>(unit).is_none());
+
+        advance(&mut app, 1.);
+        assert_eq!(app.world.get::<Speed>(unit).unwrap().0, 2.);
+        let attack_speed = app.world.get::<AttackSpeed>(unit).unwrap();
+        assert_eq!(attack_speed.0.duration(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use tiled_game::network::{
+    messages::server::{CharacterErrorMessage, CharacterSummary, ServerMessages},
+    user_data::is_valid_character_name,
+};
+
+use crate::{
+    config::ServerConfig,
+    game::stats::Stats,
+    network::{AccountId, SendServerMessageEvent},
+    storage::{CharacterRecord, Storage},
+};
+
+use super::player::Player;
+
+// What a connected client wants to do before entering the world
+#[derive(Debug)]
+pub enum CharacterRequest {
+    List,
+    Create { name: String, class: String },
+    Delete { name: String },
+    Select { name: String },
+}
+
+#[derive(Event)]
+pub struct CharacterRequestEvent {
+    pub client_id: u64,
+    pub entity: Entity,
+    pub request: CharacterRequest,
+}
+
+// Set on a connection once a character was picked
+// player_join turns it into a player in the world
+#[derive(Component)]
+pub struct SelectedCharacter(pub CharacterRecord);
+
+pub struct CharacterSelectPlugin;
+
+impl Plugin for CharacterSelectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CharacterRequestEvent>()
+            .add_systems(Update, handle_character_requests);
+    }
+}
+
+fn handle_character_requests(
+    mut commands: Commands,
+    mut requests: EventReader<CharacterRequestEvent>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    config: Res<ServerConfig>,
+    storage: Res<Storage>,
+    accounts: Query<&AccountId, (Without<Player>, Without<SelectedCharacter>)>,
+    players: Query<&Name, With<Player>>,
+    selected_characters: Query<&SelectedCharacter>,
+) {
+    // SelectedCharacter is only inserted once the commands ran,
+    // so selects within this tick have to be remembered here
+    let mut selected_now = HashSet::new();
+
+    for event in requests.iter() {
+        // characters can only be changed from the character select screen
+        let Ok(account_id) = accounts.get(event.entity) else {
+            continue;
+        };
+
+        // in the world, or about to be spawned by player_join
+        let in_use = |name: &String| {
+            selected_now.contains(name)
+                || players.iter().any(|player| player.as_str() == name)
+                || selected_characters
+                    .iter()
+                    .any(|SelectedCharacter(character)| character.name == *name)
+        };
+
+        let result = match &event.request {
+            CharacterRequest::List => Ok(()),
+            CharacterRequest::Create { name, class } => {
+                create_character(&config, &storage, account_id.0, name, class)
+            }
+            CharacterRequest::Delete { name } | CharacterRequest::Select { name }
+                if in_use(name) =>
+            {
+                Err(CharacterErrorMessage::AlreadyInGame)
+            }
+            CharacterRequest::Delete { name } => {
+                match storage.0.delete_character(account_id.0, name) {
+                    Ok(true) => {
+                        println!("Deleted character {:?} of account {}", name, account_id.0);
+                        Ok(())
+                    }
+                    Ok(false) => Err(CharacterErrorMessage::NotFound),
+                    Err(e) => {
+                        println!("Could not delete character {:?}: {}", name, e);
+                        Err(CharacterErrorMessage::StorageFailure)
+                    }
+                }
+            }
+            CharacterRequest::Select { name } => {
+                match storage.0.load_character(account_id.0, name) {
+                    Ok(Some(character)) => {
+                        selected_now.insert(character.name.clone());
+                        commands
+                            .entity(event.entity)
+                            .insert(SelectedCharacter(character));
+                        // player_join answers with the player info
+                        continue;
+                    }
+                    Ok(None) => Err(CharacterErrorMessage::NotFound),
+                    Err(e) => {
+                        println!("Could not load character {:?}: {}", name, e);
+                        Err(CharacterErrorMessage::StorageFailure)
+                    }
+                }
+            }
+        };
+
+        if let Err(error) = result {
+            server_messages.send(SendServerMessageEvent {
+                client_id: Some(event.client_id),
+                message: ServerMessages::CharacterError { error },
+            });
+            continue;
+        }
+
+        // every successful change sends the new list
+        match character_list(&config, &storage, account_id.0) {
+            Ok(message) => server_messages.send(SendServerMessageEvent {
+                client_id: Some(event.client_id),
+                message,
+            }),
+            Err(e) => println!(
+                "Could not list characters of account {}: {}",
+                account_id.0, e
+            ),
+        }
+    }
+}
+
+fn create_character(
+    config: &ServerConfig,
+    storage: &Storage,
+    account_id: u64,
+    name: &str,
+    class: &str,
+) -> Result<(), CharacterErrorMessage> {
+    if !is_valid_character_name(name) {
+        return Err(CharacterErrorMessage::InvalidName);
+    }
+
+    if !config.classes.iter().any(|allowed| allowed == class) {
+        return Err(CharacterErrorMessage::UnknownClass);
+    }
+
+    let existing = storage.0.list_characters(account_id).map_err(|e| {
+        println!("Could not list characters of account {}: {}", account_id, e);
+        CharacterErrorMessage::StorageFailure
+    })?;
+
+    if existing.len() >= config.max_characters {
+        return Err(CharacterErrorMessage::TooManyCharacters);
+    }
+
+    let character = CharacterRecord::new(
+        account_id,
+        name.to_string(),
+        class.to_string(),
+        Stats::default().max_health(),
+        Stats::default().max_mana(),
+    );
+
+    match storage.0.create_character(&character) {
+        Ok(true) => {
+            println!("Created character {:?} for account {}", name, account_id);
+            Ok(())
+        }
+        Ok(false) => Err(CharacterErrorMessage::NameTaken),
+        Err(e) => {
+            println!("Could not create character {:?}: {}", name, e);
+            Err(CharacterErrorMessage::StorageFailure)
+        }
+    }
+}
+
+fn character_list(
+    config: &ServerConfig,
+    storage: &Storage,
+    account_id: u64,
+) -> anyhow::Result<ServerMessages> {
+    let characters = storage
+        .0
+        .list_characters(account_id)?
+        .into_iter()
+        .map(|character| CharacterSummary {
+            name: character.name,
+            class: character.class,
+            map: character.map,
+            level: character.level,
+        })
+        .collect();
+
+    Ok(ServerMessages::CharacterList {
+        characters,
+        classes: config.classes.clone(),
+    })
+}
@@ -0,0 +1,478 @@
+use bevy::prelude::*;
+use tiled_game::network::{
+    chat::{ChatChannel, MAX_CHAT_LENGTH},
+    messages::server::ServerMessages,
+};
+
+use crate::{
+    config::ServerConfig,
+    network::{NetworkClientId, SendServerMessageEvent},
+};
+
+use super::{
+    commands::{reply, AddGameCommand, CommandEvent, GameCommand, Permission},
+    player::Player,
+    unit::UnitsNearby,
+};
+
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        let config = app.world.resource::<ServerConfig>();
+        let filter = ChatFilter(Box::new(WordFilter::new(&config.chat_filter)));
+
+        app.add_event::<ChatEvent>()
+            .insert_resource(filter)
+            .init_resource::<NextPartyId>()
+            .add_game_command::<Emote>()
+            .add_game_command::<Roll>()
+            .add_game_command::<Invite>()
+            .add_game_command::<Accept>()
+            .add_systems(
+                Update,
+                (
+                    (emote_command, roll_command, invite_command, accept_command),
+                    send_chat,
+                )
+                    .chain(),
+            );
+    }
+}
+
+// Players with the same id are in a party together
+#[derive(Component)]
+pub struct Party(pub u64);
+
+#[derive(Resource, Default)]
+struct NextPartyId(u64);
+
+// Set on a player that was invited by another one, until it accepts
+#[derive(Component)]
+pub struct PartyInvite(pub Entity);
+
+// Describes what the player does, "/emote waves" shows "* Name waves"
+pub struct Emote(String);
+
+impl GameCommand for Emote {
+    const NAME: &'static str = "emote";
+    const USAGE: &'static str = "<action>";
+    const PERMISSION: Permission = Permission::Player;
+
+    fn parse(args: &[&str]) -> Option<Self> {
+        (!args.is_empty()).then(|| Emote(args.join(" ")))
+    }
+}
+
+// A random number from 1 to the maximum, 100 if none is given
+pub struct Roll(u32);
+
+impl GameCommand for Roll {
+    const NAME: &'static str = "roll";
+    const USAGE: &'static str = "[max]";
+    const PERMISSION: Permission = Permission::Player;
+
+    fn parse(args: &[&str]) -> Option<Self> {
+        match args {
+            [] => Some(Roll(100)),
+            [max] => max.parse().ok().filter(|max| *max > 0).map(Roll),
+            _ => None,
+        }
+    }
+}
+
+pub struct Invite(String);
+
+impl GameCommand for Invite {
+    const NAME: &'static str = "invite";
+    const USAGE: &'static str = "<name>";
+    const PERMISSION: Permission = Permission::Player;
+
+    fn parse(args: &[&str]) -> Option<Self> {
+        match args {
+            [name] => Some(Invite(name.to_string())),
+            _ => None,
+        }
+    }
+}
+
+// Joins the party of the last player that sent an invite
+pub struct Accept;
+
+impl GameCommand for Accept {
+    const NAME: &'static str = "accept";
+    const USAGE: &'static str = "";
+    const PERMISSION: Permission = Permission::Player;
+
+    fn parse(args: &[&str]) -> Option<Self> {
+        args.is_empty().then_some(Accept)
+    }
+}
+
+// A player wrote something
+#[derive(Event)]
+pub struct ChatEvent {
+    pub entity: Entity,
+    pub channel: ChatChannel,
+    pub message: String,
+}
+
+// Rewrites chat messages before anyone else gets to see them
+pub trait MessageFilter: Send + Sync {
+    fn filter(&self, message: &str) -> String;
+}
+
+#[derive(Resource)]
+pub struct ChatFilter(pub Box<dyn MessageFilter>);
+
+// Replaces every listed word with asterisks, ignoring case
+pub struct WordFilter {
+    words: Vec<String>,
+}
+
+impl WordFilter {
+    pub fn new(words: &[String]) -> Self {
+        Self {
+            words: words.iter().map(|word| word.to_lowercase()).collect(),
+        }
+    }
+
+    fn censor(&self, word: &str, filtered: &mut String) {
+        if self.words.contains(&word.to_lowercase()) {
+            filtered.extend(word.chars().map(|_| '*'));
+        } else {
+            filtered.push_str(word);
+        }
+    }
+}
+
+impl MessageFilter for WordFilter {
+    fn filter(&self, message: &str) -> String {
+        let mut filtered = String::with_capacity(message.len());
+        let mut word = String::new();
+
+        for c in message.chars() {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+
+            self.censor(&word, &mut filtered);
+            word.clear();
+            filtered.push(c);
+        }
+
+        self.censor(&word, &mut filtered);
+        filtered
+    }
+}
+
+// Without the whitespace around it and characters that would mess up the chat window
+fn clean_message(message: &str) -> String {
+    message.trim().chars().filter(|c| !c.is_control()).collect()
+}
+
+// Counted in characters, a message in another script takes more bytes for the same length
+fn is_too_long(message: &str) -> bool {
+    message.chars().count() > MAX_CHAT_LENGTH
+}
+
+fn send_chat(
+    config: Res<ServerConfig>,
+    filter: Res<ChatFilter>,
+    units_nearby: Res<UnitsNearby>,
+    mut chat_events: EventReader<ChatEvent>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    players: Query<
+        (
+            Entity,
+            &Name,
+            &NetworkClientId,
+            &Transform,
+            &Parent,
+            Option<&Party>,
+        ),
+        With<Player>,
+    >,
+) {
+    for event in chat_events.iter() {
+        let Ok((sender, name, client_id, transform, map_instance, party)) =
+            players.get(event.entity)
+        else {
+            continue;
+        };
+
+        let mut reply = |message: &str| {
+            server_messages.send(SendServerMessageEvent {
+                client_id: Some(client_id.0),
+                message: ServerMessages::system_chat(message),
+            });
+        };
+
+        let message = clean_message(&event.message);
+        if message.is_empty() {
+            continue;
+        }
+
+        if is_too_long(&message) {
+            reply("Your message is too long");
+            continue;
+        }
+
+        let same_map = |entity: Entity| {
+            players
+                .get(entity)
+                .is_ok_and(|(.., parent, _)| parent.get() == map_instance.get())
+        };
+
+        let recipients: Vec<Entity> = match &event.channel {
+            ChatChannel::Say | ChatChannel::Emote => {
+                let position = transform.translation.truncate();
+
+                // the spatial index lags behind, the sender always hears itself
+                units_nearby
+                    .within_distance(position, config.say_radius)
+                    .into_iter()
+                    .filter_map(|(_, entity)| entity)
+                    .filter(|entity| *entity != sender && same_map(*entity))
+                    .chain([sender])
+                    .collect()
+            }
+            ChatChannel::Yell => players
+                .iter()
+                .filter(|(.., parent, _)| parent.get() == map_instance.get())
+                .map(|(entity, ..)| entity)
+                .collect(),
+            ChatChannel::Whisper { to } => {
+                let Some((recipient, ..)) = players
+                    .iter()
+                    .find(|(_, name, ..)| name.as_str() == to.as_str())
+                else {
+                    reply(&format!("No player named {} is online", to));
+                    continue;
+                };
+
+                let mut recipients = vec![recipient, sender];
+                recipients.dedup();
+                recipients
+            }
+            ChatChannel::Party => {
+                let Some(party) = party else {
+                    reply("You are not in a party");
+                    continue;
+                };
+
+                players
+                    .iter()
+                    .filter(|(.., member)| member.is_some_and(|member| member.0 == party.0))
+                    .map(|(entity, ..)| entity)
+                    .collect()
+            }
+            ChatChannel::System => {
+                println!("{} tried to chat on the system channel", name);
+                continue;
+            }
+        };
+
+        let message = filter.0.filter(&message);
+        println!("[{:?}] {}: {}", event.channel, name, message);
+
+        for recipient in recipients {
+            let Ok((_, _, client_id, ..)) = players.get(recipient) else {
+                continue;
+            };
+
+            server_messages.send(SendServerMessageEvent {
+                client_id: Some(client_id.0),
+                message: ServerMessages::Chat {
+                    channel: event.channel.clone(),
+                    sender: Some(name.to_string()),
+                    message: message.clone(),
+                },
+            });
+        }
+    }
+}
+
+fn emote_command(
+    mut emote_commands: EventReader<CommandEvent<Emote>>,
+    mut chat_events: EventWriter<ChatEvent>,
+) {
+    for event in emote_commands.iter() {
+        chat_events.send(ChatEvent {
+            entity: event.player,
+            channel: ChatChannel::Emote,
+            message: event.command.0.clone(),
+        });
+    }
+}
+
+fn roll_command(
+    mut roll_commands: EventReader<CommandEvent<Roll>>,
+    mut chat_events: EventWriter<ChatEvent>,
+) {
+    for event in roll_commands.iter() {
+        let max = event.command.0;
+
+        chat_events.send(ChatEvent {
+            entity: event.player,
+            channel: ChatChannel::Emote,
+            message: format!("rolls {} (1-{})", fastrand::u32(1..=max), max),
+        });
+    }
+}
+
+fn invite_command(
+    mut commands: Commands,
+    mut invite_commands: EventReader<CommandEvent<Invite>>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    players: Query<(Entity, &Name, &NetworkClientId, Option<&Party>), With<Player>>,
+) {
+    for event in invite_commands.iter() {
+        let Ok((_, name, client_id, party)) = players.get(event.player) else {
+            continue;
+        };
+
+        let Some((invited, _, invited_client_id, invited_party)) = players
+            .iter()
+            .find(|(_, name, ..)| name.as_str() == event.command.0)
+        else {
+            server_messages.send(reply(
+                client_id,
+                format!("No player named {} is online", event.command.0),
+            ));
+            continue;
+        };
+
+        let same_party = matches!((party, invited_party), (Some(a), Some(b)) if a.0 == b.0);
+        if invited == event.player || same_party {
+            server_messages.send(reply(
+                client_id,
+                format!("{} is already in your party", event.command.0),
+            ));
+            continue;
+        }
+
+        commands.entity(invited).insert(PartyInvite(event.player));
+        server_messages.send(reply(
+            client_id,
+            format!("You invited {} to your party", event.command.0),
+        ));
+        server_messages.send(reply(
+            invited_client_id,
+            format!("{} invites you to a party, /accept to join", name),
+        ));
+    }
+}
+
+fn accept_command(
+    mut commands: Commands,
+    mut next_party_id: ResMut<NextPartyId>,
+    mut accept_commands: EventReader<CommandEvent<Accept>>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    players: Query<
+        (
+            &Name,
+            &NetworkClientId,
+            Option<&Party>,
+            Option<&PartyInvite>,
+        ),
+        With<Player>,
+    >,
+) {
+    for event in accept_commands.iter() {
+        let Ok((name, client_id, _, invite)) = players.get(event.player) else {
+            continue;
+        };
+
+        commands.entity(event.player).remove::<PartyInvite>();
+
+        // the player that invited might have logged out since
+        let Some((inviter, (inviter_name, inviter_client_id, inviter_party, _))) =
+            invite.and_then(|invite| {
+                players
+                    .get(invite.0)
+                    .ok()
+                    .map(|inviter| (invite.0, inviter))
+            })
+        else {
+            server_messages.send(reply(client_id, "You have no party invite"));
+            continue;
+        };
+
+        // the first member to accept founds the party
+        let party = match inviter_party {
+            Some(party) => party.0,
+            None => {
+                next_party_id.0 += 1;
+                commands.entity(inviter).insert(Party(next_party_id.0));
+                next_party_id.0
+            }
+        };
+
+        commands.entity(event.player).insert(Party(party));
+        server_messages.send(reply(
+            client_id,
+            format!("You joined the party of {}", inviter_name),
+        ));
+        server_messages.send(reply(
+            inviter_client_id,
+            format!("{} joined your party", name),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(message: &str) -> String {
+        WordFilter::new(&["darn".to_string(), "HECK".to_string()]).filter(message)
+    }
+
+    #[test]
+    fn roll_takes_an_optional_maximum() {
+        assert_eq!(Roll::parse(&[]).map(|roll| roll.0), Some(100));
+        assert_eq!(Roll::parse(&["6"]).map(|roll| roll.0), Some(6));
+
+        for args in [&["0"][..], &["-1"], &["six"], &["6", "6"]] {
+            assert!(Roll::parse(args).is_none(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn filtered_words_ignore_case() {
+        assert_eq!(filter("darn it"), "**** it");
+        assert_eq!(filter("DaRn it"), "**** it");
+        assert_eq!(filter("what the heck"), "what the ****");
+    }
+
+    #[test]
+    fn punctuation_ends_a_word() {
+        assert_eq!(filter("darn!"), "****!");
+        assert_eq!(filter("(heck),darn."), "(****),****.");
+        assert_eq!(filter("darn's"), "****'s");
+        assert_eq!(filter("darnit heckle"), "darnit heckle");
+    }
+
+    #[test]
+    fn the_last_word_is_filtered_too() {
+        assert_eq!(filter("darn"), "****");
+        assert_eq!(filter("oh darn"), "oh ****");
+        assert_eq!(filter(""), "");
+    }
+
+    #[test]
+    fn messages_are_trimmed_of_control_characters() {
+        assert_eq!(clean_message("  hi\tthere\n "), "hithere");
+        assert_eq!(clean_message("\u{7}\r\n"), "");
+    }
+
+    #[test]
+    fn the_length_limit_counts_characters() {
+        let limit = "ü".repeat(MAX_CHAT_LENGTH);
+        assert!(limit.len() > MAX_CHAT_LENGTH);
+        assert!(!is_too_long(&limit));
+        assert!(is_too_long(&(limit + "a")));
+    }
+}
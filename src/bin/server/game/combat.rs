@@ -5,37 +5,72 @@
  */
 use bevy::{prelude::*, time::Time};
 
-use tiled_game::components::*;
+use tiled_game::{
+    combat::{DamageSchool, HitResult},
+    components::*,
+};
 
 use super::{
+    auras::{AuraEvent, Auras, Stunned},
     npc::{Evading, Home, NPC},
+    stats::{roll_auto_attack, Stats},
     unit::AttackSpeed,
 };
 
 const COMBAT_RANGE: f32 = 20.0;
 
+// Rolled and mitigated by the attacker, a miss or dodge does 0 damage
 #[derive(Event)]
 pub struct DoDamageEvent {
     pub origin: Entity,
     pub receiver: Entity,
     pub damage: i32,
+    pub school: DamageSchool,
+    pub result: HitResult,
 }
 
 impl DoDamageEvent {
-    pub fn new(origin: Entity, receiver: Entity, damage: i32) -> Self {
+    pub fn new(
+        origin: Entity,
+        receiver: Entity,
+        damage: i32,
+        school: DamageSchool,
+        result: HitResult,
+    ) -> Self {
         Self {
             origin,
             receiver,
             damage,
+            school,
+            result,
         }
     }
 }
 
+// What the receiver actually took after shields, everyone who sees it is told
+#[derive(Event)]
+pub struct DamageTakenEvent {
+    pub origin: Entity,
+    pub receiver: Entity,
+    pub damage: i32,
+    pub school: DamageSchool,
+    pub result: HitResult,
+}
+
+#[derive(Event)]
+pub struct HealEvent {
+    pub origin: Entity,
+    pub receiver: Entity,
+    pub amount: i32,
+}
+
 pub struct CombatPlugin;
 
 impl Plugin for CombatPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<DoDamageEvent>()
+            .add_event::<DamageTakenEvent>()
+            .add_event::<HealEvent>()
             .add_event::<LeaveCombatEvent>()
             .add_systems(
                 Update,
@@ -46,7 +81,12 @@ impl Plugin for CombatPlugin {
                     auto_attack_system,
                 ),
             )
-            .add_systems(Update, do_damage_system.after(auto_attack_system))
+            .add_systems(
+                Update,
+                (do_damage_system, heal_system)
+                    .chain()
+                    .after(auto_attack_system),
+            )
             .add_systems(PostUpdate, leave_combat);
     }
 }
@@ -56,20 +96,20 @@ impl Plugin for CombatPlugin {
 fn auto_attack_system(
     // Everyone in combat with a target that is not dead
     mut attackers: Query<
-        (Entity, &Target, &Transform, &mut AttackSpeed),
-        (With<InCombat>, Without<Dead>),
+        (Entity, &Target, &Transform, &Stats, &mut AttackSpeed),
+        (With<InCombat>, Without<Dead>, Without<Stunned>),
     >,
 
     // query for every unit so that we can query attackers target
-    targets: Query<(Entity, &Transform), (With<Unit>, Without<Dead>)>,
+    targets: Query<(Entity, &Transform, &Stats), (With<Unit>, Without<Dead>)>,
 
     mut damage_event: EventWriter<DoDamageEvent>,
     time: Res<Time>,
 ) {
-    for (attacker, target, position, mut attack_speed) in attackers.iter_mut() {
+    for (attacker, target, position, stats, mut attack_speed) in attackers.iter_mut() {
         let target = targets.get(target.0).ok();
 
-        if let Some((enemy, t_position)) = target {
+        if let Some((enemy, t_position, target_stats)) = target {
             if !attack_speed.0.tick(time.delta()).just_finished() {
                 continue;
             }
@@ -81,37 +121,80 @@ fn auto_attack_system(
                 continue;
             }
 
-            let damage = 1;
+            let (damage, result) = roll_auto_attack(stats, target_stats);
 
-            damage_event.send(DoDamageEvent::new(attacker, enemy, damage));
+            damage_event.send(DoDamageEvent::new(
+                attacker,
+                enemy,
+                damage,
+                DamageSchool::Physical,
+                result,
+            ));
         }
     }
 }
 
 fn do_damage_system(
-    mut cmd: Commands,
     mut damage_events: EventReader<DoDamageEvent>,
+    mut damage_taken: EventWriter<DamageTakenEvent>,
+    mut aura_events: EventWriter<AuraEvent>,
 
-    targets: Query<(&Health, Option<&Evading>)>,
+    mut targets: Query<(&mut Health, &mut Auras, Option<&Evading>)>,
 ) {
     for evt in damage_events.iter() {
-        let target_entity = targets.get(evt.receiver).ok();
-        if let Some((health, evading)) = target_entity {
+        let target_entity = targets.get_mut(evt.receiver).ok();
+        if let Some((mut health, mut auras, evading)) = target_entity {
             if evading.is_some() {
                 // send message to player that creature is evading
                 // and is immune to all damage
                 continue;
             }
 
-            let mut target = cmd.entity(evt.receiver);
+            let absorbed = auras.absorb(evt.receiver, evt.damage, &mut aura_events);
+            let damage = evt.damage - absorbed;
+            let result = match evt.result {
+                HitResult::Hit | HitResult::Crit if damage == 0 && absorbed > 0 => {
+                    HitResult::Absorb
+                }
+                result => result,
+            };
+
+            println!(
+                "{:?} took {} damage ({:?}, {} absorbed)",
+                evt.receiver, damage, result, absorbed
+            );
 
-            println!("{:?} took {} damage", evt.receiver, evt.damage);
+            // several hits in one frame all count
+            health.0 -= damage;
 
-            target.insert(Health(health.0 - evt.damage));
+            damage_taken.send(DamageTakenEvent {
+                origin: evt.origin,
+                receiver: evt.receiver,
+                damage,
+                school: evt.school,
+                result,
+            });
         }
     }
 }
 
+// Heals never raise health above the maximum, the dead stay dead
+fn heal_system(
+    mut heal_events: EventReader<HealEvent>,
+    mut targets: Query<(&mut Health, &MaxHealth), Without<Dead>>,
+) {
+    for evt in heal_events.iter() {
+        let Ok((mut health, max_health)) = targets.get_mut(evt.receiver) else {
+            continue;
+        };
+
+        let healed = evt.amount.min(max_health.0 - health.0).max(0);
+        println!("{:?} healed {:?} for {}", evt.origin, evt.receiver, healed);
+
+        health.0 += healed;
+    }
+}
+
 #[derive(Event)]
 pub struct LeaveCombatEvent {
     pub entity: Entity,
@@ -0,0 +1,312 @@
+/**
+ * Slash commands players type into the chat
+ *
+ * Game modules register their commands on the app and handle them as events,
+ * the network layer only passes the text along. Commands above the permission
+ * of a player look to it as if they didn't exist.
+ */
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use tiled_game::network::messages::server::ServerMessages;
+
+use crate::{
+    config::ServerConfig,
+    network::{AccountId, NetworkClientId, SendServerMessageEvent},
+};
+
+use super::player::Player;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    Player,
+    // Accounts listed in the admins setting
+    Admin,
+}
+
+pub trait GameCommand: Send + Sync + Sized + 'static {
+    const NAME: &'static str;
+    // Arguments, shown when the command can't be parsed
+    const USAGE: &'static str;
+    const PERMISSION: Permission;
+
+    fn parse(args: &[&str]) -> Option<Self>;
+}
+
+// A player ran a command it is allowed to use
+#[derive(Event)]
+pub struct CommandEvent<C: GameCommand> {
+    pub player: Entity,
+    pub command: C,
+}
+
+// Text a client sent after the "/"
+#[derive(Event)]
+pub struct CommandTextEvent {
+    pub entity: Entity,
+    pub text: String,
+}
+
+struct RegisteredCommand {
+    usage: &'static str,
+    permission: Permission,
+    // Parses the arguments and sends the event, false if they don't fit
+    dispatch: fn(&mut Commands, Entity, &[&str]) -> bool,
+}
+
+#[derive(Resource, Default)]
+pub struct CommandRegistry {
+    commands: BTreeMap<&'static str, RegisteredCommand>,
+}
+
+pub trait AddGameCommand {
+    fn add_game_command<C: GameCommand>(&mut self) -> &mut Self;
+}
+
+impl AddGameCommand for App {
+    fn add_game_command<C: GameCommand>(&mut self) -> &mut Self {
+        self.add_event::<CommandEvent<C>>();
+        self.world
+            .get_resource_or_insert_with(CommandRegistry::default)
+            .commands
+            .insert(
+                C::NAME,
+                RegisteredCommand {
+                    usage: C::USAGE,
+                    permission: C::PERMISSION,
+                    dispatch: dispatch::<C>,
+                },
+            );
+        self
+    }
+}
+
+fn dispatch<C: GameCommand>(commands: &mut Commands, player: Entity, args: &[&str]) -> bool {
+    let Some(command) = C::parse(args) else {
+        return false;
+    };
+
+    commands.add(move |world: &mut World| {
+        world
+            .resource_mut::<Events<CommandEvent<C>>>()
+            .send(CommandEvent { player, command });
+    });
+    true
+}
+
+// Answer to the player that ran a command
+pub fn reply(client_id: &NetworkClientId, message: impl Into<String>) -> SendServerMessageEvent {
+    SendServerMessageEvent {
+        client_id: Some(client_id.0),
+        message: ServerMessages::system_chat(message),
+    }
+}
+
+pub struct CommandsPlugin;
+
+impl Plugin for CommandsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CommandRegistry>()
+            .add_event::<CommandTextEvent>()
+            .add_systems(Update, run_commands);
+    }
+}
+
+fn run_commands(
+    mut commands: Commands,
+    config: Res<ServerConfig>,
+    registry: Res<CommandRegistry>,
+    mut command_texts: EventReader<CommandTextEvent>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    players: Query<(&NetworkClientId, &AccountId), With<Player>>,
+) {
+    for event in command_texts.iter() {
+        let Ok((client_id, account_id)) = players.get(event.entity) else {
+            continue;
+        };
+
+        let permission = if config.admins.contains(&account_id.0) {
+            Permission::Admin
+        } else {
+            Permission::Player
+        };
+
+        let mut args = event.text.split_whitespace();
+        let Some(name) = args.next() else {
+            continue;
+        };
+        let name = name.to_lowercase();
+        let args: Vec<&str> = args.collect();
+
+        if name == "help" {
+            let available: Vec<String> = registry
+                .commands
+                .iter()
+                .filter(|(_, command)| command.permission <= permission)
+                .map(|(name, command)| format!("/{} {}", name, command.usage))
+                .collect();
+
+            server_messages.send(reply(client_id, available.join("\n")));
+            continue;
+        }
+
+        let Some(command) = registry
+            .commands
+            .get(name.as_str())
+            .filter(|command| command.permission <= permission)
+        else {
+            server_messages.send(reply(
+                client_id,
+                format!("Unknown command /{}, try /help", name),
+            ));
+            continue;
+        };
+
+        println!("Player {:?} runs /{}", event.entity, event.text);
+
+        if !(command.dispatch)(&mut commands, event.entity, &args) {
+            server_messages.send(reply(
+                client_id,
+                format!("Usage: /{} {}", name, command.usage),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tiled_game::network::chat::ChatChannel;
+
+    use super::*;
+
+    const ADMIN: u64 = 1;
+    const PLAYER: u64 = 2;
+
+    struct Echo(String);
+
+    impl GameCommand for Echo {
+        const NAME: &'static str = "echo";
+        const USAGE: &'static str = "<word>";
+        const PERMISSION: Permission = Permission::Player;
+
+        fn parse(args: &[&str]) -> Option<Self> {
+            match args {
+                [word] => Some(Echo(word.to_string())),
+                _ => None,
+            }
+        }
+    }
+
+    struct Shutdown;
+
+    impl GameCommand for Shutdown {
+        const NAME: &'static str = "shutdown";
+        const USAGE: &'static str = "";
+        const PERMISSION: Permission = Permission::Admin;
+
+        fn parse(args: &[&str]) -> Option<Self> {
+            args.is_empty().then_some(Shutdown)
+        }
+    }
+
+    fn setup() -> App {
+        let mut app = App::new();
+        app.insert_resource(ServerConfig {
+            admins: vec![ADMIN],
+            ..Default::default()
+        })
+        .add_event::<SendServerMessageEvent>()
+        .add_plugins(CommandsPlugin)
+        .add_game_command::<Echo>()
+        .add_game_command::<Shutdown>();
+        app
+    }
+
+    // Runs the text as a command of the account and returns what it was told
+    fn run(app: &mut App, account: u64, text: &str) -> Vec<String> {
+        app.world
+            .resource_mut::<Events<SendServerMessageEvent>>()
+            .clear();
+
+        let entity = app
+            .world
+            .spawn((Player, NetworkClientId(account), AccountId(account)))
+            .id();
+        app.world.send_event(CommandTextEvent {
+            entity,
+            text: text.to_string(),
+        });
+        app.update();
+
+        let events = app.world.resource::<Events<SendServerMessageEvent>>();
+        events
+            .get_reader()
+            .iter(events)
+            .map(|event| match &event.message {
+                ServerMessages::Chat {
+                    channel: ChatChannel::System,
+                    message,
+                    ..
+                } if event.client_id == Some(account) => message.clone(),
+                _ => panic!("unexpected message"),
+            })
+            .collect()
+    }
+
+    fn ran<C: GameCommand>(app: &App) -> usize {
+        app.world.resource::<Events<CommandEvent<C>>>().len()
+    }
+
+    #[test]
+    fn commands_are_dispatched_with_their_arguments() {
+        let mut app = setup();
+
+        assert!(run(&mut app, PLAYER, "ECHO hello").is_empty());
+
+        let events = app.world.resource::<Events<CommandEvent<Echo>>>();
+        let echoed: Vec<String> = events
+            .get_reader()
+            .iter(events)
+            .map(|event| event.command.0.clone())
+            .collect();
+        assert_eq!(echoed, ["hello"]);
+    }
+
+    #[test]
+    fn admin_commands_look_unknown_to_players() {
+        let mut app = setup();
+
+        assert_eq!(
+            run(&mut app, PLAYER, "shutdown"),
+            ["Unknown command /shutdown, try /help"]
+        );
+        assert_eq!(
+            run(&mut app, PLAYER, "nonsense"),
+            ["Unknown command /nonsense, try /help"]
+        );
+        assert_eq!(ran::<Shutdown>(&app), 0);
+
+        assert!(run(&mut app, ADMIN, "shutdown").is_empty());
+        assert_eq!(ran::<Shutdown>(&app), 1);
+    }
+
+    #[test]
+    fn help_lists_the_commands_the_player_may_use() {
+        let mut app = setup();
+
+        assert_eq!(run(&mut app, PLAYER, "help"), ["/echo <word>"]);
+
+        let help = run(&mut app, ADMIN, "help");
+        let lines: Vec<&str> = help[0].lines().map(str::trim_end).collect();
+        assert_eq!(lines, ["/echo <word>", "/shutdown"]);
+    }
+
+    #[test]
+    fn arguments_that_dont_fit_get_the_usage() {
+        let mut app = setup();
+
+        assert_eq!(run(&mut app, PLAYER, "echo"), ["Usage: /echo <word>"]);
+        assert_eq!(run(&mut app, PLAYER, "echo a b"), ["Usage: /echo <word>"]);
+        assert_eq!(ran::<Echo>(&app), 0);
+    }
+}
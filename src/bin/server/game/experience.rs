@@ -0,0 +1,179 @@
+/**
+ * Experience for killing creatures and the levels it buys
+ *
+ * Everyone a creature had threat on when it died shares in the kill,
+ * creatures far below a player are worth nothing to them.
+ */
+use bevy::prelude::*;
+use tiled_game::{components::*, network::messages::server::ServerMessages};
+
+use crate::network::{NetworkClientId, SendServerMessageEvent};
+
+use super::{
+    npc::NPC,
+    player::Player,
+    stats::Stats,
+    unit::{death_system, DeathEvent},
+};
+
+pub const MAX_LEVEL: u32 = 20;
+
+// Levels below the player at which creatures stop giving experience
+const GRAY_LEVELS: i32 = 5;
+
+// Experience a player has towards the next level
+#[derive(Component)]
+pub struct Experience(pub u32);
+
+// Experience needed to get from the level to the next
+pub fn experience_to_level(level: u32) -> u32 {
+    level.saturating_mul(100)
+}
+
+// Higher creatures give a bit more, lower ones less down to nothing
+pub fn experience_reward(creature_level: u32, player_level: u32) -> u32 {
+    let base = creature_level.saturating_mul(5).saturating_add(20);
+    let difference = creature_level as i64 - player_level as i64;
+
+    let factor = if difference >= 0 {
+        1. + 0.1 * difference.min(GRAY_LEVELS as i64) as f32
+    } else {
+        (1. + difference as f32 / GRAY_LEVELS as f32).max(0.)
+    };
+
+    (base as f32 * factor).round() as u32
+}
+
+// Level and experience towards the next one after gaining some
+// What is left over after a level up counts towards the next, at the last level there is none
+pub fn add_experience(mut level: u32, experience: u32, gained: u32) -> (u32, u32) {
+    let mut experience = experience.saturating_add(gained);
+
+    while level < MAX_LEVEL && experience >= experience_to_level(level) {
+        experience -= experience_to_level(level);
+        level += 1;
+    }
+
+    if level >= MAX_LEVEL {
+        (MAX_LEVEL, 0)
+    } else {
+        (level, experience)
+    }
+}
+
+// A unit reached a new level, everyone who sees it is told
+#[derive(Event)]
+pub struct LevelUpEvent {
+    pub entity: Entity,
+}
+
+pub struct ExperiencePlugin;
+
+impl Plugin for ExperiencePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<LevelUpEvent>()
+            // the threat of a creature is gone once it left combat after dying
+            .add_systems(Update, grant_experience.after(death_system));
+    }
+}
+
+fn grant_experience(
+    mut death_events: EventReader<DeathEvent>,
+    mut level_ups: EventWriter<LevelUpEvent>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    creatures: Query<(&Level, &Threat), With<NPC>>,
+    mut players: Query<
+        (
+            &mut Level,
+            &mut Experience,
+            &mut Stats,
+            &mut Health,
+            &mut Mana,
+            &NetworkClientId,
+        ),
+        (With<Player>, Without<NPC>, Without<Dead>),
+    >,
+) {
+    for death_event in death_events.iter() {
+        let Ok((creature_level, threat)) = creatures.get(death_event.entity) else {
+            continue;
+        };
+
+        for attacker in threat.0.keys() {
+            let Ok((mut level, mut experience, mut stats, mut health, mut mana, client_id)) =
+                players.get_mut(*attacker)
+            else {
+                continue;
+            };
+
+            let gained = experience_reward(creature_level.0, level.0);
+            if gained == 0 || level.0 >= MAX_LEVEL {
+                continue;
+            }
+
+            let previous_level = level.0;
+            (level.0, experience.0) = add_experience(level.0, experience.0, gained);
+
+            if level.0 > previous_level {
+                println!("{:?} reached level {}", attacker, level.0);
+
+                // the pools follow the stats, vitals are filled up right away
+                *stats = Stats::player(level.0);
+                health.0 = stats.max_health();
+                mana.0 = stats.max_mana();
+
+                level_ups.send(LevelUpEvent { entity: *attacker });
+            }
+
+            server_messages.send(SendServerMessageEvent {
+                client_id: Some(client_id.0),
+                message: ServerMessages::Experience {
+                    gained,
+                    experience: experience.0,
+                    next_level: experience_to_level(level.0),
+                },
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creatures_far_below_give_nothing() {
+        assert!(experience_reward(10, 10) > 0);
+        assert!(experience_reward(6, 10) > 0);
+        assert_eq!(experience_reward(5, 10), 0);
+        assert_eq!(experience_reward(1, 10), 0);
+        assert_eq!(experience_reward(1, u32::MAX), 0);
+    }
+
+    #[test]
+    fn higher_creatures_give_more() {
+        assert_eq!(experience_reward(10, 10), 70);
+        assert_eq!(experience_reward(12, 10), 96);
+        assert!(experience_reward(9, 10) < experience_reward(10, 10));
+
+        // the bonus stops growing at 50%, 20 levels above still isn't triple
+        assert_eq!(experience_reward(30, 10), 255);
+        assert_eq!(experience_reward(u32::MAX, 1), u32::MAX);
+    }
+
+    #[test]
+    fn leftover_experience_carries_over() {
+        assert_eq!(add_experience(1, 0, 50), (1, 50));
+        assert_eq!(add_experience(1, 50, 50), (2, 0));
+
+        // 100 to reach level 2, 200 to reach 3, 300 to reach 4
+        assert_eq!(add_experience(1, 90, 560), (4, 50));
+    }
+
+    #[test]
+    fn experience_stops_at_max_level() {
+        assert_eq!(add_experience(MAX_LEVEL - 1, 0, u32::MAX), (MAX_LEVEL, 0));
+        assert_eq!(add_experience(MAX_LEVEL, 0, 500), (MAX_LEVEL, 0));
+        assert_eq!(add_experience(1, u32::MAX, u32::MAX), (MAX_LEVEL, 0));
+    }
+}
@@ -9,17 +9,25 @@ use bevy::{
     time::{Time, Timer, TimerMode},
 };
 use tiled::{Loader, Map as TiledMap};
-use tiled_game::{components::Interactable, network::messages::server::ServerMessages};
+use tiled_game::{
+    collision::CollisionMap, components::Interactable, network::messages::server::ServerMessages,
+};
 
 use crate::{
+    config::ServerConfig,
     game::{
         interactions::Portal,
-        npc::{Enemy, NPCBundle},
+        npc::{Enemy, NPCBundle, MAX_CREATURE_LEVEL},
     },
-    network::{NetworkClientId, SendServerMessageEvent},
+    network::{net_ids::NetIds, AccountId, NetworkClientId, SendServerMessageEvent},
+    storage::Storage,
 };
 
-use super::{player::Player, scripts::handle_add_script};
+use super::{
+    commands::{reply, AddGameCommand, CommandEvent, GameCommand, Permission},
+    player::{character_record, save_character, OnlinePlayer, Player},
+    scripts::handle_add_script,
+};
 
 #[derive(Component, Debug)]
 pub struct MapName(pub String);
@@ -27,6 +35,11 @@ pub struct MapName(pub String);
 #[derive(Component)]
 pub struct MapInstance;
 
+// Set on players after a map change until their client reports
+// that the map is loaded. Other players and NPCs ignore them meanwhile.
+#[derive(Component)]
+pub struct LoadingMap;
+
 #[derive(Component)]
 pub struct GlobalMap;
 
@@ -48,9 +61,34 @@ pub struct Teleport {
     pub position: Transform,
 }
 
+// Moves the admin to a position on a map
+pub struct TeleportCommand {
+    map: String,
+    position: Vec2,
+}
+
+impl GameCommand for TeleportCommand {
+    const NAME: &'static str = "teleport";
+    const USAGE: &'static str = "<map> <x> <y>";
+    const PERMISSION: Permission = Permission::Admin;
+
+    fn parse(args: &[&str]) -> Option<Self> {
+        let [map, x, y] = args else {
+            return None;
+        };
+
+        Some(TeleportCommand {
+            map: map.to_string(),
+            position: Vec2::new(x.parse().ok()?, y.parse().ok()?),
+        })
+    }
+}
+
 #[derive(Resource, Default)]
 pub struct MapManager {
     pub atlas: HashMap<String, TiledMap>,
+    // Blocking areas of each map in atlas
+    pub collision: HashMap<String, CollisionMap>,
     pub instances: Vec<Entity>,
     pub global: HashMap<String, Entity>,
     pub cleanup_timer: Timer,
@@ -73,14 +111,15 @@ impl Plugin for MapsPlugin {
             // Handle despawn of entities
             .add_event::<DespawnEvent>()
             .add_event::<Teleport>()
+            .add_game_command::<TeleportCommand>()
             // removes map instances with no players in certain intervals
             .add_systems(
                 Update,
                 (
                     map_instance_cleanup,
                     handle_teleport,
-                    send_map_instance_entities,
                     spawn_units,
+                    teleport_command,
                 ),
             );
 
@@ -91,12 +130,12 @@ impl Plugin for MapsPlugin {
     }
 }
 
-fn setup_map_manager(mut commands: Commands) {
+fn setup_map_manager(mut commands: Commands, config: Res<ServerConfig>) {
     let mut map_manager = MapManager::new();
 
-    let dir = "maps";
+    let dir = &config.maps_dir;
     let maps: Vec<String> = fs::read_dir(dir)
-        .unwrap()
+        .unwrap_or_else(|_| panic!("Could not read maps directory {:?}", dir))
         .filter(|f| {
             f.as_ref()
                 .expect("Could not read file")
@@ -113,7 +152,7 @@ fn setup_map_manager(mut commands: Commands) {
     let mut maps_collection = HashMap::new();
     for name in maps.iter() {
         let tilemap_data = loader
-            .load_tmx_map(format!("{}/{}", dir, name))
+            .load_tmx_map(dir.join(name))
             .expect("Could not load map");
         maps_collection.insert(name.clone(), tilemap_data);
     }
@@ -139,6 +178,10 @@ fn setup_map_manager(mut commands: Commands) {
         })
         .collect();
 
+    map_manager.collision = maps_collection
+        .iter()
+        .map(|(name, map)| (name.clone(), CollisionMap::from_tiled(map)))
+        .collect();
     map_manager.atlas = maps_collection;
     map_manager.global = global;
     // Insert maps as resource
@@ -195,6 +238,9 @@ fn handle_teleport(
     mut despawn_event: EventWriter<DespawnEvent>,
     mut teleport_events: EventReader<Teleport>,
     players: Query<(Entity, &NetworkClientId)>,
+    characters: Query<OnlinePlayer, With<Player>>,
+    map_names: Query<&MapName>,
+    storage: Res<Storage>,
 ) {
     for teleport in teleport_events.iter() {
         println!(
@@ -216,7 +262,12 @@ fn handle_teleport(
         // Check if map instance exists
         if let Some(dest_instance) = teleport.map_instance {
             for instance_entity in map_manager.instances.iter() {
-                if *instance_entity == dest_instance {
+                // an instance of another map would put the player into the wrong world
+                let same_map = map_names
+                    .get(*instance_entity)
+                    .is_ok_and(|map_name| map_name.0 == teleport.map);
+
+                if *instance_entity == dest_instance && same_map {
                     // found map instance
                     // todo decrement population of previous map instance
                     // if let Some(prev_instance) = map_change_request.prev_map_instance {
@@ -272,14 +323,24 @@ fn handle_teleport(
             });
         };
 
+        let map_instance = map_instance.unwrap();
+
         commands
-            .entity(map_instance.unwrap())
+            .entity(map_instance)
             .push_children(&[teleporting_entity.0]);
 
+        // Persist the map change right away, a player that just joined has nothing new to save
+        if let Ok(character) = characters.get(teleporting_entity.0) {
+            save_character(
+                &storage,
+                &character_record(character, &teleport.map, teleport.position.translation),
+            );
+        }
+
         // Add player to map instance
         commands
             .entity(teleporting_entity.0)
-            .insert(teleport.position);
+            .insert((teleport.position, LoadingMap));
 
         // send map change event to client
         // so it can load the new map
@@ -293,11 +354,62 @@ fn handle_teleport(
     }
 }
 
+// /teleport moves the admin who used it to a position on any map
+fn teleport_command(
+    map_manager: Res<MapManager>,
+    mut teleport_commands: EventReader<CommandEvent<TeleportCommand>>,
+    mut teleports: EventWriter<Teleport>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    players: Query<(&NetworkClientId, &Parent), With<Player>>,
+) {
+    for event in teleport_commands.iter() {
+        let Ok((client_id, map_instance)) = players.get(event.player) else {
+            continue;
+        };
+
+        let TeleportCommand { map, position } = &event.command;
+        if !map_manager.atlas.contains_key(map) {
+            server_messages.send(reply(client_id, format!("Unknown map {}", map)));
+            continue;
+        }
+
+        teleports.send(Teleport {
+            entity: event.player,
+            map: map.clone(),
+            map_instance: None,
+            prev_map_instance: Some(map_instance.get()),
+            position: Transform::from_translation(position.extend(0.)),
+        });
+    }
+}
+
+/**
+ * Level of a unit from the properties of its point object
+ * Either a fixed `level` or a random one between `level_min` and `level_max`,
+ * never above MAX_CREATURE_LEVEL
+ */
+fn spawn_level(properties: &tiled::Properties) -> u32 {
+    let level = |name: &str| match properties.get(name) {
+        Some(tiled::PropertyValue::IntValue(level)) if *level > 0 => Some(*level as u32),
+        _ => None,
+    };
+
+    let level = match (level("level"), level("level_min"), level("level_max")) {
+        (Some(level), _, _) => level,
+        (None, Some(min), Some(max)) if min <= max => fastrand::u32(min..=max),
+        (None, Some(level), None) | (None, None, Some(level)) => level,
+        _ => 1,
+    };
+
+    level.min(MAX_CREATURE_LEVEL)
+}
+
 // Spawn units for each newly created map instance
 fn spawn_units(
     mut commands: Commands,
     query: Query<(Entity, &MapName), Added<MapInstance>>,
     map_manager: Res<MapManager>,
+    mut net_ids: ResMut<NetIds>,
 ) {
     for (map_instance_entity, map_name) in query.iter() {
         if let Some(map) = map_manager.atlas.get(&map_name.0) {
@@ -325,7 +437,12 @@ fn spawn_units(
                     );
 
                     cmd.insert((
-                        NPCBundle::new(obj.name.to_owned(), obj.user_type.clone(), spawn_point),
+                        NPCBundle::new(
+                            obj.name.to_owned(),
+                            obj.user_type.clone(),
+                            spawn_point,
+                            spawn_level(&obj.properties),
+                        ),
                         MapInstanceEntity(map_instance_entity),
                     ));
 
@@ -402,6 +519,7 @@ fn spawn_units(
                     }
 
                     let id = cmd.id();
+                    cmd.insert(net_ids.allocate(id));
 
                     println!(
                         "Spawning unit {:?} ({:?}) Server ID: {:?}",
@@ -414,35 +532,6 @@ fn spawn_units(
     }
 }
 
-// Sends a list of entities to the client
-// when they spawn in a new map instance
-fn send_map_instance_entities(
-    mut server_messages: EventWriter<SendServerMessageEvent>,
-    players_spawning_in: Query<
-        (Entity, &NetworkClientId, &Parent),
-        (With<Player>, Or<(Changed<Parent>, Added<Parent>)>),
-    >,
-    map_instances: Query<&Children, With<MapInstance>>,
-) {
-    for (player_entity, client_id, current_map_instance) in players_spawning_in.iter() {
-        let npc = map_instances.get(current_map_instance.get()).ok();
-
-        if let Some(npc_list) = npc {
-            println!("Sending entity list to client {:?}", client_id.0);
-            server_messages.send(SendServerMessageEvent {
-                client_id: Some(client_id.0),
-                message: ServerMessages::EntityList {
-                    entities: npc_list
-                        .iter()
-                        .filter(|e| &player_entity != *e)
-                        .copied()
-                        .collect(),
-                },
-            });
-        }
-    }
-}
-
 #[cfg(feature = "verbose-output")]
 fn map_instance_debug(
     map_instances: Query<(Entity, &PlayerCount, &MapName), Changed<PlayerCount>>,
@@ -456,3 +545,29 @@ fn map_instance_debug(
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn teleport(args: &[&str]) -> Option<(String, Vec2)> {
+        TeleportCommand::parse(args).map(|teleport| (teleport.map, teleport.position))
+    }
+
+    #[test]
+    fn teleport_takes_a_map_and_a_position() {
+        assert_eq!(
+            teleport(&["town", "12.5", "-3"]),
+            Some(("town".to_string(), Vec2::new(12.5, -3.)))
+        );
+
+        for args in [
+            &["town", "12"][..],
+            &["town", "x", "3"],
+            &["town", "12", "3", "4"],
+            &[],
+        ] {
+            assert!(teleport(args).is_none(), "{:?}", args);
+        }
+    }
+}
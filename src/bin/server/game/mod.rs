@@ -5,20 +5,36 @@ use bevy::prelude::*;
 use bevy_spatial::{AutomaticUpdate, SpatialStructure};
 use tiled_game::components::Unit;
 
+pub mod abilities;
+pub mod auras;
+pub mod character_select;
+pub mod chat;
 pub mod combat;
+pub mod commands;
+pub mod experience;
 pub mod interactions;
 pub mod map;
+pub mod movement;
 pub mod npc;
 pub mod player;
 pub mod scripts;
+pub mod stats;
 pub mod unit;
 
+use self::abilities::AbilitiesPlugin;
+use self::auras::AurasPlugin;
+use self::character_select::CharacterSelectPlugin;
+use self::chat::ChatPlugin;
 use self::combat::CombatPlugin;
+use self::commands::CommandsPlugin;
+use self::experience::ExperiencePlugin;
 use self::interactions::InteractionPlugin;
 use self::map::*;
+use self::movement::MovementPlugin;
 use self::npc::NPCPlugin;
 use self::player::*;
 use self::scripts::ScriptsPlugin;
+use self::stats::StatsPlugin;
 use self::unit::UnitPlugin;
 
 pub struct GamePlugin;
@@ -32,10 +48,18 @@ impl Plugin for GamePlugin {
         ) // track everything that has the unit marker in a spatial index
         .add_plugins(UnitPlugin)
         .add_plugins(NPCPlugin)
+        .add_plugins(CharacterSelectPlugin)
         .add_plugins(PlayerPlugin)
         .add_plugins(MapsPlugin)
+        .add_plugins(MovementPlugin)
         .add_plugins(CombatPlugin)
+        .add_plugins(StatsPlugin)
+        .add_plugins(ExperiencePlugin)
+        .add_plugins(AbilitiesPlugin)
+        .add_plugins(AurasPlugin)
         .add_plugins(ScriptsPlugin)
-        .add_plugins(InteractionPlugin);
+        .add_plugins(InteractionPlugin)
+        .add_plugins(ChatPlugin)
+        .add_plugins(CommandsPlugin);
     }
 }
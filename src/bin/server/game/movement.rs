@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use tiled_game::{
+    components::Dead,
+    movement::{apply_input, MoveInput, INPUT_TICK_SECONDS},
+    network::messages::server::ServerMessages,
+};
+
+use crate::network::{NetworkClientId, SendServerMessageEvent};
+
+use super::{
+    auras::{Rooted, Stunned},
+    map::{MapManager, MapName},
+    player::{Charmed, Player},
+    unit::Speed,
+};
+
+// Seconds of inputs a player can bank, covers packets arriving in bursts
+const MAX_BUDGET_SECONDS: f32 = 0.25;
+
+// Inputs beyond this are dropped, the client reconciles anyway
+const MAX_QUEUED_INPUTS: usize = 30;
+
+// Inputs a client sent for its player
+#[derive(Event)]
+pub struct PlayerInputEvent {
+    pub entity: Entity,
+    pub inputs: Vec<MoveInput>,
+}
+
+// Inputs waiting to be simulated
+#[derive(Component, Default)]
+pub struct InputQueue {
+    inputs: VecDeque<MoveInput>,
+    // Last sequence that was queued or applied
+    last_sequence: u32,
+    // Seconds of inputs the player may still apply
+    // Refills in real time so clients can't move faster by sending more inputs
+    budget: f32,
+}
+
+pub struct MovementPlugin;
+
+impl Plugin for MovementPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlayerInputEvent>()
+            .add_systems(Update, (queue_player_inputs, apply_player_inputs).chain());
+    }
+}
+
+fn queue_player_inputs(
+    mut input_events: EventReader<PlayerInputEvent>,
+    mut players: Query<&mut InputQueue, With<Player>>,
+) {
+    for event in input_events.iter() {
+        let Ok(mut queue) = players.get_mut(event.entity) else {
+            continue;
+        };
+
+        // the client resends unacknowledged inputs, skip the ones we already have
+        let mut inputs = event.inputs.clone();
+        inputs.sort_by_key(|input| input.sequence);
+
+        for input in inputs {
+            if input.sequence <= queue.last_sequence || queue.inputs.len() >= MAX_QUEUED_INPUTS {
+                continue;
+            }
+
+            queue.last_sequence = input.sequence;
+            queue.inputs.push_back(input);
+        }
+    }
+}
+
+// Simulates the queued inputs with the shared movement step
+// and tells the client where its player ended up
+fn apply_player_inputs(
+    time: Res<Time>,
+    map_manager: Res<MapManager>,
+    mut players: Query<
+        (
+            &mut Transform,
+            &mut InputQueue,
+            &Speed,
+            &Parent,
+            &NetworkClientId,
+            Option<&Charmed>,
+            Option<&Dead>,
+            Option<&Stunned>,
+            Option<&Rooted>,
+        ),
+        With<Player>,
+    >,
+    map_instances: Query<&MapName>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+) {
+    for (
+        mut transform,
+        mut queue,
+        speed,
+        map_instance,
+        client_id,
+        charmed,
+        dead,
+        stunned,
+        rooted,
+    ) in players.iter_mut()
+    {
+        queue.budget = (queue.budget + time.delta_seconds()).min(MAX_BUDGET_SECONDS);
+
+        if queue.inputs.is_empty() {
+            continue;
+        }
+
+        let collision = map_instances
+            .get(map_instance.get())
+            .ok()
+            .and_then(|map_name| map_manager.collision.get(&map_name.0));
+
+        let mut position = transform.translation.truncate();
+        let mut acknowledged = None;
+
+        // charmed, dead, stunned and rooted players don't control their position
+        // their inputs are used up so the client snaps back
+        let controlled =
+            charmed.is_none() && dead.is_none() && stunned.is_none() && rooted.is_none();
+
+        while queue.budget >= INPUT_TICK_SECONDS {
+            let Some(input) = queue.inputs.pop_front() else {
+                break;
+            };
+
+            if controlled {
+                position = apply_input(position, &input, speed.0, collision);
+            }
+
+            queue.budget -= INPUT_TICK_SECONDS;
+            acknowledged = Some(input.sequence);
+        }
+
+        let Some(sequence) = acknowledged else {
+            continue;
+        };
+
+        // keep z, it decides what is drawn on top
+        if position != transform.translation.truncate() {
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+        }
+
+        server_messages.send(SendServerMessageEvent {
+            client_id: Some(client_id.0),
+            message: ServerMessages::MoveAck {
+                sequence,
+                pos: transform.translation,
+                speed: speed.0,
+            },
+        });
+    }
+}
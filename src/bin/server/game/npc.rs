@@ -4,13 +4,21 @@ use bevy::prelude::*;
 use bevy_spatial::SpatialAccess;
 use tiled_game::components::*;
 
+use crate::network::net_ids::NetIds;
+
 use super::{
     combat::{DoDamageEvent, LeaveCombatEvent},
-    map,
+    commands::{AddGameCommand, CommandEvent, GameCommand, Permission},
+    experience::MAX_LEVEL,
+    map::{self, MapInstanceEntity},
     player::Player,
+    stats::Stats,
     unit::{Follow, MoveDestination, Speed, UnitBundle, UnitsNearby},
 };
 
+// Bosses may be a few levels above any player, but levels come from maps and admins
+pub const MAX_CREATURE_LEVEL: u32 = MAX_LEVEL + 10;
+
 // A vector that represents the NPCs home position
 // Usually the spawn point
 #[derive(Component, Debug)]
@@ -35,13 +43,14 @@ pub struct NPCBundle {
 }
 
 impl NPCBundle {
-    pub fn new(name: String, class: String, transform: Transform) -> Self {
+    pub fn new(name: String, class: String, transform: Transform, level: u32) -> Self {
         let mut npc = Self {
             npc: NPC,
-            unit: UnitBundle::new(name, class, transform),
+            unit: UnitBundle::new(name, class, transform).with_stats(Stats::creature(level)),
             home: Home(transform.translation),
         };
 
+        npc.unit.level = Level(level);
         npc.unit.speed = Speed(0.9);
         npc
     }
@@ -51,7 +60,8 @@ pub struct NPCPlugin;
 
 impl Plugin for NPCPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, npc_evaded_system)
+        app.add_game_command::<Spawn>()
+            .add_systems(Update, (npc_evaded_system, spawn_command))
             .add_systems(PostUpdate, return_to_home_system)
             .add_systems(PostUpdate, npc_evaded.after(return_to_home_system))
             .add_systems(
@@ -65,6 +75,65 @@ impl Plugin for NPCPlugin {
     }
 }
 
+// Spawns a hostile unit of the given class where the admin stands
+pub struct Spawn {
+    unit: String,
+    level: u32,
+}
+
+impl GameCommand for Spawn {
+    const NAME: &'static str = "spawn";
+    const USAGE: &'static str = "<unit> [level 1-30]";
+    const PERMISSION: Permission = Permission::Admin;
+
+    fn parse(args: &[&str]) -> Option<Self> {
+        let (unit, level) = match args {
+            [unit] => (unit, 1),
+            [unit, level] => (
+                unit,
+                level
+                    .parse()
+                    .ok()
+                    .filter(|level| (1..=MAX_CREATURE_LEVEL).contains(level))?,
+            ),
+            _ => return None,
+        };
+
+        Some(Spawn {
+            unit: unit.to_string(),
+            level,
+        })
+    }
+}
+
+fn spawn_command(
+    mut commands: Commands,
+    mut net_ids: ResMut<NetIds>,
+    mut spawn_commands: EventReader<CommandEvent<Spawn>>,
+    players: Query<(&Transform, &Parent), With<Player>>,
+) {
+    for event in spawn_commands.iter() {
+        let Ok((transform, map_instance)) = players.get(event.player) else {
+            continue;
+        };
+
+        let unit = &event.command.unit;
+        let spawn_point = Transform::from_translation(transform.translation);
+
+        let id = commands
+            .spawn((
+                NPCBundle::new(unit.clone(), unit.clone(), spawn_point, event.command.level),
+                MapInstanceEntity(map_instance.get()),
+                Enemy,
+            ))
+            .id();
+        commands.entity(id).insert(net_ids.allocate(id));
+        commands.entity(map_instance.get()).push_children(&[id]);
+
+        println!("Spawning unit {:?} for {:?}", unit, event.player);
+    }
+}
+
 // Returns the NPC to its home position if it is not in combat anymore
 fn return_to_home_system(
     mut cmd: Commands,
@@ -114,7 +183,11 @@ fn aggro_by_range_system(
     aggressors: Query<(Entity, &Transform, &Parent), (With<NPC>, With<Enemy>, Without<InCombat>)>,
 
     // possible targets that can pull the aggressor
-    entities_that_can_aggro: Query<(Entity, &Transform, &Parent), (With<Player>, Without<Dead>)>, // currently only players can aggro
+    // currently only players can aggro, players still loading the map are ignored
+    entities_that_can_aggro: Query<
+        (Entity, &Transform, &Parent),
+        (With<Player>, Without<Dead>, Without<map::LoadingMap>),
+    >,
 
     units: Res<UnitsNearby>,
 ) {
@@ -217,3 +290,36 @@ fn target_and_follow_highest_threat(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn(args: &[&str]) -> Option<(String, u32)> {
+        Spawn::parse(args).map(|spawn| (spawn.unit, spawn.level))
+    }
+
+    #[test]
+    fn spawn_takes_a_unit_and_a_level() {
+        assert_eq!(spawn(&["wolf"]), Some(("wolf".to_string(), 1)));
+        assert_eq!(spawn(&["wolf", "7"]), Some(("wolf".to_string(), 7)));
+
+        for args in [&[][..], &["wolf", "seven"], &["wolf", "7", "8"]] {
+            assert!(spawn(args).is_none(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn spawn_levels_stay_within_creature_levels() {
+        let max = MAX_CREATURE_LEVEL.to_string();
+        let above = (MAX_CREATURE_LEVEL + 1).to_string();
+
+        assert_eq!(spawn(&["wolf", &max]).unwrap().1, MAX_CREATURE_LEVEL);
+        assert!(spawn(&["wolf", &above]).is_none());
+        assert!(spawn(&["wolf", "0"]).is_none());
+        assert!(spawn(&["wolf", "4294967296"]).is_none());
+
+        // the usage tells admins the range
+        assert!(Spawn::USAGE.ends_with(&format!("[level 1-{}]", MAX_CREATURE_LEVEL)));
+    }
+}
@@ -1,8 +1,29 @@
 use bevy::prelude::*;
+use bevy_renet::renet::RenetServer;
+use tiled_game::{
+    components::{Health, Level, Mana, Unit},
+    network::messages::server::{DisconnectionReason, ServerMessages},
+};
 
-use crate::{game::unit::UnitBundle, network::NetworkClientId};
+use crate::{
+    config::ServerConfig,
+    game::unit::UnitBundle,
+    network::{
+        disconnect_client, net_ids::NetIds, relevance::RelevantEntities,
+        snapshots::SnapshotHistory, AccountId, NetworkClientId, PendingDisconnects,
+        SendServerMessageEvent,
+    },
+    storage::{CharacterRecord, Storage},
+};
 
-use super::map::{DespawnEvent, Teleport};
+use super::{
+    character_select::SelectedCharacter,
+    commands::{reply, AddGameCommand, CommandEvent, GameCommand, Permission},
+    experience::Experience,
+    map::{DespawnEvent, MapName, Teleport},
+    movement::InputQueue,
+    stats::Stats,
+};
 
 #[derive(Component)]
 pub struct Player;
@@ -15,36 +36,147 @@ pub struct LoggingOut;
 #[derive(Component)]
 pub struct Charmed;
 
+#[derive(Resource)]
+pub struct AutosaveTimer(pub Timer);
+
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (player_logout, player_join));
+        let autosave_interval = app.world.resource::<ServerConfig>().autosave_duration();
+
+        app.insert_resource(AutosaveTimer(Timer::new(
+            autosave_interval,
+            TimerMode::Repeating,
+        )))
+        .add_game_command::<Who>()
+        .add_game_command::<Logout>()
+        .add_systems(
+            Update,
+            (
+                player_logout,
+                player_join,
+                autosave_players,
+                who_command,
+                logout_command,
+            ),
+        );
+    }
+}
+
+// Lists the players that are online
+pub struct Who;
+
+impl GameCommand for Who {
+    const NAME: &'static str = "who";
+    const USAGE: &'static str = "";
+    const PERMISSION: Permission = Permission::Player;
+
+    fn parse(args: &[&str]) -> Option<Self> {
+        args.is_empty().then_some(Who)
+    }
+}
+
+// Saves the character and leaves the game
+pub struct Logout;
+
+impl GameCommand for Logout {
+    const NAME: &'static str = "logout";
+    const USAGE: &'static str = "";
+    const PERMISSION: Permission = Permission::Player;
+
+    fn parse(args: &[&str]) -> Option<Self> {
+        args.is_empty().then_some(Logout)
+    }
+}
+
+// Components of a player in the world that end up in the storage
+pub type OnlinePlayer<'a> = (
+    &'a Parent,
+    &'a AccountId,
+    &'a Name,
+    &'a Unit,
+    &'a Transform,
+    &'a Health,
+    &'a Mana,
+    &'a Level,
+    &'a Experience,
+);
+
+// Builds the record that gets written to the storage
+// The map and position are given, a teleport saves where the player ends up
+pub fn character_record(
+    (_, account_id, name, unit, _, health, mana, level, experience): OnlinePlayer,
+    map: &str,
+    position: Vec3,
+) -> CharacterRecord {
+    CharacterRecord {
+        account_id: account_id.0,
+        name: name.to_string(),
+        class: unit.0.clone(),
+        map: map.to_string(),
+        x: position.x,
+        y: position.y,
+        health: health.0,
+        mana: mana.0,
+        level: level.0,
+        experience: experience.0,
+    }
+}
+
+pub fn save_character(storage: &Storage, character: &CharacterRecord) {
+    if let Err(e) = storage.0.save_character(character) {
+        println!("Could not save character {:?}: {}", character.name, e);
     }
 }
 
 pub fn player_join(
     mut commands: Commands,
     mut teleport_event: EventWriter<Teleport>,
-    new_connected_players: Query<Entity, Added<NetworkClientId>>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    mut net_ids: ResMut<NetIds>,
+    selected_characters: Query<(Entity, &NetworkClientId, &SelectedCharacter)>,
 ) {
-    for entity in new_connected_players.iter() {
-        println!("Adding new player: {:?}", entity);
-        // fetch player info from database
-        commands.entity(entity).insert((
-            UnitBundle::new(
-                "John Doe".into(),
-                String::from("Fantasy Dreamland/Characters/Character_001"),
-                Transform::from_xyz(30., 30., 0.),
-            ),
-            Player,
-        ));
+    for (entity, client_id, SelectedCharacter(character)) in selected_characters.iter() {
+        println!("Adding new player: {:?} ({})", entity, character.name);
+
+        let position = Transform::from_xyz(character.x, character.y, 0.);
+        let mut unit = UnitBundle::new(character.name.clone(), character.class.clone(), position)
+            .with_stats(Stats::player(character.level));
+        unit.level = Level(character.level);
+        unit.health = Health(character.health);
+        unit.mana = Mana(character.mana);
+        let net_id = net_ids.allocate(entity);
+
+        commands
+            .entity(entity)
+            .insert((
+                unit,
+                Experience(character.experience),
+                net_id,
+                Player,
+                InputQueue::default(),
+                SnapshotHistory::default(),
+                RelevantEntities::default(),
+            ))
+            .remove::<SelectedCharacter>();
+
+        server_messages.send(SendServerMessageEvent {
+            client_id: Some(client_id.0),
+            message: ServerMessages::PlayerInfo {
+                entity: net_id,
+                pos: position.translation,
+                name: character.name.clone(),
+                img: character.class.clone(),
+            },
+        });
 
         teleport_event.send(Teleport {
             entity,
-            map: "start.tmx".to_string(),                // todo: db
-            position: Transform::from_xyz(30., 30., 0.), // todo: db
-            map_instance: None,                          // todo: db
+            map: character.map.clone(),
+            position,
+            // entity ids don't survive a restart, characters always join the global instance
+            map_instance: None,
             prev_map_instance: None,
         });
     }
@@ -52,10 +184,27 @@ pub fn player_join(
 
 pub fn player_logout(
     mut commands: Commands,
-    players_logging_out: Query<(Entity, &Parent), With<LoggingOut>>,
+    storage: Res<Storage>,
+    players_logging_out: Query<(Entity, OnlinePlayer), With<LoggingOut>>,
+    map_instances: Query<&MapName>,
     mut despawn_events: EventWriter<DespawnEvent>,
+    // connections that never made it into the world
+    connections_logging_out: Query<Entity, (With<LoggingOut>, Without<Parent>)>,
 ) {
-    for (player_entity, map_instance_entity) in players_logging_out.iter() {
+    for entity in connections_logging_out.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    for (player_entity, player) in players_logging_out.iter() {
+        let (map_instance_entity, _, _, _, transform, ..) = player;
+
+        if let Ok(map_name) = map_instances.get(map_instance_entity.get()) {
+            save_character(
+                &storage,
+                &character_record(player, &map_name.0, transform.translation),
+            );
+        }
+
         // remove child from map instance
         commands
             .entity(map_instance_entity.get())
@@ -70,3 +219,84 @@ pub fn player_logout(
         });
     }
 }
+
+// Periodically writes every online character to the storage
+// so a crash doesn't lose too much progress
+fn autosave_players(
+    time: Res<Time>,
+    storage: Res<Storage>,
+    mut autosave_timer: ResMut<AutosaveTimer>,
+    players: Query<OnlinePlayer, With<Player>>,
+    map_instances: Query<&MapName>,
+) {
+    if !autosave_timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let saved = save_online_players(&storage, &players, &map_instances);
+    if saved > 0 {
+        println!("Autosaved {} characters", saved);
+    }
+}
+
+// Writes every player in the world to the storage, returns how many were saved
+pub fn save_online_players(
+    storage: &Storage,
+    players: &Query<OnlinePlayer, With<Player>>,
+    map_instances: &Query<&MapName>,
+) -> usize {
+    let mut saved = 0;
+    for player in players.iter() {
+        let (map_instance, _, _, _, transform, ..) = player;
+        let Ok(map_name) = map_instances.get(map_instance.get()) else {
+            continue;
+        };
+
+        save_character(
+            storage,
+            &character_record(player, &map_name.0, transform.translation),
+        );
+        saved += 1;
+    }
+
+    saved
+}
+
+fn who_command(
+    mut who_commands: EventReader<CommandEvent<Who>>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    players: Query<(&Name, &NetworkClientId), With<Player>>,
+) {
+    for event in who_commands.iter() {
+        let Ok((_, client_id)) = players.get(event.player) else {
+            continue;
+        };
+
+        let mut names: Vec<&str> = players.iter().map(|(name, _)| name.as_str()).collect();
+        names.sort_unstable();
+
+        server_messages.send(reply(
+            client_id,
+            format!("{} players online: {}", names.len(), names.join(", ")),
+        ));
+    }
+}
+
+fn logout_command(
+    mut server: ResMut<RenetServer>,
+    mut pending_disconnects: ResMut<PendingDisconnects>,
+    mut logout_commands: EventReader<CommandEvent<Logout>>,
+    players: Query<&NetworkClientId, With<Player>>,
+) {
+    for event in logout_commands.iter() {
+        // the disconnect saves the character like any other
+        if let Ok(client_id) = players.get(event.player) {
+            disconnect_client(
+                &mut server,
+                &mut pending_disconnects,
+                client_id.0,
+                DisconnectionReason::LoggedOut,
+            );
+        }
+    }
+}
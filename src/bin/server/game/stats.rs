@@ -0,0 +1,399 @@
+/**
+ * Attributes of units and the formulas that turn them into combat numbers
+ *
+ * Everything else is derived from the stats: health and mana pools,
+ * how hard a unit hits, how often it crits or dodges and how much of
+ * a physical hit its armor takes away.
+ */
+use bevy::prelude::*;
+use tiled_game::{
+    combat::{DamageSchool, HitResult},
+    components::*,
+};
+
+// Health and mana every unit has before stamina and intellect
+const BASE_HEALTH: i32 = 5;
+const BASE_MANA: i32 = 5;
+
+// Damage of an unarmed swing, before attack power
+const WEAPON_DAMAGE: (i32, i32) = (1, 2);
+
+// Attack power and intellect per point of bonus damage
+const DAMAGE_PER_BONUS: i32 = 10;
+
+// Percent chances
+const MISS_CHANCE: f32 = 5.;
+const BASE_DODGE_CHANCE: f32 = 3.;
+const CRIT_PER_AGILITY: f32 = 0.2;
+const DODGE_PER_AGILITY: f32 = 0.2;
+
+const CRIT_MULTIPLIER: i32 = 2;
+
+// Armor that takes away half of a physical hit
+const ARMOR_HALF_MITIGATION: f32 = 100.;
+
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Stats {
+    // Adds to attack power
+    pub strength: i32,
+    // Crit and dodge chance
+    pub agility: i32,
+    // Mana and bonus damage of spells
+    pub intellect: i32,
+    // Health
+    pub stamina: i32,
+    pub armor: i32,
+    // Bonus damage of physical attacks, on top of strength
+    pub attack_power: i32,
+    // Percent, on top of agility
+    pub crit_chance: f32,
+}
+
+impl Default for Stats {
+    // What a new character starts with
+    fn default() -> Self {
+        Self {
+            strength: 10,
+            agility: 10,
+            intellect: 10,
+            stamina: 10,
+            armor: 20,
+            attack_power: 0,
+            crit_chance: 5.,
+        }
+    }
+}
+
+// Levels gained since level 1
+fn levels_gained(level: u32) -> i32 {
+    i32::try_from(level.saturating_sub(1)).unwrap_or(i32::MAX)
+}
+
+// Stat at the level, stops growing instead of wrapping around
+fn scaled(base: i32, per_level: i32, gained: i32) -> i32 {
+    base.saturating_add(per_level.saturating_mul(gained))
+}
+
+impl Stats {
+    // What a character has at the level, every level up raises these
+    pub fn player(level: u32) -> Self {
+        let gained = levels_gained(level);
+        let base = Self::default();
+
+        Self {
+            strength: scaled(base.strength, 2, gained),
+            agility: scaled(base.agility, 2, gained),
+            intellect: scaled(base.intellect, 2, gained),
+            stamina: scaled(base.stamina, 3, gained),
+            armor: scaled(base.armor, 5, gained),
+            ..base
+        }
+    }
+
+    // Creatures hit softer and have no mana to speak of
+    pub fn creature(level: u32) -> Self {
+        let gained = levels_gained(level);
+
+        Self {
+            strength: scaled(5, 1, gained),
+            agility: scaled(5, 1, gained),
+            intellect: 0,
+            stamina: scaled(5, 2, gained),
+            armor: scaled(10, 4, gained),
+            attack_power: scaled(0, 2, gained),
+            crit_chance: 5.,
+        }
+    }
+
+    pub fn max_health(&self) -> i32 {
+        BASE_HEALTH.saturating_add(self.stamina)
+    }
+
+    pub fn max_mana(&self) -> i32 {
+        BASE_MANA.saturating_add(self.intellect)
+    }
+
+    pub fn total_attack_power(&self) -> i32 {
+        self.attack_power.saturating_add(self.strength)
+    }
+
+    // Lowest and highest damage of an auto attack
+    pub fn weapon_damage(&self) -> (i32, i32) {
+        let bonus = self.total_attack_power() / DAMAGE_PER_BONUS;
+        (
+            WEAPON_DAMAGE.0.saturating_add(bonus),
+            WEAPON_DAMAGE.1.saturating_add(bonus),
+        )
+    }
+
+    // Damage abilities of the school do on top of their own
+    pub fn bonus_damage(&self, school: DamageSchool) -> i32 {
+        match school {
+            DamageSchool::Physical => self.total_attack_power() / DAMAGE_PER_BONUS,
+            _ => self.intellect / DAMAGE_PER_BONUS,
+        }
+    }
+
+    pub fn total_crit_chance(&self) -> f32 {
+        self.crit_chance + self.agility as f32 * CRIT_PER_AGILITY
+    }
+
+    pub fn dodge_chance(&self) -> f32 {
+        BASE_DODGE_CHANCE + self.agility as f32 * DODGE_PER_AGILITY
+    }
+
+    // Share of physical damage that gets through the armor
+    pub fn armor_factor(&self) -> f32 {
+        let armor = self.armor.max(0) as f32;
+        1. - armor / (armor + ARMOR_HALF_MITIGATION)
+    }
+
+    // Damage left after armor, a hit always does at least 1
+    pub fn mitigate(&self, school: DamageSchool, damage: i32) -> i32 {
+        match school {
+            DamageSchool::Physical if damage > 0 => {
+                ((damage as f32 * self.armor_factor()).round() as i32).max(1)
+            }
+            _ => damage,
+        }
+    }
+}
+
+// An auto attack of the attacker on the target
+pub fn roll_auto_attack(attacker: &Stats, target: &Stats) -> (i32, HitResult) {
+    let (min, max) = attacker.weapon_damage();
+    roll_attack(
+        attacker,
+        target,
+        DamageSchool::Physical,
+        fastrand::i32(min..=max),
+    )
+}
+
+// Rolls for miss, dodge and crit, then applies the armor of the target
+// Only physical attacks can miss or be dodged
+pub fn roll_attack(
+    attacker: &Stats,
+    target: &Stats,
+    school: DamageSchool,
+    damage: i32,
+) -> (i32, HitResult) {
+    let roll = fastrand::f32() * 100.;
+    let crit_chance = attacker.total_crit_chance();
+
+    let (damage, result) = match school {
+        DamageSchool::Physical if roll < MISS_CHANCE => (0, HitResult::Miss),
+        DamageSchool::Physical if roll < MISS_CHANCE + target.dodge_chance() => {
+            (0, HitResult::Dodge)
+        }
+        DamageSchool::Physical if roll < MISS_CHANCE + target.dodge_chance() + crit_chance => {
+            (damage.saturating_mul(CRIT_MULTIPLIER), HitResult::Crit)
+        }
+        DamageSchool::Physical => (damage, HitResult::Hit),
+        _ if roll < crit_chance => (damage.saturating_mul(CRIT_MULTIPLIER), HitResult::Crit),
+        _ => (damage, HitResult::Hit),
+    };
+
+    (target.mitigate(school, damage), result)
+}
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, update_pools);
+    }
+}
+
+// Stats changed, health and mana are kept within the new maximum
+fn update_pools(
+    mut units: Query<
+        (&Stats, &mut Health, &mut MaxHealth, &mut Mana, &mut MaxMana),
+        Changed<Stats>,
+    >,
+) {
+    for (stats, mut health, mut max_health, mut mana, mut max_mana) in units.iter_mut() {
+        max_health.0 = stats.max_health();
+        max_mana.0 = stats.max_mana();
+        health.0 = health.0.min(max_health.0);
+        mana.0 = mana.0.min(max_mana.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROLLS: usize = 10_000;
+
+    fn unarmored() -> Stats {
+        Stats {
+            agility: 0,
+            armor: 0,
+            crit_chance: 0.,
+            ..Stats::default()
+        }
+    }
+
+    fn rolls(
+        attacker: &Stats,
+        target: &Stats,
+        school: DamageSchool,
+        damage: i32,
+    ) -> Vec<(i32, HitResult)> {
+        fastrand::seed(7);
+        (0..ROLLS)
+            .map(|_| roll_attack(attacker, target, school, damage))
+            .collect()
+    }
+
+    fn share(rolls: &[(i32, HitResult)], result: HitResult) -> f32 {
+        let count = rolls.iter().filter(|(_, roll)| *roll == result).count();
+        count as f32 * 100. / rolls.len() as f32
+    }
+
+    #[test]
+    fn armor_mitigation_stays_within_bounds() {
+        assert_eq!(unarmored().mitigate(DamageSchool::Physical, 40), 40);
+
+        let half = Stats {
+            armor: ARMOR_HALF_MITIGATION as i32,
+            ..unarmored()
+        };
+        assert_eq!(half.mitigate(DamageSchool::Physical, 40), 20);
+
+        let fortress = Stats {
+            armor: i32::MAX,
+            ..unarmored()
+        };
+        assert_eq!(fortress.mitigate(DamageSchool::Physical, 40), 1);
+
+        // armor only stops physical damage
+        assert_eq!(fortress.mitigate(DamageSchool::Fire, 40), 40);
+
+        let broken = Stats {
+            armor: -50,
+            ..unarmored()
+        };
+        assert_eq!(broken.mitigate(DamageSchool::Physical, 40), 40);
+    }
+
+    #[test]
+    fn mitigated_damage_is_never_negative_or_more_than_the_hit() {
+        for armor in [-10, 0, 1, 20, 100, 1000, i32::MAX] {
+            let target = Stats {
+                armor,
+                ..unarmored()
+            };
+
+            for damage in [0, 1, 2, 7, 100, i32::MAX] {
+                let mitigated = target.mitigate(DamageSchool::Physical, damage);
+                assert!((0..=damage).contains(&mitigated), "{} {}", armor, damage);
+            }
+        }
+    }
+
+    #[test]
+    fn physical_rolls_miss_dodge_hit_and_crit() {
+        let attacker = Stats {
+            crit_chance: 10.,
+            ..unarmored()
+        };
+        let rolls = rolls(&attacker, &unarmored(), DamageSchool::Physical, 10);
+
+        for (damage, result) in rolls.iter() {
+            let expected = match result {
+                HitResult::Miss | HitResult::Dodge => 0,
+                HitResult::Hit => 10,
+                HitResult::Crit => 10 * CRIT_MULTIPLIER,
+                HitResult::Absorb => panic!("rolls don't know about shields"),
+            };
+            assert_eq!(*damage, expected);
+        }
+
+        // about 5% miss, 3% dodge and 10% crit
+        assert!((4. ..6.).contains(&share(&rolls, HitResult::Miss)));
+        assert!((2. ..4.).contains(&share(&rolls, HitResult::Dodge)));
+        assert!((9. ..11.).contains(&share(&rolls, HitResult::Crit)));
+    }
+
+    #[test]
+    fn spells_only_hit_or_crit() {
+        let attacker = Stats {
+            crit_chance: 25.,
+            ..unarmored()
+        };
+        let rolls = rolls(&attacker, &unarmored(), DamageSchool::Frost, 10);
+
+        assert!(rolls
+            .iter()
+            .all(|(_, result)| matches!(result, HitResult::Hit | HitResult::Crit)));
+        assert!((23. ..27.).contains(&share(&rolls, HitResult::Crit)));
+    }
+
+    #[test]
+    fn crit_chance_is_bounded_by_the_roll() {
+        let rolls_without = rolls(&unarmored(), &unarmored(), DamageSchool::Fire, 10);
+        assert_eq!(share(&rolls_without, HitResult::Crit), 0.);
+
+        let certain = Stats {
+            crit_chance: 100.,
+            ..unarmored()
+        };
+        let rolls_with = rolls(&certain, &unarmored(), DamageSchool::Fire, 10);
+        assert_eq!(share(&rolls_with, HitResult::Crit), 100.);
+
+        // agility adds to the chance
+        let agile = Stats {
+            agility: 50,
+            ..unarmored()
+        };
+        assert_eq!(agile.total_crit_chance(), 10.);
+    }
+
+    #[test]
+    fn auto_attacks_roll_within_weapon_damage() {
+        let attacker = Stats {
+            strength: 25,
+            attack_power: 5,
+            ..unarmored()
+        };
+        let (min, max) = attacker.weapon_damage();
+        assert_eq!((min, max), (WEAPON_DAMAGE.0 + 3, WEAPON_DAMAGE.1 + 3));
+
+        fastrand::seed(7);
+        for _ in 0..ROLLS {
+            let (damage, result) = roll_auto_attack(&attacker, &unarmored());
+            match result {
+                HitResult::Hit => assert!((min..=max).contains(&damage)),
+                HitResult::Crit => {
+                    assert!((min * CRIT_MULTIPLIER..=max * CRIT_MULTIPLIER).contains(&damage))
+                }
+                _ => assert_eq!(damage, 0),
+            }
+        }
+    }
+
+    #[test]
+    fn stats_grow_with_the_level() {
+        let first = Stats::player(1);
+        assert_eq!(first.strength, Stats::default().strength);
+        assert_eq!(Stats::player(0).stamina, first.stamina);
+
+        let fifth = Stats::player(5);
+        assert_eq!(fifth.stamina, first.stamina + 12);
+        assert!(fifth.max_health() > first.max_health());
+
+        assert!(Stats::creature(10).armor > Stats::creature(1).armor);
+    }
+
+    #[test]
+    fn huge_levels_saturate_instead_of_wrapping() {
+        for stats in [Stats::player(u32::MAX), Stats::creature(u32::MAX)] {
+            assert!(stats.max_health() > 0);
+            assert!(stats.armor > 0);
+            assert!(stats.total_attack_power() > 0);
+            assert!(stats.weapon_damage().0 > 0);
+        }
+    }
+}
@@ -6,7 +6,11 @@ use bevy::prelude::*;
 use bevy_spatial::kdtree::KDTree2;
 use tiled_game::components::*;
 
-use super::combat::DoDamageEvent;
+use super::{
+    auras::{Auras, Rooted, Stunned},
+    combat::DoDamageEvent,
+    stats::Stats,
+};
 
 // A vector that the movement system will try to get to
 #[derive(Component)]
@@ -46,6 +50,9 @@ pub struct UnitBundle {
     pub mana: Mana,
     pub max_mana: MaxMana,
     pub attack_speed: AttackSpeed,
+    pub level: Level,
+    pub stats: Stats,
+    pub auras: Auras,
     pub spatial: SpatialBundle,
 }
 
@@ -54,17 +61,31 @@ impl UnitBundle {
         Self {
             name: Name::new(name),
             speed: Speed(1.0),
-            health: Health(5),
-            max_health: MaxHealth(5),
-            mana: Mana(5),
-            max_mana: MaxMana(5),
+            health: Health(0),
+            max_health: MaxHealth(0),
+            mana: Mana(0),
+            max_mana: MaxMana(0),
             attack_speed: AttackSpeed(Timer::from_seconds(1., TimerMode::Repeating)),
+            level: Level(1),
+            stats: Stats::default(),
+            auras: Auras::default(),
             spatial: SpatialBundle {
                 transform,
                 ..Default::default()
             },
             unit: Unit(class),
         }
+        .with_stats(Stats::default())
+    }
+
+    // Replaces the stats, the unit starts with full health and mana
+    pub fn with_stats(mut self, stats: Stats) -> Self {
+        self.stats = stats;
+        self.health = Health(stats.max_health());
+        self.max_health = MaxHealth(stats.max_health());
+        self.mana = Mana(stats.max_mana());
+        self.max_mana = MaxMana(stats.max_mana());
+        self
     }
 }
 
@@ -79,6 +100,7 @@ impl Plugin for UnitPlugin {
                 movement_system,
                 follow_system,
                 health_recovery_system,
+                mana_recovery_system,
                 death_system,
                 target_on_damage,
             ),
@@ -87,6 +109,10 @@ impl Plugin for UnitPlugin {
             2.,
             TimerMode::Repeating,
         )))
+        .insert_resource(ManaRecoveryTimer(Timer::from_seconds(
+            1.,
+            TimerMode::Repeating,
+        )))
         .add_event::<DeathEvent>();
     }
 }
@@ -94,7 +120,10 @@ impl Plugin for UnitPlugin {
 // Moves entities towards their destination
 fn movement_system(
     mut commands: Commands,
-    mut movements: Query<(Entity, &mut Transform, &MoveDestination, &Speed)>,
+    mut movements: Query<
+        (Entity, &mut Transform, &MoveDestination, &Speed),
+        (Without<Stunned>, Without<Rooted>),
+    >,
 ) {
     for (entity, mut transform, destination, speed) in movements.iter_mut() {
         let distance = transform.translation.distance(destination.0);
@@ -154,12 +183,32 @@ fn health_recovery_system(
     }
 }
 
+#[derive(Resource)]
+pub struct ManaRecoveryTimer(pub Timer);
+
+// Recovers mana of living units, in combat too so abilities stay usable
+fn mana_recovery_system(
+    mut manas: Query<(&mut Mana, &MaxMana), Without<Dead>>,
+    mut recovery_timer: ResMut<ManaRecoveryTimer>,
+    time: Res<Time>,
+) {
+    if !recovery_timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    for (mut mana, max_mana) in manas.iter_mut() {
+        if mana.0 < max_mana.0 {
+            mana.0 += 1;
+        }
+    }
+}
+
 #[derive(Event)]
 pub struct DeathEvent {
     pub entity: Entity,
 }
 
-fn death_system(
+pub fn death_system(
     mut commands: Commands,
     mut events: EventWriter<DeathEvent>,
     healths: Query<(Entity, &Health), Without<Dead>>,
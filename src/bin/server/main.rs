@@ -1,19 +1,31 @@
+mod config;
+mod console;
 mod game;
 mod network;
-
-use std::time::Duration;
+mod shutdown;
+mod storage;
 
 use bevy::{app::ScheduleRunnerPlugin, prelude::*, MinimalPlugins};
 
+use config::ServerConfig;
 use game::GamePlugin;
+use storage::Storage;
 
 fn main() {
+    let config = ServerConfig::load();
+    let storage = Storage::open(&config.database).expect("Could not open storage");
     let mut app = App::new();
 
-    app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_millis(16))));
+    app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(config.tick_duration())))
+        .insert_resource(config)
+        .insert_resource(storage);
 
     app.add_plugins(network::NetworkPlugin)
-        .add_plugins(GamePlugin);
+        .add_plugins(GamePlugin)
+        .add_plugins(console::ConsolePlugin)
+        .add_plugins(shutdown::ShutdownPlugin);
 
     app.run();
+
+    shutdown::restart_if_requested();
 }
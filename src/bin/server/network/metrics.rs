@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+
+use crate::config::ServerConfig;
+
+// Counters about the traffic of all clients since the server started
+#[derive(Resource, Default)]
+pub struct NetworkMetrics {
+    // Accepted messages by type
+    pub received: BTreeMap<&'static str, u64>,
+    // Messages dropped for going over the rate limit, by type
+    pub rate_limited: BTreeMap<&'static str, u64>,
+    pub decode_errors: u64,
+    pub kicked: u64,
+}
+
+impl NetworkMetrics {
+    pub fn count_received(&mut self, name: &'static str) {
+        *self.received.entry(name).or_default() += 1;
+    }
+
+    pub fn count_rate_limited(&mut self, name: &'static str) {
+        *self.rate_limited.entry(name).or_default() += 1;
+    }
+}
+
+#[derive(Resource)]
+pub struct MetricsTimer(pub Timer);
+
+pub fn report_network_metrics(
+    time: Res<Time>,
+    config: Res<ServerConfig>,
+    mut timer: ResMut<MetricsTimer>,
+    metrics: Res<NetworkMetrics>,
+) {
+    if config.metrics_interval == 0 || !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let format = |counters: &BTreeMap<&'static str, u64>| {
+        counters
+            .iter()
+            .map(|(name, count)| format!("{}={}", name, count))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    println!(
+        "Network metrics: received [{}] rate limited [{}] decode errors {} kicked {}",
+        format(&metrics.received),
+        format(&metrics.rate_limited),
+        metrics.decode_errors,
+        metrics.kicked
+    );
+}
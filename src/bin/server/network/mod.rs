@@ -1,12 +1,23 @@
+pub mod metrics;
+pub mod net_ids;
+pub mod rate_limit;
+pub mod relevance;
+pub mod snapshots;
 mod sync_systems;
 
-use std::{net::UdpSocket, time::SystemTime};
+use std::{
+    collections::HashMap,
+    net::UdpSocket,
+    time::{Duration, Instant, SystemTime},
+};
 
-use bevy::{app::AppExit, prelude::*};
+use bevy::prelude::*;
 use bevy_renet::{
     renet::{
-        transport::{NetcodeServerTransport, ServerAuthentication, ServerConfig},
-        ConnectionConfig, DefaultChannel, RenetServer, ServerEvent,
+        transport::{
+            NetcodeServerTransport, ServerAuthentication, ServerConfig as NetcodeServerConfig,
+        },
+        RenetServer, ServerEvent,
     },
     transport::NetcodeServerPlugin,
     RenetServerPlugin,
@@ -14,21 +25,78 @@ use bevy_renet::{
 use tiled_game::{
     components::Target,
     network::{
-        messages::{client::ClientMessages, server::ServerMessages},
+        channels::{connection_config, Channel},
+        messages::{
+            client::ClientMessages,
+            decode_message, encode_message,
+            server::{DisconnectionReason, ServerMessages},
+        },
+        net_id::NetId,
+        user_data::{parse_private_key, ConnectionUserData},
+        version::PROTOCOL_VERSION,
         PROTOCOL_ID,
     },
 };
 
+use metrics::*;
+use net_ids::*;
+use rate_limit::{message_name, RateLimiter, Verdict};
+use relevance::*;
+use snapshots::*;
 use sync_systems::*;
 
-use crate::game::player::LoggingOut;
+use crate::{
+    config::ServerConfig,
+    game::{
+        abilities::CastAbilityEvent,
+        character_select::{CharacterRequest, CharacterRequestEvent},
+        chat::ChatEvent,
+        commands::CommandTextEvent,
+        interactions::EntityInteractionEvent,
+        map::LoadingMap,
+        movement::PlayerInputEvent,
+        player::LoggingOut,
+    },
+    shutdown::ShutdownState,
+    storage::Storage,
+};
 
 #[derive(Component, Debug)]
 pub struct NetworkClientId(pub u64);
 
+// The account the connection belongs to
+// Only trustworthy when the server runs in secure mode
+#[derive(Component, Debug)]
+pub struct AccountId(pub u64);
+
+// Clients that send more undecodable messages than this get disconnected
+const MAX_DECODE_ERRORS: u32 = 5;
+
+// How long a disconnected client gets to receive the reason, a few resends of the reliable channels
+const DISCONNECT_GRACE: Duration = Duration::from_secs(1);
+
 #[derive(Default, Resource)]
 pub struct NetworkResource {
     player_entity_map: std::collections::HashMap<u64, Entity>,
+
+    // Undecodable messages per client
+    decode_errors: std::collections::HashMap<u64, u32>,
+
+    // Clients that completed the Hello handshake
+    verified_clients: std::collections::HashSet<u64>,
+
+    rate_limiters: std::collections::HashMap<u64, RateLimiter>,
+}
+
+// Clients that were told why they get dropped, with the time their connection gets closed
+// Closing it right away would throw away the message before it was sent
+#[derive(Default, Resource)]
+pub struct PendingDisconnects(HashMap<u64, Instant>);
+
+impl PendingDisconnects {
+    pub fn contains(&self, client_id: u64) -> bool {
+        self.0.contains_key(&client_id)
+    }
 }
 
 #[derive(Event)]
@@ -41,14 +109,22 @@ pub struct NetworkPlugin;
 
 impl Plugin for NetworkPlugin {
     fn build(&self, app: &mut App) {
-        let (server, transport) = new_renet_server();
+        let config = app.world.resource::<ServerConfig>();
+        let (server, transport) = new_renet_server(config);
+        let metrics_timer =
+            MetricsTimer(Timer::new(config.metrics_duration(), TimerMode::Repeating));
         app
             // Initialize Network
             .add_event::<SendServerMessageEvent>()
             .add_event::<SendEntityInfoEvent>()
+            .add_event::<SnapshotAckEvent>()
             .insert_resource(server)
             .insert_resource(transport)
             .insert_resource(NetworkResource::default())
+            .init_resource::<PendingDisconnects>()
+            .init_resource::<NetIds>()
+            .init_resource::<NetworkMetrics>()
+            .insert_resource(metrics_timer)
             .add_plugins(RenetServerPlugin)
             .add_plugins(NetcodeServerPlugin)
             // Receive Server Events
@@ -59,22 +135,34 @@ impl Plugin for NetworkPlugin {
                     handle_client_messages.after(handle_connection_events),
                 ),
             )
-            .add_systems(Update, send_message_system)
+            .add_systems(
+                Update,
+                (
+                    receive_snapshot_acks,
+                    send_message_system,
+                    report_network_metrics,
+                    close_pending_disconnects,
+                ),
+            )
             .add_systems(
                 PostUpdate,
                 (
-                    send_movement,
-                    send_despawn,
-                    send_vitals_changed,
-                    send_death_events,
-                    send_threat,
-                    send_entered_combat,
-                    send_spawn,
-                    send_exit_combat,
+                    (send_despawn, update_relevance, send_world_snapshots).chain(),
+                    (
+                        send_death_events,
+                        send_threat,
+                        send_entered_combat,
+                        send_exit_combat,
+                        send_cast_events,
+                        send_aura_events,
+                        send_damage_events,
+                        send_level_ups,
+                    )
+                        .after(update_relevance),
                     send_entity_info,
                 ),
             )
-            .add_systems(PostUpdate, disconnect_clients_on_exit);
+            .add_systems(Last, forget_net_ids);
     }
 }
 
@@ -83,24 +171,44 @@ fn send_message_system(
     mut send_server_message_event: EventReader<SendServerMessageEvent>,
 ) {
     for event in send_server_message_event.iter() {
-        let message = bincode::serialize(&event.message).unwrap();
+        let message = match encode_message(&event.message) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Could not encode message {:?}: {}", event.message, e);
+                continue;
+            }
+        };
+
+        let channel = event.message.channel();
 
         match event.client_id {
-            Some(client) => server.send_message(client, DefaultChannel::ReliableUnordered, message),
-            None => server.broadcast_message(DefaultChannel::ReliableUnordered, message),
+            Some(client) => server.send_message(client, channel, message),
+            None => server.broadcast_message(channel, message),
         }
     }
 }
 
-pub fn new_renet_server() -> (RenetServer, NetcodeServerTransport) {
-    let server = RenetServer::new(ConnectionConfig::default());
-    let public_addr = "127.0.0.1:3387".parse().expect("Invalid server address");
-    let socket = UdpSocket::bind(public_addr).expect("Failed to bind socket");
-    let server_config = ServerConfig {
-        max_clients: 64,
+pub fn new_renet_server(config: &ServerConfig) -> (RenetServer, NetcodeServerTransport) {
+    let server = RenetServer::new(connection_config());
+    let public_addr = config.public_address();
+    let socket = UdpSocket::bind(config.bind_address()).expect("Failed to bind socket");
+
+    let authentication = match &config.private_key {
+        Some(private_key) => ServerAuthentication::Secure {
+            private_key: parse_private_key(private_key)
+                .expect("private_key must be 64 hex characters"),
+        },
+        None => {
+            println!("No private key configured, accepting unsecure connections");
+            ServerAuthentication::Unsecure
+        }
+    };
+
+    let server_config = NetcodeServerConfig {
+        max_clients: config.max_clients,
         protocol_id: PROTOCOL_ID,
         public_addr,
-        authentication: ServerAuthentication::Unsecure,
+        authentication,
     };
     let current_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -108,41 +216,70 @@ pub fn new_renet_server() -> (RenetServer, NetcodeServerTransport) {
 
     let transport = NetcodeServerTransport::new(current_time, server_config, socket).unwrap();
 
+    println!(
+        "Listening on {} (public address {})",
+        config.bind_address(),
+        public_addr
+    );
+
     (server, transport)
 }
 
 fn handle_connection_events(
     mut commands: Commands,
+    mut server: ResMut<RenetServer>,
     mut client_entities: ResMut<NetworkResource>,
+    mut pending_disconnects: ResMut<PendingDisconnects>,
     mut connection_events: EventReader<ServerEvent>,
-    mut server_message_events: EventWriter<SendServerMessageEvent>,
+    transport: Res<NetcodeServerTransport>,
+    storage: Res<Storage>,
+    shutdown: Res<ShutdownState>,
 ) {
     for event in connection_events.iter() {
         match event {
-            ServerEvent::ClientConnected {
-                client_id, /* auth message */
-            } => {
-                println!("Client connected: {}", client_id);
-                // create an empty entity with the client id
-                // other systems should add the rest of the components
+            ServerEvent::ClientConnected { client_id } => {
+                // In secure mode the user data comes from the signed connect token
+                let account_id = transport
+                    .user_data(*client_id)
+                    .and_then(|user_data| ConnectionUserData::from_bytes(&user_data))
+                    .map_or(*client_id, |user_data| user_data.account_id);
+
+                println!("Client connected: {} (account {})", client_id, account_id);
+
+                let banned = storage.0.is_banned(account_id).unwrap_or_else(|e| {
+                    println!("Could not check bans of account {}: {}", account_id, e);
+                    false
+                });
+                if banned {
+                    disconnect_client(
+                        &mut server,
+                        &mut pending_disconnects,
+                        *client_id,
+                        DisconnectionReason::Banned,
+                    );
+                    continue;
+                }
+
+                // nobody gets in while the server is going down
+                if let Some(reason) = shutdown.reason() {
+                    disconnect_client(&mut server, &mut pending_disconnects, *client_id, reason);
+                    continue;
+                }
+
+                // create an entity with the client id and the authenticated identity
+                // the client picks a character before it enters the world
                 let entity = commands
-                    .spawn_empty()
-                    .insert(NetworkClientId(*client_id))
+                    .spawn((NetworkClientId(*client_id), AccountId(account_id)))
                     .id();
                 client_entities.player_entity_map.insert(*client_id, entity);
-
-                server_message_events.send(SendServerMessageEvent {
-                    client_id: Some(*client_id),
-                    message: ServerMessages::PlayerInfo {
-                        entity,
-                        pos: Vec3::new(0., 0., 0.),
-                        img: "dreamland/Characters/Character_001".to_string(),
-                    },
-                });
             }
             ServerEvent::ClientDisconnected { client_id, reason } => {
                 println!("Client disconnected: {}", client_id);
                 let client = client_entities.player_entity_map.remove(client_id);
+                client_entities.decode_errors.remove(client_id);
+                client_entities.verified_clients.remove(client_id);
+                client_entities.rate_limiters.remove(client_id);
+                pending_disconnects.0.remove(client_id);
 
                 if let Some(entity) = client {
                     commands.entity(entity).insert(LoggingOut);
@@ -154,26 +291,147 @@ fn handle_connection_events(
 
 fn handle_client_messages(
     mut server: ResMut<RenetServer>,
-    client_entities: ResMut<NetworkResource>,
+    mut client_entities: ResMut<NetworkResource>,
+    mut pending_disconnects: ResMut<PendingDisconnects>,
+    mut metrics: ResMut<NetworkMetrics>,
+    config: Res<ServerConfig>,
     mut entity_info_request: EventWriter<SendEntityInfoEvent>,
+    mut character_requests: EventWriter<CharacterRequestEvent>,
+    mut player_inputs: EventWriter<PlayerInputEvent>,
+    mut snapshot_acks: EventWriter<SnapshotAckEvent>,
+    mut interactions: EventWriter<EntityInteractionEvent>,
+    mut chat: EventWriter<ChatEvent>,
+    mut command_texts: EventWriter<CommandTextEvent>,
+    mut casts: EventWriter<CastAbilityEvent>,
+    net_ids: Res<NetIds>,
+    parents: Query<&Parent>,
     mut commands: Commands,
 ) {
+    let now = Instant::now();
+
     for client_id in server.clients_id().into_iter() {
-        while let Some(message) =
-            server.receive_message(client_id, DefaultChannel::ReliableUnordered)
+        // whatever a client sends after it was dropped is thrown away
+        if pending_disconnects.contains(client_id) {
+            for channel in Channel::ALL {
+                while server.receive_message(client_id, channel).is_some() {}
+            }
+            continue;
+        }
+
+        while let Some(message) = Channel::ALL
+            .iter()
+            .find_map(|channel| server.receive_message(client_id, *channel))
         {
-            let message: ClientMessages = bincode::deserialize(&message).unwrap();
+            let message: ClientMessages = match decode_message(&message) {
+                Ok(message) => message,
+                Err(e) => {
+                    metrics.decode_errors += 1;
+                    let errors = client_entities.decode_errors.entry(client_id).or_default();
+                    *errors += 1;
+                    println!(
+                        "Invalid message from client {} ({}/{}): {}",
+                        client_id, errors, MAX_DECODE_ERRORS, e
+                    );
+
+                    if *errors >= MAX_DECODE_ERRORS {
+                        disconnect_client(
+                            &mut server,
+                            &mut pending_disconnects,
+                            client_id,
+                            DisconnectionReason::InvalidMessages,
+                        );
+                        break;
+                    }
+
+                    continue;
+                }
+            };
             // println!("Received message from client {}: {:?}", client_id, message);
+
+            // every message costs the server work, floods are dropped and then kicked
+            let name = message_name(&message);
+            let verdict = client_entities
+                .rate_limiters
+                .entry(client_id)
+                .or_default()
+                .check(name, config.rate_limit(name), now);
+            match verdict {
+                Verdict::Allow => {}
+                Verdict::Drop => {
+                    metrics.count_rate_limited(name);
+                    continue;
+                }
+                Verdict::Kick => {
+                    metrics.count_rate_limited(name);
+                    metrics.kicked += 1;
+                    println!("Client {} kept flooding {} messages", client_id, name);
+                    disconnect_client(
+                        &mut server,
+                        &mut pending_disconnects,
+                        client_id,
+                        DisconnectionReason::Kicked,
+                    );
+                    break;
+                }
+            }
+            metrics.count_received(name);
+
+            if let ClientMessages::Hello { version } = message {
+                if version != PROTOCOL_VERSION {
+                    println!(
+                        "Client {} has protocol version {:x}, expected {:x}",
+                        client_id, version, PROTOCOL_VERSION
+                    );
+                    disconnect_client(
+                        &mut server,
+                        &mut pending_disconnects,
+                        client_id,
+                        DisconnectionReason::VersionMismatch,
+                    );
+                    break;
+                }
+
+                client_entities.verified_clients.insert(client_id);
+                let welcome = ServerMessages::Welcome;
+                if let Ok(message) = encode_message(&welcome) {
+                    server.send_message(client_id, welcome.channel(), message);
+                }
+                continue;
+            }
+
+            // nothing else is trusted to decode correctly before the handshake
+            if !client_entities.verified_clients.contains(&client_id) {
+                println!("Client {} sent {:?} before Hello", client_id, message);
+                continue;
+            }
+
             if let Some(entity) = client_entities.player_entity_map.get(&client_id) {
                 match message {
-                    ClientMessages::Move { x, y } => {
-                        commands
-                            .entity(*entity)
-                            .insert(Transform::from_xyz(x, y, 0.));
+                    // handled above
+                    ClientMessages::Hello { .. } => {}
+                    // simulated by the movement system
+                    ClientMessages::Input { inputs } => {
+                        player_inputs.send(PlayerInputEvent {
+                            entity: *entity,
+                            inputs,
+                        });
                     }
-                    ClientMessages::Ready => todo!("Handle ready message"),
-                    ClientMessages::RequestEntityInfo { entity } => {
-                        entity_info_request.send(SendEntityInfoEvent { client_id, entity });
+                    ClientMessages::SnapshotAck { tick } => {
+                        snapshot_acks.send(SnapshotAckEvent {
+                            entity: *entity,
+                            tick,
+                        });
+                    }
+                    // the client has loaded the map, start sending it the world
+                    ClientMessages::Ready => {
+                        commands.entity(*entity).remove::<LoadingMap>();
+                    }
+                    ClientMessages::RequestEntityInfo { entity: net_id } => {
+                        entity_info_request.send(SendEntityInfoEvent {
+                            client_id,
+                            player: *entity,
+                            entity: net_id,
+                        });
                     }
                     ClientMessages::Disconnect => {
                         // todo: handle remove children on map instance
@@ -183,15 +441,74 @@ fn handle_client_messages(
                     // user selected or unselected a target
                     ClientMessages::Target { target } => {
                         println!("Target: {:?}", target);
-                        if let Some(target_entity) = target {
+                        let target_entity = target
+                            .and_then(|net_id| resolve_on_map(&net_ids, &parents, *entity, net_id));
+                        if let Some(target_entity) = target_entity {
                             commands.entity(*entity).insert(Target(target_entity));
                             continue;
                         }
 
                         commands.entity(*entity).remove::<Target>();
                     }
-                    ClientMessages::Interact { entity } => {
-                        println!("Interact with {:?}", entity);
+                    ClientMessages::Interact { entity: net_id } => {
+                        println!("Interact with {:?}", net_id);
+                        if let Some(target) = resolve_on_map(&net_ids, &parents, *entity, net_id) {
+                            interactions.send(EntityInteractionEvent {
+                                target,
+                                source: *entity,
+                            });
+                        }
+                    }
+                    ClientMessages::CastAbility { ability, target } => {
+                        // targets the player can't see count as no target
+                        let target = target
+                            .and_then(|net_id| resolve_on_map(&net_ids, &parents, *entity, net_id));
+                        casts.send(CastAbilityEvent {
+                            caster: *entity,
+                            ability,
+                            target,
+                        });
+                    }
+                    ClientMessages::Chat { channel, message } => {
+                        chat.send(ChatEvent {
+                            entity: *entity,
+                            channel,
+                            message,
+                        });
+                    }
+                    ClientMessages::Command { text } => {
+                        command_texts.send(CommandTextEvent {
+                            entity: *entity,
+                            text,
+                        });
+                    }
+                    ClientMessages::ListCharacters => {
+                        character_requests.send(CharacterRequestEvent {
+                            client_id,
+                            entity: *entity,
+                            request: CharacterRequest::List,
+                        });
+                    }
+                    ClientMessages::CreateCharacter { name, class } => {
+                        character_requests.send(CharacterRequestEvent {
+                            client_id,
+                            entity: *entity,
+                            request: CharacterRequest::Create { name, class },
+                        });
+                    }
+                    ClientMessages::DeleteCharacter { name } => {
+                        character_requests.send(CharacterRequestEvent {
+                            client_id,
+                            entity: *entity,
+                            request: CharacterRequest::Delete { name },
+                        });
+                    }
+                    ClientMessages::SelectCharacter { name } => {
+                        character_requests.send(CharacterRequestEvent {
+                            client_id,
+                            entity: *entity,
+                            request: CharacterRequest::Select { name },
+                        });
                     }
                 }
             }
@@ -199,13 +516,55 @@ fn handle_client_messages(
     }
 }
 
-fn disconnect_clients_on_exit(exit: EventReader<AppExit>, mut server: ResMut<RenetServer>) {
-    if !exit.is_empty() {
-        let msg = ServerMessages::Disconnect {
-            reason: tiled_game::network::messages::server::DisconnectionReason::ServerShutdown,
-        };
-        let disconnect_msg = bincode::serialize(&msg).unwrap();
-        server.broadcast_message(DefaultChannel::ReliableUnordered, disconnect_msg);
-        server.disconnect_all();
+// Entity behind the id, only if it is on the same map instance as the player
+// Clients must not be able to reach entities on other maps by guessing ids
+pub fn resolve_on_map(
+    net_ids: &NetIds,
+    parents: &Query<&Parent>,
+    player: Entity,
+    net_id: NetId,
+) -> Option<Entity> {
+    let entity = net_ids.entity(net_id)?;
+    let map_instance = parents.get(entity).ok()?.get();
+
+    (parents.get(player).ok()?.get() == map_instance).then_some(entity)
+}
+
+// Tells the client why, the connection is closed by close_pending_disconnects once that had time to arrive
+pub fn disconnect_client(
+    server: &mut RenetServer,
+    pending_disconnects: &mut PendingDisconnects,
+    client_id: u64,
+    reason: DisconnectionReason,
+) {
+    if pending_disconnects.contains(client_id) {
+        return;
     }
+
+    println!("Disconnecting client {}: {:?}", client_id, reason);
+
+    let msg = ServerMessages::Disconnect { reason };
+    if let Ok(encoded) = encode_message(&msg) {
+        server.send_message(client_id, msg.channel(), encoded);
+    }
+
+    pending_disconnects
+        .0
+        .insert(client_id, Instant::now() + DISCONNECT_GRACE);
+}
+
+fn close_pending_disconnects(
+    mut server: ResMut<RenetServer>,
+    mut pending_disconnects: ResMut<PendingDisconnects>,
+) {
+    let now = Instant::now();
+
+    pending_disconnects.0.retain(|client_id, close_at| {
+        if *close_at > now {
+            return true;
+        }
+
+        server.disconnect(*client_id);
+        false
+    });
 }
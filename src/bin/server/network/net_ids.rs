@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use tiled_game::{
+    components::ThreatMap,
+    network::{messages::server::NetThreatMap, net_id::NetId},
+};
+
+// Ids handed out to replicated entities and the entities behind them
+#[derive(Resource)]
+pub struct NetIds {
+    next: u64,
+    entities: HashMap<NetId, Entity>,
+    ids: HashMap<Entity, NetId>,
+}
+
+impl Default for NetIds {
+    fn default() -> Self {
+        Self {
+            next: 1,
+            entities: HashMap::new(),
+            ids: HashMap::new(),
+        }
+    }
+}
+
+impl NetIds {
+    // New id for the entity, the caller inserts it as a component
+    pub fn allocate(&mut self, entity: Entity) -> NetId {
+        let net_id = NetId(self.next);
+        self.next += 1;
+
+        self.entities.insert(net_id, entity);
+        self.ids.insert(entity, net_id);
+        net_id
+    }
+
+    pub fn entity(&self, net_id: NetId) -> Option<Entity> {
+        self.entities.get(&net_id).copied()
+    }
+
+    pub fn net_id(&self, entity: Entity) -> Option<NetId> {
+        self.ids.get(&entity).copied()
+    }
+
+    // Threat towards entities without an id is left out
+    pub fn threat_map(&self, threat: &ThreatMap) -> NetThreatMap {
+        threat
+            .iter()
+            .filter_map(|(entity, value)| Some((self.net_id(*entity)?, *value)))
+            .collect()
+    }
+}
+
+// Despawned entities can't be looked up anymore, even if bevy reuses their index
+pub fn forget_net_ids(mut net_ids: ResMut<NetIds>, mut removed: RemovedComponents<NetId>) {
+    for entity in removed.iter() {
+        if let Some(net_id) = net_ids.ids.remove(&entity) {
+            net_ids.entities.remove(&net_id);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::{collections::HashMap, time::Instant};
+
+use serde::Deserialize;
+use tiled_game::network::messages::client::ClientMessages;
+
+// Limit of the bucket messages of one type share
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct RateLimit {
+    // Messages per second a client may send on average
+    pub per_second: f32,
+    // Messages a client may send at once after being quiet
+    pub burst: f32,
+}
+
+impl RateLimit {
+    pub fn new(per_second: f32, burst: f32) -> Self {
+        Self { per_second, burst }
+    }
+}
+
+// Name the rate limits and metrics use for the message type
+pub fn message_name(message: &ClientMessages) -> &'static str {
+    match message {
+        ClientMessages::Hello { .. } => "Hello",
+        ClientMessages::Ready => "Ready",
+        ClientMessages::Disconnect => "Disconnect",
+        ClientMessages::Target { .. } => "Target",
+        ClientMessages::Input { .. } => "Input",
+        ClientMessages::SnapshotAck { .. } => "SnapshotAck",
+        ClientMessages::RequestEntityInfo { .. } => "RequestEntityInfo",
+        ClientMessages::Interact { .. } => "Interact",
+        ClientMessages::CastAbility { .. } => "CastAbility",
+        ClientMessages::Chat { .. } => "Chat",
+        ClientMessages::Command { .. } => "Command",
+        ClientMessages::ListCharacters => "ListCharacters",
+        ClientMessages::CreateCharacter { .. } => "CreateCharacter",
+        ClientMessages::DeleteCharacter { .. } => "DeleteCharacter",
+        ClientMessages::SelectCharacter { .. } => "SelectCharacter",
+    }
+}
+
+// What happens to a message after checking it against its bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    // Over the limit, the message is ignored
+    Drop,
+    // Kept flooding until a whole burst was dropped
+    Kick,
+}
+
+struct TokenBucket {
+    // Negative while the client owes tokens for dropped messages
+    tokens: f32,
+    refilled: Instant,
+}
+
+// Token buckets of a single client, one per message type
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: HashMap<&'static str, TokenBucket>,
+}
+
+impl RateLimiter {
+    // Takes a token for the message
+    // Dropped messages still cost one, so short spikes only lose messages
+    // while a client that keeps flooding runs into debt and gets kicked
+    pub fn check(&mut self, name: &'static str, limit: RateLimit, now: Instant) -> Verdict {
+        let bucket = self.buckets.entry(name).or_insert(TokenBucket {
+            tokens: limit.burst,
+            refilled: now,
+        });
+
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f32();
+        bucket.tokens = (bucket.tokens + elapsed * limit.per_second).min(limit.burst);
+        bucket.refilled = now;
+
+        let verdict = if bucket.tokens >= 1. {
+            Verdict::Allow
+        } else if bucket.tokens > -limit.burst {
+            Verdict::Drop
+        } else {
+            Verdict::Kick
+        };
+
+        bucket.tokens -= 1.;
+        verdict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        per_second: 10.,
+        burst: 5.,
+    };
+
+    #[test]
+    fn bursts_are_allowed() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..5 {
+            assert_eq!(limiter.check("Chat", LIMIT, now), Verdict::Allow);
+        }
+        assert_eq!(limiter.check("Chat", LIMIT, now), Verdict::Drop);
+
+        // every type has its own bucket
+        assert_eq!(limiter.check("Target", LIMIT, now), Verdict::Allow);
+    }
+
+    #[test]
+    fn spikes_are_dropped_and_floods_kicked() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        let verdicts: Vec<_> = (0..11).map(|_| limiter.check("Chat", LIMIT, now)).collect();
+        assert_eq!(&verdicts[..5], &[Verdict::Allow; 5]);
+        assert_eq!(&verdicts[5..10], &[Verdict::Drop; 5]);
+        assert_eq!(verdicts[10], Verdict::Kick);
+    }
+
+    #[test]
+    fn debt_is_paid_off_over_time() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..8 {
+            limiter.check("Chat", LIMIT, now);
+        }
+
+        // 3 tokens of debt and one for the next message
+        let later = now + Duration::from_millis(350);
+        assert_eq!(limiter.check("Chat", LIMIT, later), Verdict::Drop);
+
+        let much_later = later + Duration::from_secs(1);
+        assert_eq!(limiter.check("Chat", LIMIT, much_later), Verdict::Allow);
+    }
+}
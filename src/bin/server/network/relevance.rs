@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use tiled_game::{
+    components::Unit,
+    network::{messages::server::ServerMessages, net_id::NetId},
+};
+
+use crate::{
+    config::ServerConfig,
+    game::{map::LoadingMap, player::Player, unit::UnitsNearby},
+};
+
+use super::{NetworkClientId, SendServerMessageEvent};
+
+// Entities only leave the view once they are this much further away than the view radius,
+// so units on the edge don't spawn and despawn all the time
+const VIEW_RADIUS_HYSTERESIS: f32 = 1.2;
+
+// Entities the client of a player knows about, with the ids it knows them by
+// Everything else about other entities is only sent for the ones in here
+#[derive(Component, Default)]
+pub struct RelevantEntities(pub HashMap<Entity, NetId>);
+
+impl RelevantEntities {
+    // Players always know about themselves
+    pub fn includes(&self, player: Entity, entity: Entity) -> bool {
+        player == entity || self.0.contains_key(&entity)
+    }
+}
+
+// Spawns entities on the client when they come into view and despawns them when they leave it
+pub fn update_relevance(
+    config: Res<ServerConfig>,
+    units_nearby: Res<UnitsNearby>,
+    units: Query<(&Transform, &Parent, &NetId), (With<Unit>, Without<LoadingMap>)>,
+    mut players: Query<
+        (
+            Entity,
+            &Transform,
+            &Parent,
+            &NetworkClientId,
+            &mut RelevantEntities,
+            Option<&LoadingMap>,
+        ),
+        With<Player>,
+    >,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+) {
+    for (player, transform, map_instance, client_id, mut relevant, loading) in players.iter_mut() {
+        // the client drops everything when it changes maps
+        if loading.is_some() {
+            for (_, net_id) in relevant.0.drain() {
+                server_messages.send(SendServerMessageEvent {
+                    client_id: Some(client_id.0),
+                    message: ServerMessages::Despawn { entity: net_id },
+                });
+            }
+            continue;
+        }
+
+        let position = transform.translation.truncate();
+        let max_radius = config.view_radius * VIEW_RADIUS_HYSTERESIS;
+
+        // the spatial index lags behind a little, the distance is checked again with the transform
+        let in_view: HashMap<Entity, NetId> = units_nearby
+            .within_distance(position, max_radius)
+            .into_iter()
+            .filter_map(|(_, entity)| entity)
+            .filter(|entity| *entity != player)
+            .filter_map(|entity| {
+                let (unit_transform, unit_map_instance, net_id) = units.get(entity).ok()?;
+
+                let radius = if relevant.0.contains_key(&entity) {
+                    max_radius
+                } else {
+                    config.view_radius
+                };
+
+                (unit_map_instance.get() == map_instance.get()
+                    && unit_transform.translation.truncate().distance(position) <= radius)
+                    .then_some((entity, *net_id))
+            })
+            .collect();
+
+        if in_view == relevant.0 {
+            continue;
+        }
+
+        for (entity, net_id) in relevant.0.iter() {
+            if !in_view.contains_key(entity) {
+                server_messages.send(SendServerMessageEvent {
+                    client_id: Some(client_id.0),
+                    message: ServerMessages::Despawn { entity: *net_id },
+                });
+            }
+        }
+
+        for (entity, net_id) in in_view.iter() {
+            if !relevant.0.contains_key(entity) {
+                server_messages.send(SendServerMessageEvent {
+                    client_id: Some(client_id.0),
+                    message: ServerMessages::Spawn { entity: *net_id },
+                });
+            }
+        }
+
+        relevant.0 = in_view;
+    }
+}
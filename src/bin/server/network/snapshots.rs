@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use tiled_game::{
+    components::{Health, Mana},
+    network::{
+        messages::server::ServerMessages,
+        net_id::NetId,
+        snapshot::{EntityState, QuantizedPosition, QuantizedRotation, WorldSnapshot, WorldState},
+    },
+};
+
+use crate::game::{map::LoadingMap, player::Charmed};
+
+use super::{relevance::RelevantEntities, NetworkClientId, SendServerMessageEvent};
+
+// Without acknowledgements for this many ticks the client gets full snapshots again
+const MAX_UNACKNOWLEDGED_SNAPSHOTS: usize = 64;
+
+// Snapshots sent to a client that it may still acknowledge
+#[derive(Component)]
+pub struct SnapshotHistory {
+    next_tick: u32,
+    acknowledged: Option<u32>,
+    sent: VecDeque<(u32, WorldState)>,
+}
+
+impl Default for SnapshotHistory {
+    fn default() -> Self {
+        Self {
+            next_tick: 1,
+            acknowledged: None,
+            sent: VecDeque::new(),
+        }
+    }
+}
+
+impl SnapshotHistory {
+    fn acknowledge(&mut self, tick: u32) {
+        if self
+            .acknowledged
+            .is_some_and(|acknowledged| acknowledged >= tick)
+        {
+            return;
+        }
+
+        if !self.sent.iter().any(|(sent_tick, _)| *sent_tick == tick) {
+            return;
+        }
+
+        // older snapshots are never used as a baseline again
+        self.sent.retain(|(sent_tick, _)| *sent_tick >= tick);
+        self.acknowledged = Some(tick);
+    }
+
+    fn baseline(&self) -> Option<(u32, &WorldState)> {
+        let acknowledged = self.acknowledged?;
+
+        self.sent
+            .iter()
+            .find(|(tick, _)| *tick == acknowledged)
+            .map(|(tick, state)| (*tick, state))
+    }
+
+    fn record(&mut self, state: WorldState) {
+        self.sent.push_back((self.next_tick, state));
+        self.next_tick += 1;
+
+        while self.sent.len() > MAX_UNACKNOWLEDGED_SNAPSHOTS {
+            self.sent.pop_front();
+        }
+    }
+}
+
+#[derive(Event)]
+pub struct SnapshotAckEvent {
+    pub entity: Entity,
+    pub tick: u32,
+}
+
+pub fn receive_snapshot_acks(
+    mut acks: EventReader<SnapshotAckEvent>,
+    mut histories: Query<&mut SnapshotHistory>,
+) {
+    for ack in acks.iter() {
+        if let Ok(mut history) = histories.get_mut(ack.entity) {
+            history.acknowledge(ack.tick);
+        }
+    }
+}
+
+// Sends every player one message per tick with the changes of the entities it knows about
+pub fn send_world_snapshots(
+    time: Res<Time>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    units: Query<(&NetId, &Transform, &Health, &Mana)>,
+    mut players: Query<
+        (
+            Entity,
+            &NetworkClientId,
+            &RelevantEntities,
+            &mut SnapshotHistory,
+            Option<&Charmed>,
+        ),
+        Without<LoadingMap>,
+    >,
+) {
+    for (player, client_id, relevant, mut history, charmed) in players.iter_mut() {
+        let state: WorldState = relevant
+            .0
+            .keys()
+            .chain([&player])
+            .filter_map(|entity| {
+                let (net_id, transform, health, mana) = units.get(*entity).ok()?;
+
+                // the client predicts its own position unless the server controls it
+                let position = (*entity != player || charmed.is_some())
+                    .then(|| QuantizedPosition::new(transform.translation.truncate()));
+
+                Some((
+                    *net_id,
+                    EntityState {
+                        position,
+                        rotation: Some(QuantizedRotation::new(transform.rotation)),
+                        health: Some(health.0),
+                        mana: Some(mana.0),
+                    },
+                ))
+            })
+            .collect();
+
+        let snapshot = WorldSnapshot::delta(
+            history.next_tick,
+            time.elapsed_seconds_f64(),
+            history.baseline(),
+            &state,
+        );
+
+        if snapshot.is_empty() && snapshot.baseline.is_some() {
+            continue;
+        }
+
+        history.record(state);
+
+        server_messages.send(SendServerMessageEvent {
+            client_id: Some(client_id.0),
+            message: ServerMessages::Snapshot { snapshot },
+        });
+    }
+}
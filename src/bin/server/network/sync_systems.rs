@@ -1,185 +1,138 @@
 use bevy::prelude::*;
 use tiled_game::{
     components::*,
-    network::messages::server::{ServerMessages, Vitals},
+    network::{
+        messages::server::{ServerMessages, Vitals},
+        net_id::NetId,
+    },
 };
 
 use crate::game::{
-    combat::LeaveCombatEvent,
+    abilities::{Abilities, CastEvent},
+    auras::{AuraEvent, Auras},
+    combat::{DamageTakenEvent, LeaveCombatEvent},
+    experience::LevelUpEvent,
     map::DespawnEvent,
     npc::{Enemy, NPC},
-    player::{Charmed, Player},
+    player::Player,
+    stats::Stats,
     unit::DeathEvent,
 };
 
-use super::{NetworkClientId, SendServerMessageEvent};
+use super::{
+    net_ids::NetIds, relevance::RelevantEntities, resolve_on_map, NetworkClientId,
+    SendServerMessageEvent,
+};
 
 pub fn send_threat(
-    threats: Query<(Entity, &Threat, &Parent), (With<NPC>, Changed<Threat>)>,
+    threats: Query<(Entity, &NetId, &Threat), (With<NPC>, Changed<Threat>)>,
+    net_ids: Res<NetIds>,
     mut server_message: EventWriter<SendServerMessageEvent>,
-    players: Query<(&NetworkClientId, &Parent), With<Player>>,
+    players: Query<(Entity, &NetworkClientId, &RelevantEntities), With<Player>>,
 ) {
-    for (entity, threat, creature_map_instance) in threats.iter() {
-        for (client_id, player_map_instance) in players.iter() {
-            if player_map_instance.get() != creature_map_instance.get() {
-                continue;
-            }
-
+    for (entity, net_id, threat) in threats.iter() {
+        for client_id in clients_seeing(&players, entity) {
             server_message.send(SendServerMessageEvent {
-                client_id: Some(client_id.0),
+                client_id: Some(client_id),
                 message: ServerMessages::Threat {
-                    entity,
-                    threat: threat.0.clone(),
+                    entity: *net_id,
+                    threat: net_ids.threat_map(&threat.0),
                 },
             });
         }
     }
 }
 
-pub fn filter_players_on_map_instance<'a>(player: &'a Parent) -> impl FnMut(&'a Parent) -> bool {
-    |unit: &'a Parent| unit.get() == player.get()
+// Clients of the players that know about the entity
+pub fn clients_seeing(
+    players: &Query<(Entity, &NetworkClientId, &RelevantEntities), With<Player>>,
+    entity: Entity,
+) -> Vec<u64> {
+    players
+        .iter()
+        .filter(|(player, _, relevant)| relevant.includes(*player, entity))
+        .map(|(_, client_id, _)| client_id.0)
+        .collect()
 }
 
 pub fn send_exit_combat(
     mut server_message: EventWriter<SendServerMessageEvent>,
     mut exit_combat: EventReader<LeaveCombatEvent>,
-    units: Query<&Parent, With<Unit>>,
-    players: Query<(&NetworkClientId, &Parent), With<Player>>,
+    net_ids: Res<NetIds>,
+    players: Query<(Entity, &NetworkClientId, &RelevantEntities), With<Player>>,
 ) {
     for leave_combat_event in exit_combat.iter() {
-        let unit_exited_combat = units.get(leave_combat_event.entity).ok();
-
-        if unit_exited_combat.is_none() {
+        let Some(net_id) = net_ids.net_id(leave_combat_event.entity) else {
             continue;
-        }
-
-        let unit = unit_exited_combat.unwrap();
-
-        let clients_to_notify = players
-            .iter()
-            .filter(|p| filter_players_on_map_instance(unit)(p.1))
-            .map(|p| p.0);
+        };
 
-        clients_to_notify.for_each(|client| {
+        for client_id in clients_seeing(&players, leave_combat_event.entity) {
             server_message.send(SendServerMessageEvent {
-                client_id: Some(client.0),
+                client_id: Some(client_id),
                 message: ServerMessages::CombatState {
-                    entity: leave_combat_event.entity,
+                    entity: net_id,
                     in_combat: false,
                 },
             });
-        });
+        }
     }
 }
 
 pub fn send_entered_combat(
     mut server_message: EventWriter<SendServerMessageEvent>,
-    entered_combat: Query<(Entity, &Parent), Added<InCombat>>,
-    players: Query<(&NetworkClientId, &Parent), With<Player>>,
+    entered_combat: Query<(Entity, &NetId), Added<InCombat>>,
+    players: Query<(Entity, &NetworkClientId, &RelevantEntities), With<Player>>,
 ) {
-    for (entity, map_instance) in entered_combat.iter() {
-        let clients_to_notify = players
-            .iter()
-            .filter(|p| filter_players_on_map_instance(map_instance)(p.1))
-            .map(|p| p.0);
-
-        clients_to_notify.for_each(|client| {
+    for (entity, net_id) in entered_combat.iter() {
+        for client_id in clients_seeing(&players, entity) {
             server_message.send(SendServerMessageEvent {
-                client_id: Some(client.0),
+                client_id: Some(client_id),
                 message: ServerMessages::CombatState {
-                    entity,
+                    entity: *net_id,
                     in_combat: true,
                 },
             });
-        });
-    }
-}
-
-pub fn send_spawn(
-    spawns: Query<(Entity, &Parent), Or<(Added<Parent>, Changed<Parent>)>>,
-    players: Query<(Entity, &NetworkClientId, &Parent), With<Player>>,
-    mut server_message: EventWriter<SendServerMessageEvent>,
-) {
-    for (entity, map_instance) in spawns.iter() {
-        for (player, client_id, player_map_instance) in players.iter() {
-            // Don't send spawn to the player that spawned
-            if player == entity {
-                continue;
-            }
-
-            // Don't send spawn to players on a different map instance
-            if player_map_instance.get() != map_instance.get() {
-                continue;
-            }
-
-            server_message.send(SendServerMessageEvent {
-                client_id: Some(client_id.0),
-                message: ServerMessages::Spawn { entity },
-            });
         }
     }
 }
 
-// Sends a despawn message to each player on the map
+// Sends a despawn message to each player that knows about the entity
 // When a player or entity leaves the map
 pub fn send_despawn(
     mut server_message: EventWriter<SendServerMessageEvent>,
     mut despawn_events: EventReader<DespawnEvent>,
-    all_players: Query<(Entity, &NetworkClientId, &Parent), With<Player>>,
+    mut players: Query<(&NetworkClientId, &mut RelevantEntities), With<Player>>,
 ) {
     for despawn in despawn_events.iter() {
-        // get players on the map
-        all_players
-            .iter()
-            .filter(|(_, _, map_instance)| map_instance.get() == despawn.map)
-            .for_each(|(_, client_id, _)| {
-                // send despawn event to all players in map
-                server_message.send(SendServerMessageEvent {
-                    client_id: Some(client_id.0),
-                    message: ServerMessages::Despawn {
-                        entity: despawn.entity,
-                    },
-                });
-            });
-    }
-}
+        for (client_id, mut relevant) in players.iter_mut() {
+            let Some(net_id) = relevant.0.remove(&despawn.entity) else {
+                continue;
+            };
 
-// Send entity movements to relevant players
-pub fn send_movement(
-    mut server_messages: EventWriter<SendServerMessageEvent>,
-    moved_entities: Query<(Entity, &Transform, &Parent), Changed<Transform>>,
-    players: Query<(Entity, &Parent, &NetworkClientId, Option<&Charmed>)>,
-) {
-    for (moved_entity, transform, map_instance) in moved_entities.iter() {
-        for (player_entity, player_map_instance, client_id, charmed) in players.iter() {
-            // If player and entity parents match, they are on the same map instance
-            if player_map_instance.get() == map_instance.get()
-            // Don't send movement to the entity that moved
-            // unless it is charmed
-            && (player_entity != moved_entity || charmed.is_some())
-            {
-                server_messages.send(SendServerMessageEvent {
-                    client_id: Some(client_id.0),
-                    message: ServerMessages::Move {
-                        entity: moved_entity,
-                        pos: transform.translation,
-                        rotation: transform.rotation,
-                    },
-                });
-            }
+            server_message.send(SendServerMessageEvent {
+                client_id: Some(client_id.0),
+                message: ServerMessages::Despawn { entity: net_id },
+            });
         }
     }
 }
 
+// A client asked about an entity
 #[derive(Event)]
 pub struct SendEntityInfoEvent {
     pub client_id: u64,
-    pub entity: Entity,
+    // player of the client
+    pub player: Entity,
+    pub entity: NetId,
 }
 
 pub fn send_entity_info(
     mut events: EventReader<SendEntityInfoEvent>,
     mut server_message: EventWriter<SendServerMessageEvent>,
+    net_ids: Res<NetIds>,
+    abilities: Res<Abilities>,
+    parents: Query<&Parent>,
+    auras: Query<&Auras>,
     entities: Query<(
         &Name,
         &Transform,
@@ -187,6 +140,7 @@ pub fn send_entity_info(
         &MaxHealth,
         &Mana,
         &MaxMana,
+        &Level,
         &Unit,
         Option<&Player>,
         Option<&Enemy>,
@@ -195,8 +149,10 @@ pub fn send_entity_info(
     )>,
 ) {
     for event in events.iter() {
-        let entity = event.entity;
-        let entity_ref = entities.get(entity).ok();
+        let net_id = event.entity;
+        let entity = resolve_on_map(&net_ids, &parents, event.player, net_id);
+        let entity_ref = entity.and_then(|entity| entities.get(entity).ok());
+        let client_id = event.client_id;
 
         let event = match entity_ref {
             Some((
@@ -206,6 +162,7 @@ pub fn send_entity_info(
                 max_health,
                 mana,
                 max_mana,
+                level,
                 unit,
                 player,
                 friend,
@@ -214,7 +171,7 @@ pub fn send_entity_info(
             )) => SendServerMessageEvent {
                 client_id: Some(event.client_id),
                 message: ServerMessages::EntityInfo {
-                    entity,
+                    entity: net_id,
                     pos: transform.translation,
                     name: name.to_string(),
                     is_player: player.is_some(),
@@ -223,73 +180,204 @@ pub fn send_entity_info(
                     max_health: max_health.0,
                     mana: mana.0,
                     max_mana: max_mana.0,
+                    level: level.0,
                     unit: unit.0.clone(),
-                    threat: threat.map(|t| Some(t.0.clone())).unwrap_or(None),
+                    threat: threat.map(|t| net_ids.threat_map(&t.0)),
                     interactable: interactable.is_some(),
                     rotation: transform.rotation,
                 },
             },
             _ => {
-                // entity doesn't exist.. send a despawn message
+                // entity doesn't exist or isn't on the map of the player.. send a despawn message
                 SendServerMessageEvent {
                     client_id: Some(event.client_id),
-                    message: ServerMessages::Despawn { entity },
+                    message: ServerMessages::Despawn { entity: net_id },
                 }
             }
         };
 
         server_message.send(event);
+
+        // the auras already on the entity, the client only hears about changes after this
+        let Some(auras) = entity.and_then(|entity| auras.get(entity).ok()) else {
+            continue;
+        };
+
+        for active in auras.0.iter() {
+            let Some(aura) = abilities.aura(&active.id) else {
+                continue;
+            };
+
+            server_message.send(SendServerMessageEvent {
+                client_id: Some(client_id),
+                message: ServerMessages::AuraApplied {
+                    entity: net_id,
+                    aura: aura.id.clone(),
+                    name: aura.name.clone(),
+                    stacks: active.stacks,
+                    duration: active.remaining_secs(),
+                    harmful: aura.harmful,
+                },
+            });
+        }
     }
 }
 
 pub fn send_death_events(
     mut server_messages: EventWriter<SendServerMessageEvent>,
     mut death_events: EventReader<DeathEvent>,
+    net_ids: Res<NetIds>,
+    players: Query<(Entity, &NetworkClientId, &RelevantEntities), With<Player>>,
 ) {
     for death_event in death_events.iter() {
-        server_messages.send(SendServerMessageEvent {
-            client_id: None,
-            message: ServerMessages::Vitals {
-                entity: death_event.entity,
-                vital: Vitals::Dead(true),
-            },
-        });
+        let Some(net_id) = net_ids.net_id(death_event.entity) else {
+            continue;
+        };
+
+        for client_id in clients_seeing(&players, death_event.entity) {
+            server_messages.send(SendServerMessageEvent {
+                client_id: Some(client_id),
+                message: ServerMessages::Vitals {
+                    entity: net_id,
+                    vital: Vitals::Dead(true),
+                },
+            });
+        }
     }
 }
 
-// Send entity health, mana, etc to relevant players
-pub fn send_vitals_changed(
+// Lets everyone around a caster see its cast bar
+pub fn send_cast_events(
     mut server_messages: EventWriter<SendServerMessageEvent>,
-    vitals_changed: Query<
-        (Entity, &Parent, Ref<Health>, Ref<Mana>),
-        Or<(Changed<Health>, Changed<Mana>)>,
-    >,
-    players: Query<(&Parent, &NetworkClientId)>,
+    mut cast_events: EventReader<CastEvent>,
+    net_ids: Res<NetIds>,
+    players: Query<(Entity, &NetworkClientId, &RelevantEntities), With<Player>>,
 ) {
-    for (entity, map_instance, health, mana) in vitals_changed.iter() {
-        for (player_map_instance, client_id) in players.iter() {
-            // If player and entity parents match, they are on the same map instance
-            if player_map_instance.get() == map_instance.get() {
-                if health.is_changed() {
-                    server_messages.send(SendServerMessageEvent {
-                        client_id: Some(client_id.0),
-                        message: ServerMessages::Vitals {
-                            entity,
-                            vital: Vitals::Health(health.0),
-                        },
-                    });
-                }
+    for cast_event in cast_events.iter() {
+        let caster = match cast_event {
+            CastEvent::Started { caster, .. }
+            | CastEvent::Finished { caster }
+            | CastEvent::Interrupted { caster } => *caster,
+        };
 
-                if mana.is_changed() {
-                    server_messages.send(SendServerMessageEvent {
-                        client_id: Some(client_id.0),
-                        message: ServerMessages::Vitals {
-                            entity,
-                            vital: Vitals::Mana(mana.0),
-                        },
-                    });
-                }
-            }
+        let Some(net_id) = net_ids.net_id(caster) else {
+            continue;
+        };
+
+        for client_id in clients_seeing(&players, caster) {
+            let message = match cast_event {
+                CastEvent::Started { name, duration, .. } => ServerMessages::CastStart {
+                    entity: net_id,
+                    name: name.clone(),
+                    duration: *duration,
+                },
+                CastEvent::Finished { .. } => ServerMessages::CastFinish { entity: net_id },
+                CastEvent::Interrupted { .. } => ServerMessages::CastInterrupt { entity: net_id },
+            };
+
+            server_messages.send(SendServerMessageEvent {
+                client_id: Some(client_id),
+                message,
+            });
+        }
+    }
+}
+
+// Lets everyone around a unit see its buffs and debuffs
+pub fn send_aura_events(
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    mut aura_events: EventReader<AuraEvent>,
+    net_ids: Res<NetIds>,
+    players: Query<(Entity, &NetworkClientId, &RelevantEntities), With<Player>>,
+) {
+    for aura_event in aura_events.iter() {
+        let entity = match aura_event {
+            AuraEvent::Applied { entity, .. } | AuraEvent::Removed { entity, .. } => *entity,
+        };
+
+        let Some(net_id) = net_ids.net_id(entity) else {
+            continue;
+        };
+
+        for client_id in clients_seeing(&players, entity) {
+            let message = match aura_event {
+                AuraEvent::Applied {
+                    aura,
+                    name,
+                    stacks,
+                    duration,
+                    harmful,
+                    ..
+                } => ServerMessages::AuraApplied {
+                    entity: net_id,
+                    aura: aura.clone(),
+                    name: name.clone(),
+                    stacks: *stacks,
+                    duration: *duration,
+                    harmful: *harmful,
+                },
+                AuraEvent::Removed { aura, .. } => ServerMessages::AuraRemoved {
+                    entity: net_id,
+                    aura: aura.clone(),
+                },
+            };
+
+            server_messages.send(SendServerMessageEvent {
+                client_id: Some(client_id),
+                message,
+            });
+        }
+    }
+}
+
+// Lets everyone around a unit see the damage it takes and the attacks that didn't land
+pub fn send_damage_events(
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    mut damage_events: EventReader<DamageTakenEvent>,
+    net_ids: Res<NetIds>,
+    players: Query<(Entity, &NetworkClientId, &RelevantEntities), With<Player>>,
+) {
+    for damage_event in damage_events.iter() {
+        let Some(net_id) = net_ids.net_id(damage_event.receiver) else {
+            continue;
+        };
+
+        for client_id in clients_seeing(&players, damage_event.receiver) {
+            server_messages.send(SendServerMessageEvent {
+                client_id: Some(client_id),
+                message: ServerMessages::Damage {
+                    origin: net_ids.net_id(damage_event.origin),
+                    target: net_id,
+                    amount: damage_event.damage,
+                    school: damage_event.school,
+                    result: damage_event.result,
+                },
+            });
+        }
+    }
+}
+
+pub fn send_level_ups(
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    mut level_ups: EventReader<LevelUpEvent>,
+    units: Query<(&NetId, &Level, &Stats)>,
+    players: Query<(Entity, &NetworkClientId, &RelevantEntities), With<Player>>,
+) {
+    for level_up in level_ups.iter() {
+        let Ok((net_id, level, stats)) = units.get(level_up.entity) else {
+            continue;
+        };
+
+        for client_id in clients_seeing(&players, level_up.entity) {
+            server_messages.send(SendServerMessageEvent {
+                client_id: Some(client_id),
+                message: ServerMessages::LevelUp {
+                    entity: *net_id,
+                    level: level.0,
+                    max_health: stats.max_health(),
+                    max_mana: stats.max_mana(),
+                },
+            });
         }
     }
 }
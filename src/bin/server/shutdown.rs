@@ -0,0 +1,257 @@
+/**
+ * Graceful shutdown on SIGINT/SIGTERM or from the admin console
+ *
+ * New connections are refused right away and players get a countdown.
+ * Once it runs out every character is saved and the clients are told to leave,
+ * the server waits for them to go, up to a timeout, before it exits.
+ * A restart does the same and then runs the server binary again.
+ */
+use std::{
+    env, process,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use bevy::{app::AppExit, prelude::*};
+use bevy_renet::renet::RenetServer;
+use tiled_game::network::messages::{
+    encode_message,
+    server::{DisconnectionReason, ServerMessages},
+};
+
+use crate::{
+    config::ServerConfig,
+    game::{
+        map::MapName,
+        player::{save_online_players, OnlinePlayer, Player},
+    },
+    network::SendServerMessageEvent,
+    storage::Storage,
+};
+
+// Asks the server to shut down, later requests are ignored
+#[derive(Event)]
+pub struct ShutdownEvent {
+    pub reason: DisconnectionReason,
+}
+
+#[derive(Resource, Default)]
+pub enum ShutdownState {
+    #[default]
+    Running,
+    // Players are warned, new connections are refused
+    Countdown {
+        reason: DisconnectionReason,
+        timer: Timer,
+        // Seconds left at the last announcement
+        announced: u64,
+    },
+    // Everyone was told to leave, waiting for the clients to go
+    Draining {
+        reason: DisconnectionReason,
+        timer: Timer,
+    },
+    // The transport sends the last disconnect packets, then the app exits
+    Exiting {
+        reason: DisconnectionReason,
+    },
+}
+
+impl ShutdownState {
+    // Why clients get disconnected, None while the server keeps running
+    pub fn reason(&self) -> Option<DisconnectionReason> {
+        match self {
+            ShutdownState::Running => None,
+            ShutdownState::Countdown { reason, .. }
+            | ShutdownState::Draining { reason, .. }
+            | ShutdownState::Exiting { reason } => Some(*reason),
+        }
+    }
+}
+
+// Set once the app stopped for a restart, the world is gone by the time main can look
+static RESTART: AtomicBool = AtomicBool::new(false);
+
+// Number of signals received so far, set by the signal handler
+#[derive(Resource)]
+struct Signals(Arc<AtomicUsize>);
+
+pub struct ShutdownPlugin;
+
+impl Plugin for ShutdownPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ShutdownEvent>()
+            .init_resource::<ShutdownState>()
+            .insert_resource(Signals(handle_signals()))
+            .add_systems(
+                Update,
+                (receive_signals, begin_shutdown, run_shutdown).chain(),
+            );
+    }
+}
+
+fn handle_signals() -> Arc<AtomicUsize> {
+    let signals = Arc::new(AtomicUsize::new(0));
+    let counter = signals.clone();
+
+    let result = ctrlc::set_handler(move || {
+        // a second signal means the graceful way takes too long
+        if counter.fetch_add(1, Ordering::SeqCst) > 0 {
+            println!("Received another signal, exiting without saving");
+            process::exit(1);
+        }
+    });
+
+    if let Err(e) = result {
+        println!("Could not install the signal handler: {}", e);
+    }
+
+    signals
+}
+
+fn receive_signals(signals: Res<Signals>, mut shutdown: EventWriter<ShutdownEvent>) {
+    if signals.0.load(Ordering::SeqCst) > 0 {
+        shutdown.send(ShutdownEvent {
+            reason: DisconnectionReason::ServerShutdown,
+        });
+    }
+}
+
+fn begin_shutdown(
+    config: Res<ServerConfig>,
+    mut events: EventReader<ShutdownEvent>,
+    mut state: ResMut<ShutdownState>,
+) {
+    let Some(reason) = events.iter().map(|event| event.reason).next() else {
+        return;
+    };
+
+    if !matches!(*state, ShutdownState::Running) {
+        return;
+    }
+
+    println!(
+        "Shutting down ({:?}) in {} seconds",
+        reason, config.shutdown_countdown
+    );
+
+    *state = ShutdownState::Countdown {
+        reason,
+        timer: Timer::new(config.shutdown_countdown_duration(), TimerMode::Once),
+        // the first announcement goes out right away
+        announced: u64::MAX,
+    };
+}
+
+fn run_shutdown(
+    time: Res<Time>,
+    config: Res<ServerConfig>,
+    storage: Res<Storage>,
+    mut state: ResMut<ShutdownState>,
+    mut server: ResMut<RenetServer>,
+    mut server_messages: EventWriter<SendServerMessageEvent>,
+    mut exit: EventWriter<AppExit>,
+    players: Query<OnlinePlayer, With<Player>>,
+    map_instances: Query<&MapName>,
+) {
+    match &mut *state {
+        ShutdownState::Running => {}
+        ShutdownState::Countdown {
+            reason,
+            timer,
+            announced,
+        } => {
+            timer.tick(time.delta());
+
+            if !timer.finished() {
+                let left = timer.remaining_secs().ceil() as u64;
+                if left < *announced && (left <= 5 || left % 10 == 0 || *announced == u64::MAX) {
+                    *announced = left;
+                    let message = match reason {
+                        DisconnectionReason::ServerRestart => {
+                            format!("The server restarts in {} seconds", left)
+                        }
+                        _ => format!("The server shuts down in {} seconds", left),
+                    };
+                    server_messages.send(SendServerMessageEvent {
+                        client_id: None,
+                        message: ServerMessages::system_chat(message),
+                    });
+                }
+                return;
+            }
+
+            let reason = *reason;
+            let saved = save_online_players(&storage, &players, &map_instances);
+            println!("Saved {} characters", saved);
+
+            // sent right away so it isn't queued behind anything sent this frame
+            let message = ServerMessages::Disconnect { reason };
+            if let Ok(encoded) = encode_message(&message) {
+                server.broadcast_message(message.channel(), encoded);
+            }
+
+            *state = ShutdownState::Draining {
+                reason,
+                timer: Timer::new(config.shutdown_drain_duration(), TimerMode::Once),
+            };
+        }
+        ShutdownState::Draining { reason, timer } => {
+            timer.tick(time.delta());
+
+            // clients disconnect on their own once the message arrived
+            let connected = server.clients_id().len();
+            if connected > 0 && !timer.finished() {
+                return;
+            }
+
+            if connected > 0 {
+                println!("{} clients did not leave in time", connected);
+            }
+
+            server.disconnect_all();
+            *state = ShutdownState::Exiting { reason: *reason };
+        }
+        ShutdownState::Exiting { reason } => {
+            println!("Server stopped");
+            if matches!(reason, DisconnectionReason::ServerRestart) {
+                RESTART.store(true, Ordering::SeqCst);
+            }
+            exit.send(AppExit);
+        }
+    }
+}
+
+// Runs the server again with the same arguments if it stopped for a restart
+// Only returns when it stopped for good
+pub fn restart_if_requested() {
+    if !RESTART.load(Ordering::SeqCst) {
+        return;
+    }
+
+    // the path it was started with rather than current_exe, so a rebuilt binary is picked up
+    let mut args = env::args_os();
+    let Some(program) = args.next() else {
+        println!("Could not restart, the server binary is unknown");
+        process::exit(1);
+    };
+
+    println!("Restarting");
+    let mut command = process::Command::new(program);
+    command.args(args);
+
+    // replaces this process, the socket and database were closed with the app
+    #[cfg(unix)]
+    let error = std::os::unix::process::CommandExt::exec(&mut command);
+
+    #[cfg(not(unix))]
+    let error = match command.status() {
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(e) => e,
+    };
+
+    println!("Could not restart: {}", error);
+    process::exit(1);
+}
@@ -0,0 +1,78 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use super::{CharacterRecord, CharacterStorage};
+
+// Keeps everything in memory
+// Used for tests and throwaway servers
+#[derive(Default)]
+pub struct MemoryStorage {
+    characters: Mutex<HashMap<(u64, String), CharacterRecord>>,
+    bans: Mutex<HashSet<u64>>,
+}
+
+impl CharacterStorage for MemoryStorage {
+    fn load_character(
+        &self,
+        account_id: u64,
+        name: &str,
+    ) -> anyhow::Result<Option<CharacterRecord>> {
+        let characters = self.characters.lock().unwrap();
+        Ok(characters.get(&(account_id, name.to_string())).cloned())
+    }
+
+    fn save_character(&self, character: &CharacterRecord) -> anyhow::Result<()> {
+        let mut characters = self.characters.lock().unwrap();
+        characters.insert(
+            (character.account_id, character.name.clone()),
+            character.clone(),
+        );
+        Ok(())
+    }
+
+    fn list_characters(&self, account_id: u64) -> anyhow::Result<Vec<CharacterRecord>> {
+        let characters = self.characters.lock().unwrap();
+        let mut list: Vec<CharacterRecord> = characters
+            .values()
+            .filter(|character| character.account_id == account_id)
+            .cloned()
+            .collect();
+
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(list)
+    }
+
+    fn create_character(&self, character: &CharacterRecord) -> anyhow::Result<bool> {
+        let mut characters = self.characters.lock().unwrap();
+
+        if characters.keys().any(|(_, name)| *name == character.name) {
+            return Ok(false);
+        }
+
+        characters.insert(
+            (character.account_id, character.name.clone()),
+            character.clone(),
+        );
+        Ok(true)
+    }
+
+    fn delete_character(&self, account_id: u64, name: &str) -> anyhow::Result<bool> {
+        let mut characters = self.characters.lock().unwrap();
+        Ok(characters.remove(&(account_id, name.to_string())).is_some())
+    }
+
+    fn ban_account(&self, account_id: u64) -> anyhow::Result<()> {
+        self.bans.lock().unwrap().insert(account_id);
+        Ok(())
+    }
+
+    fn unban_account(&self, account_id: u64) -> anyhow::Result<bool> {
+        Ok(self.bans.lock().unwrap().remove(&account_id))
+    }
+
+    fn is_banned(&self, account_id: u64) -> anyhow::Result<bool> {
+        Ok(self.bans.lock().unwrap().contains(&account_id))
+    }
+}
@@ -0,0 +1,210 @@
+/**
+ * Persistence of accounts and characters
+ *
+ * Game systems only talk to the CharacterStorage trait,
+ * so the backend can be swapped without touching them.
+ */
+mod memory;
+mod sqlite;
+
+use std::path::Path;
+
+use bevy::prelude::Resource;
+
+pub use memory::MemoryStorage;
+pub use sqlite::SqliteStorage;
+
+// Where new characters start
+pub const START_MAP: &str = "start.tmx";
+pub const START_POSITION: (f32, f32) = (30., 30.);
+
+// Use this as database path to keep everything in memory
+pub const IN_MEMORY_DATABASE: &str = ":memory:";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CharacterRecord {
+    pub account_id: u64,
+    pub name: String,
+
+    // Sprite sheet of the character
+    pub class: String,
+
+    // Characters join the global instance of the map
+    pub map: String,
+
+    pub x: f32,
+    pub y: f32,
+
+    pub health: i32,
+    pub mana: i32,
+
+    pub level: u32,
+    // Towards the next level
+    pub experience: u32,
+}
+
+impl CharacterRecord {
+    pub fn new(account_id: u64, name: String, class: String, health: i32, mana: i32) -> Self {
+        Self {
+            account_id,
+            name,
+            class,
+            map: START_MAP.to_string(),
+            x: START_POSITION.0,
+            y: START_POSITION.1,
+            health,
+            mana,
+            level: 1,
+            experience: 0,
+        }
+    }
+}
+
+pub trait CharacterStorage: Send + Sync {
+    fn load_character(
+        &self,
+        account_id: u64,
+        name: &str,
+    ) -> anyhow::Result<Option<CharacterRecord>>;
+
+    fn save_character(&self, character: &CharacterRecord) -> anyhow::Result<()>;
+
+    fn list_characters(&self, account_id: u64) -> anyhow::Result<Vec<CharacterRecord>>;
+
+    // Names are unique across all accounts
+    // Returns false when the name is already taken
+    fn create_character(&self, character: &CharacterRecord) -> anyhow::Result<bool>;
+
+    // Returns false when the account has no character with that name
+    fn delete_character(&self, account_id: u64, name: &str) -> anyhow::Result<bool>;
+
+    // Banned accounts are disconnected as soon as they connect
+    fn ban_account(&self, account_id: u64) -> anyhow::Result<()>;
+
+    // Returns false when the account wasn't banned
+    fn unban_account(&self, account_id: u64) -> anyhow::Result<bool>;
+
+    fn is_banned(&self, account_id: u64) -> anyhow::Result<bool>;
+}
+
+#[derive(Resource)]
+pub struct Storage(pub Box<dyn CharacterStorage>);
+
+impl Storage {
+    pub fn open(database: &Path) -> anyhow::Result<Self> {
+        if database == Path::new(IN_MEMORY_DATABASE) {
+            println!("Using in-memory storage, nothing will be persisted");
+            return Ok(Self(Box::new(MemoryStorage::default())));
+        }
+
+        println!("Using database {:?}", database);
+        Ok(Self(Box::new(SqliteStorage::open(database)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn character(account_id: u64, name: &str) -> CharacterRecord {
+        CharacterRecord::new(account_id, name.to_string(), "Warrior".to_string(), 15, 15)
+    }
+
+    // Every backend has to behave the same
+    fn backends() -> Vec<Box<dyn CharacterStorage>> {
+        vec![
+            Box::new(MemoryStorage::default()),
+            Box::new(SqliteStorage::open(Path::new(IN_MEMORY_DATABASE)).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        for storage in backends() {
+            let mut record = character(1, "Alice");
+            assert!(storage.create_character(&record).unwrap());
+            assert_eq!(
+                storage.load_character(1, "Alice").unwrap(),
+                Some(record.clone())
+            );
+
+            record.map = "dungeon.tmx".to_string();
+            record.x = 12.5;
+            record.y = -3.25;
+            record.health = 7;
+            record.mana = 3;
+            record.level = 4;
+            record.experience = 120;
+            storage.save_character(&record).unwrap();
+
+            assert_eq!(storage.load_character(1, "Alice").unwrap(), Some(record));
+            assert_eq!(storage.load_character(2, "Alice").unwrap(), None);
+            assert_eq!(storage.load_character(1, "Bob").unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn list_only_returns_the_account_sorted_by_name() {
+        for storage in backends() {
+            for (account_id, name) in [(1, "Carol"), (2, "Bob"), (1, "Alice")] {
+                assert!(storage
+                    .create_character(&character(account_id, name))
+                    .unwrap());
+            }
+
+            let names: Vec<String> = storage
+                .list_characters(1)
+                .unwrap()
+                .into_iter()
+                .map(|character| character.name)
+                .collect();
+
+            assert_eq!(names, ["Alice", "Carol"]);
+            assert!(storage.list_characters(3).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn names_are_unique_across_accounts() {
+        for storage in backends() {
+            assert!(storage.create_character(&character(1, "Alice")).unwrap());
+            assert!(!storage.create_character(&character(2, "Alice")).unwrap());
+            assert!(!storage.create_character(&character(1, "Alice")).unwrap());
+
+            assert!(storage.list_characters(2).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn delete_only_removes_own_characters() {
+        for storage in backends() {
+            assert!(storage.create_character(&character(1, "Alice")).unwrap());
+
+            assert!(!storage.delete_character(2, "Alice").unwrap());
+            assert!(storage.delete_character(1, "Alice").unwrap());
+            assert!(!storage.delete_character(1, "Alice").unwrap());
+
+            assert_eq!(storage.load_character(1, "Alice").unwrap(), None);
+            assert!(storage.list_characters(1).unwrap().is_empty());
+
+            // the name is free again
+            assert!(storage.create_character(&character(2, "Alice")).unwrap());
+        }
+    }
+
+    #[test]
+    fn bans() {
+        for storage in backends() {
+            assert!(!storage.is_banned(1).unwrap());
+
+            storage.ban_account(1).unwrap();
+            storage.ban_account(1).unwrap();
+            assert!(storage.is_banned(1).unwrap());
+            assert!(!storage.is_banned(2).unwrap());
+
+            assert!(storage.unban_account(1).unwrap());
+            assert!(!storage.unban_account(1).unwrap());
+            assert!(!storage.is_banned(1).unwrap());
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::{path::Path, sync::Mutex};
+
+use anyhow::Context;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use super::{CharacterRecord, CharacterStorage};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS characters (
+    account_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    class TEXT NOT NULL,
+    map TEXT NOT NULL,
+    x REAL NOT NULL,
+    y REAL NOT NULL,
+    health INTEGER NOT NULL,
+    mana INTEGER NOT NULL,
+    level INTEGER NOT NULL DEFAULT 1,
+    experience INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (account_id, name)
+);
+CREATE UNIQUE INDEX IF NOT EXISTS characters_name ON characters (name);
+CREATE TABLE IF NOT EXISTS bans (
+    account_id INTEGER PRIMARY KEY
+);
+";
+
+// Columns added after the first release, databases created before get them on open
+const ADDED_COLUMNS: [(&str, &str); 2] = [
+    ("level", "INTEGER NOT NULL DEFAULT 1"),
+    ("experience", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+// Databases created before still have a map_instance column, it's left alone
+const CHARACTER_COLUMNS: &str =
+    "account_id, name, class, map, x, y, health, mana, level, experience";
+
+pub struct SqliteStorage {
+    // rusqlite connections can be sent between threads but not shared
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)
+            .with_context(|| format!("Could not open database {:?}", path))?;
+
+        connection
+            .execute_batch(SCHEMA)
+            .context("Could not create database schema")?;
+        add_missing_columns(&connection).context("Could not update database schema")?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+fn add_missing_columns(connection: &Connection) -> rusqlite::Result<()> {
+    let mut statement = connection.prepare("SELECT name FROM pragma_table_info('characters')")?;
+    let columns = statement
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    for (column, definition) in ADDED_COLUMNS {
+        if !columns.iter().any(|existing| existing == column) {
+            println!("Adding column {} to the characters table", column);
+            connection.execute_batch(&format!(
+                "ALTER TABLE characters ADD COLUMN {} {}",
+                column, definition
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+// SQLite only knows signed integers, the u64 values are stored bit for bit
+fn character_from_row(row: &Row) -> rusqlite::Result<CharacterRecord> {
+    Ok(CharacterRecord {
+        account_id: row.get::<_, i64>(0)? as u64,
+        name: row.get(1)?,
+        class: row.get(2)?,
+        map: row.get(3)?,
+        x: row.get::<_, f64>(4)? as f32,
+        y: row.get::<_, f64>(5)? as f32,
+        health: row.get(6)?,
+        mana: row.get(7)?,
+        level: row.get(8)?,
+        experience: row.get(9)?,
+    })
+}
+
+impl CharacterStorage for SqliteStorage {
+    fn load_character(
+        &self,
+        account_id: u64,
+        name: &str,
+    ) -> anyhow::Result<Option<CharacterRecord>> {
+        let connection = self.connection.lock().unwrap();
+
+        let character = connection
+            .query_row(
+                &format!(
+                    "SELECT {} FROM characters WHERE account_id = ?1 AND name = ?2",
+                    CHARACTER_COLUMNS
+                ),
+                params![account_id as i64, name],
+                character_from_row,
+            )
+            .optional()?;
+
+        Ok(character)
+    }
+
+    fn save_character(&self, character: &CharacterRecord) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+
+        connection.execute(
+            &format!(
+                "INSERT OR REPLACE INTO characters ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                CHARACTER_COLUMNS
+            ),
+            params![
+                character.account_id as i64,
+                character.name,
+                character.class,
+                character.map,
+                character.x as f64,
+                character.y as f64,
+                character.health,
+                character.mana,
+                character.level,
+                character.experience,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn list_characters(&self, account_id: u64) -> anyhow::Result<Vec<CharacterRecord>> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut statement = connection.prepare(&format!(
+            "SELECT {} FROM characters WHERE account_id = ?1 ORDER BY name",
+            CHARACTER_COLUMNS
+        ))?;
+
+        let characters = statement
+            .query_map(params![account_id as i64], character_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(characters)
+    }
+
+    fn create_character(&self, character: &CharacterRecord) -> anyhow::Result<bool> {
+        let connection = self.connection.lock().unwrap();
+
+        // the unique index on name makes this a no-op for taken names
+        let inserted = connection.execute(
+            &format!(
+                "INSERT OR IGNORE INTO characters ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                CHARACTER_COLUMNS
+            ),
+            params![
+                character.account_id as i64,
+                character.name,
+                character.class,
+                character.map,
+                character.x as f64,
+                character.y as f64,
+                character.health,
+                character.mana,
+                character.level,
+                character.experience,
+            ],
+        )?;
+
+        Ok(inserted == 1)
+    }
+
+    fn delete_character(&self, account_id: u64, name: &str) -> anyhow::Result<bool> {
+        let connection = self.connection.lock().unwrap();
+
+        let deleted = connection.execute(
+            "DELETE FROM characters WHERE account_id = ?1 AND name = ?2",
+            params![account_id as i64, name],
+        )?;
+
+        Ok(deleted == 1)
+    }
+
+    fn ban_account(&self, account_id: u64) -> anyhow::Result<()> {
+        let connection = self.connection.lock().unwrap();
+
+        connection.execute(
+            "INSERT OR IGNORE INTO bans (account_id) VALUES (?1)",
+            params![account_id as i64],
+        )?;
+
+        Ok(())
+    }
+
+    fn unban_account(&self, account_id: u64) -> anyhow::Result<bool> {
+        let connection = self.connection.lock().unwrap();
+
+        let deleted = connection.execute(
+            "DELETE FROM bans WHERE account_id = ?1",
+            params![account_id as i64],
+        )?;
+
+        Ok(deleted == 1)
+    }
+
+    fn is_banned(&self, account_id: u64) -> anyhow::Result<bool> {
+        let connection = self.connection.lock().unwrap();
+
+        let banned = connection
+            .query_row(
+                "SELECT 1 FROM bans WHERE account_id = ?1",
+                params![account_id as i64],
+                |_| Ok(()),
+            )
+            .optional()?;
+
+        Ok(banned.is_some())
+    }
+}
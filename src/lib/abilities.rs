@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+
+use crate::combat::DamageSchool;
+
+// Who an ability can be used on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AbilityTarget {
+    // The hostile unit the caster targets
+    Enemy,
+    // The targeted unit if it isn't hostile, the caster otherwise
+    Friendly,
+    // Always the caster
+    Caster,
+}
+
+// What happens to the target once the ability goes off
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AbilityEffect {
+    Damage(i32),
+    Heal(i32),
+    // Id of an aura from the abilities file
+    ApplyAura(String),
+    // Removes every aura of the category from the target
+    Dispel(DispelType),
+}
+
+// Categories of auras that can be removed together
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DispelType {
+    Magic,
+    Poison,
+    Curse,
+}
+
+// What an aura does while it is on a unit
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuraEffect {
+    // Every tick, per stack
+    DamageOverTime(i32),
+    HealOverTime(i32),
+    // Damage the shield takes before the unit does, per stack
+    Absorb(i32),
+    // Multiplies the speed on foot
+    Speed(f32),
+    // Multiplies the time between auto attacks, below 1 attacks faster
+    AttackSpeed(f32),
+    // No moving, attacking or casting
+    Stun,
+    // No moving
+    Root,
+}
+
+// What happens when an aura is applied to a unit that already has it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AuraStacking {
+    // The duration starts over
+    #[default]
+    Refresh,
+    // Another stack up to max_stacks, and the duration starts over
+    Stack,
+    // Applying it again does nothing while it lasts
+    Keep,
+}
+
+// A timed effect on a unit, applied by abilities
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Aura {
+    pub id: String,
+    pub name: String,
+    // Seconds
+    pub duration: f32,
+    // Seconds between damage or heal over time ticks
+    #[serde(default = "default_tick_interval")]
+    pub tick_interval: f32,
+    pub effects: Vec<AuraEffect>,
+    // Of the damage over time
+    #[serde(default)]
+    pub school: DamageSchool,
+    #[serde(default)]
+    pub stacking: AuraStacking,
+    #[serde(default = "default_max_stacks")]
+    pub max_stacks: u32,
+    // Auras without a category can't be dispelled
+    #[serde(default)]
+    pub dispel: Option<DispelType>,
+    // Shown as a debuff
+    #[serde(default)]
+    pub harmful: bool,
+}
+
+fn default_tick_interval() -> f32 {
+    1.
+}
+
+fn default_max_stacks() -> u32 {
+    1
+}
+
+// An ability as defined in the abilities file of the server
+// Clients get the same list to fill their action bar
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ability {
+    // Stays the same when the name changes, clients refer to abilities by it
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub mana_cost: i32,
+    // Seconds after the ability went off until it can be used again
+    #[serde(default)]
+    pub cooldown: f32,
+    // Seconds between starting the cast and the ability going off, 0 is instant
+    #[serde(default)]
+    pub cast_time: f32,
+    // Pixels between caster and target, ignored for abilities on the caster
+    #[serde(default)]
+    pub range: f32,
+    pub target: AbilityTarget,
+    pub effect: AbilityEffect,
+    // Of the damage, physical damage can miss or be dodged, the rest only crit
+    #[serde(default)]
+    pub school: DamageSchool,
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::{Rect, Vec2};
+use tiled::{LayerType, Map, ObjectShape};
+
+// Name of the object layer holding the blocking rectangles
+pub const COLLISION_LAYER: &str = "collision";
+
+// Half the width and height of a player's collision box
+pub const PLAYER_HALF_SIZE: f32 = 8.;
+
+/**
+ * Blocking areas of a map in world coordinates
+ *
+ * Built from the same tiled map on the server and the client
+ * so both agree on where units can walk.
+ */
+#[derive(Debug, Default, Clone)]
+pub struct CollisionMap {
+    pub rects: Vec<Rect>,
+}
+
+impl CollisionMap {
+    pub fn from_tiled(map: &Map) -> Self {
+        let map_height = (map.height * map.tile_height) as f32;
+
+        let rects = map
+            .layers()
+            .filter(|layer| layer.name == COLLISION_LAYER)
+            .filter_map(|layer| match layer.layer_type() {
+                LayerType::Objects(layer) => Some(layer),
+                _ => None,
+            })
+            .flat_map(|layer| layer.objects().collect::<Vec<_>>())
+            .filter_map(|object| match object.shape {
+                // flipping the y coordinate to match bevy's coordinate system
+                ObjectShape::Rect { width, height } => Some(Rect::new(
+                    object.x,
+                    map_height - object.y - height,
+                    object.x + width,
+                    map_height - object.y,
+                )),
+                _ => None,
+            })
+            .collect();
+
+        Self { rects }
+    }
+
+    // Whether a box around position overlaps any blocking area
+    pub fn is_blocked(&self, position: Vec2, half_size: f32) -> bool {
+        let min = position - Vec2::splat(half_size);
+        let max = position + Vec2::splat(half_size);
+
+        self.rects.iter().any(|rect| {
+            min.x < rect.max.x && max.x > rect.min.x && min.y < rect.max.y && max.y > rect.min.y
+        })
+    }
+
+    // Checks the way from one position to another in steps
+    // small enough that the box can't skip over a wall
+    pub fn is_path_blocked(&self, from: Vec2, to: Vec2, half_size: f32) -> bool {
+        // don't trap units that already ended up inside a wall
+        if self.is_blocked(from, half_size) {
+            return false;
+        }
+
+        let distance = from.distance(to);
+        let steps = (distance / half_size.max(1.)).ceil().max(1.) as usize;
+
+        (1..=steps).any(|step| {
+            let position = from.lerp(to, step as f32 / steps as f32);
+            self.is_blocked(position, half_size)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A wall 16 wide and 100 high, right of the origin
+    fn wall() -> CollisionMap {
+        CollisionMap {
+            rects: vec![Rect::new(40., -50., 56., 50.)],
+        }
+    }
+
+    #[test]
+    fn boxes_touching_a_wall_are_not_blocked() {
+        let map = wall();
+
+        assert!(!map.is_blocked(Vec2::ZERO, PLAYER_HALF_SIZE));
+        assert!(!map.is_blocked(Vec2::new(32., 0.), PLAYER_HALF_SIZE));
+        assert!(map.is_blocked(Vec2::new(33., 0.), PLAYER_HALF_SIZE));
+        assert!(map.is_blocked(Vec2::new(48., 57.), PLAYER_HALF_SIZE));
+        assert!(!map.is_blocked(Vec2::new(48., 58.), PLAYER_HALF_SIZE));
+    }
+
+    #[test]
+    fn paths_into_walls_are_blocked() {
+        let map = wall();
+
+        assert!(map.is_path_blocked(Vec2::ZERO, Vec2::new(40., 0.), PLAYER_HALF_SIZE));
+        assert!(!map.is_path_blocked(Vec2::ZERO, Vec2::new(30., 0.), PLAYER_HALF_SIZE));
+        assert!(!map.is_path_blocked(Vec2::ZERO, Vec2::new(0., 100.), PLAYER_HALF_SIZE));
+    }
+
+    #[test]
+    fn long_paths_do_not_skip_over_walls() {
+        let map = wall();
+
+        // both ends are free, the wall is in between
+        let to = Vec2::new(100., 0.);
+        assert!(!map.is_blocked(to, PLAYER_HALF_SIZE));
+        assert!(map.is_path_blocked(Vec2::ZERO, to, PLAYER_HALF_SIZE));
+    }
+
+    #[test]
+    fn units_inside_a_wall_can_get_out() {
+        let map = wall();
+
+        assert!(!map.is_path_blocked(Vec2::new(48., 0.), Vec2::new(20., 0.), PLAYER_HALF_SIZE));
+    }
+}
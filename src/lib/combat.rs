@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+// Kind of damage, armor only mitigates physical damage
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DamageSchool {
+    #[default]
+    Physical,
+    Fire,
+    Frost,
+    Nature,
+    Holy,
+    Shadow,
+}
+
+// How an attack landed, clients show it next to the damage
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HitResult {
+    Hit,
+    Crit,
+    // The attack didn't connect, no damage
+    Miss,
+    Dodge,
+    // A shield on the target took all of the damage
+    Absorb,
+}
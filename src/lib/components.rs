@@ -16,6 +16,9 @@ pub struct MaxMana(pub i32);
 #[derive(Component, Reflect, Default)]
 pub struct MaxHealth(pub i32);
 
+#[derive(Component, Reflect)]
+pub struct Level(pub u32);
+
 #[derive(Component)]
 pub struct Target(pub Entity);
 
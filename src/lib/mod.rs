@@ -1,7 +1,10 @@
+pub mod abilities;
+pub mod collision;
+pub mod combat;
 pub mod components;
+pub mod movement;
 pub mod network;
 
 pub fn calc_z_pos(y: f32) -> f32 {
     2. - y * 0.0001
 }
-
@@ -0,0 +1,175 @@
+use bevy::prelude::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::collision::{CollisionMap, PLAYER_HALF_SIZE};
+
+// Pixels per second a unit with Speed 1 walks
+pub const BASE_MOVE_SPEED: f32 = 60.;
+
+// Players move this much faster while holding shift
+pub const SPRINT_MULTIPLIER: f32 = 2.;
+
+// Every input covers this much time
+// The client samples input at this rate and the server simulates at it
+pub const INPUT_TICK_SECONDS: f32 = 1. / 60.;
+
+// What the player pressed during one input tick
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct MoveInput {
+    // Increases by one per input, the server acknowledges the last one it applied
+    pub sequence: u32,
+    // -1, 0 or 1 on each axis
+    pub x: i8,
+    pub y: i8,
+    pub sprint: bool,
+}
+
+impl MoveInput {
+    pub fn direction(&self) -> Vec2 {
+        Vec2::new(self.x.signum() as f32, self.y.signum() as f32).normalize_or_zero()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.x == 0 && self.y == 0
+    }
+}
+
+/**
+ * Moves a player by one input tick
+ *
+ * Server and client both run this, the client to predict its own player
+ * and the server to decide where the player really is.
+ * Blocked moves slide along walls by trying each axis on its own.
+ */
+pub fn apply_input(
+    position: Vec2,
+    input: &MoveInput,
+    speed: f32,
+    collision: Option<&CollisionMap>,
+) -> Vec2 {
+    let mut distance = speed * BASE_MOVE_SPEED * INPUT_TICK_SECONDS;
+    if input.sprint {
+        distance *= SPRINT_MULTIPLIER;
+    }
+
+    let step = input.direction() * distance;
+    let Some(collision) = collision else {
+        return position + step;
+    };
+
+    [step, Vec2::new(step.x, 0.), Vec2::new(0., step.y)]
+        .into_iter()
+        .map(|step| position + step)
+        .find(|target| !collision.is_path_blocked(position, *target, PLAYER_HALF_SIZE))
+        .unwrap_or(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Rect;
+
+    use super::*;
+
+    fn input(x: i8, y: i8) -> MoveInput {
+        MoveInput {
+            x,
+            y,
+            ..Default::default()
+        }
+    }
+
+    // A wall right of the origin
+    fn wall() -> CollisionMap {
+        CollisionMap {
+            rects: vec![Rect::new(20., -100., 40., 100.)],
+        }
+    }
+
+    #[test]
+    fn diagonal_moves_are_as_fast_as_straight_ones() {
+        let straight = apply_input(Vec2::ZERO, &input(1, 0), 1., None);
+        let diagonal = apply_input(Vec2::ZERO, &input(-1, 1), 1., None);
+
+        assert!((straight.length() - BASE_MOVE_SPEED * INPUT_TICK_SECONDS).abs() < 1e-5);
+        assert!((diagonal.length() - straight.length()).abs() < 1e-5);
+        assert!(diagonal.x < 0. && diagonal.y > 0.);
+    }
+
+    #[test]
+    fn inputs_beyond_one_are_clamped() {
+        assert_eq!(input(5, -7).direction(), input(1, -1).direction());
+        assert_eq!(apply_input(Vec2::ONE, &input(0, 0), 1., None), Vec2::ONE);
+    }
+
+    #[test]
+    fn sprinting_and_speed_scale_the_distance() {
+        let walking = apply_input(Vec2::ZERO, &input(0, 1), 1., None);
+        let sprinting = apply_input(
+            Vec2::ZERO,
+            &MoveInput {
+                sprint: true,
+                ..input(0, 1)
+            },
+            1.,
+            None,
+        );
+
+        assert_eq!(sprinting, walking * SPRINT_MULTIPLIER);
+        assert_eq!(
+            apply_input(Vec2::ZERO, &input(0, 1), 0.5, None),
+            walking * 0.5
+        );
+        assert_eq!(apply_input(Vec2::ZERO, &input(0, 1), 0., None), Vec2::ZERO);
+    }
+
+    #[test]
+    fn client_and_server_end_up_in_the_same_place() {
+        let map = wall();
+        let inputs: Vec<MoveInput> = (0..120)
+            .map(|sequence| MoveInput {
+                sequence,
+                x: 1,
+                y: if sequence % 3 == 0 { 1 } else { -1 },
+                sprint: sequence % 2 == 0,
+            })
+            .collect();
+
+        // the client predicts every input, the server applies them as they arrive
+        let predicted = inputs.iter().fold(Vec2::ZERO, |position, input| {
+            apply_input(position, input, 1., Some(&map))
+        });
+        let acknowledged = inputs[..60].iter().fold(Vec2::ZERO, |position, input| {
+            apply_input(position, input, 1., Some(&map))
+        });
+
+        // and the client replays what wasn't acknowledged yet on top of the server's position
+        let replayed = inputs[60..].iter().fold(acknowledged, |position, input| {
+            apply_input(position, input, 1., Some(&map))
+        });
+
+        assert_eq!(predicted, replayed);
+        assert!(predicted.x + PLAYER_HALF_SIZE <= 20.);
+    }
+
+    #[test]
+    fn walls_stop_the_move() {
+        let map = wall();
+        let position = Vec2::new(20. - PLAYER_HALF_SIZE, 0.);
+
+        assert_eq!(
+            apply_input(position, &input(1, 0), 1., Some(&map)),
+            position
+        );
+        assert!(apply_input(position, &input(-1, 0), 1., Some(&map)).x < position.x);
+    }
+
+    #[test]
+    fn diagonal_moves_slide_along_walls() {
+        let map = wall();
+        let position = Vec2::new(20. - PLAYER_HALF_SIZE, 0.);
+
+        let moved = apply_input(position, &input(1, 1), 1., Some(&map));
+        assert_eq!(moved.x, position.x);
+        assert!(moved.y > position.y);
+    }
+}
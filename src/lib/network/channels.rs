@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use bevy_renet::renet::{ChannelConfig, ConnectionConfig, SendType};
+
+use super::messages::{client::ClientMessages, server::ServerMessages};
+
+/**
+ * Channels both sides open, every message is routed by its type
+ *
+ * Only channel 0 matches renet's DefaultChannel, both are reliable and ordered.
+ * Hello, Welcome and Disconnect go over it, so a client of another version
+ * can still complete the handshake and learn that it is outdated.
+ * The other two channels swap ids compared to DefaultChannel.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    // Reliable and ordered
+    // Entity lifecycle, character selection and everything else that depends on what came before
+    Lifecycle,
+    // Reliable but unordered
+    // Events that stand on their own, they don't wait for each other's retransmits
+    Events,
+    // Unreliable
+    // State that is superseded by the next update, like positions and vitals
+    State,
+}
+
+impl Channel {
+    // Order in which received messages are handled
+    pub const ALL: [Channel; 3] = [Channel::Lifecycle, Channel::Events, Channel::State];
+
+    pub fn id(self) -> u8 {
+        match self {
+            Channel::Lifecycle => 0,
+            Channel::Events => 1,
+            Channel::State => 2,
+        }
+    }
+
+    fn config(self) -> ChannelConfig {
+        let send_type = match self {
+            Channel::Lifecycle => SendType::ReliableOrdered {
+                resend_time: Duration::from_millis(300),
+            },
+            Channel::Events => SendType::ReliableUnordered {
+                resend_time: Duration::from_millis(300),
+            },
+            Channel::State => SendType::Unreliable,
+        };
+
+        ChannelConfig {
+            channel_id: self.id(),
+            max_memory_usage_bytes: 5 * 1024 * 1024,
+            send_type,
+        }
+    }
+}
+
+impl From<Channel> for u8 {
+    fn from(channel: Channel) -> Self {
+        channel.id()
+    }
+}
+
+// Renet config for client and server, both have to agree on the channels
+pub fn connection_config() -> ConnectionConfig {
+    let channels: Vec<ChannelConfig> = Channel::ALL
+        .iter()
+        .map(|channel| channel.config())
+        .collect();
+
+    ConnectionConfig {
+        server_channels_config: channels.clone(),
+        client_channels_config: channels,
+        ..Default::default()
+    }
+}
+
+impl ServerMessages {
+    pub fn channel(&self) -> Channel {
+        match self {
+            ServerMessages::Disconnect { .. }
+            | ServerMessages::Welcome
+            | ServerMessages::Map { .. }
+            | ServerMessages::Despawn { .. }
+            | ServerMessages::Spawn { .. }
+            | ServerMessages::EntityInfo { .. }
+            | ServerMessages::PlayerInfo { .. }
+            | ServerMessages::CharacterList { .. }
+            | ServerMessages::CharacterError { .. }
+            | ServerMessages::Abilities { .. }
+            // chat has to arrive in the order it was written
+            | ServerMessages::Chat { .. }
+            // a cast bar must not start after the cast already ended
+            | ServerMessages::CastStart { .. }
+            | ServerMessages::CastFinish { .. }
+            | ServerMessages::CastInterrupt { .. }
+            // same for an aura that is removed and applied again
+            | ServerMessages::AuraApplied { .. }
+            | ServerMessages::AuraRemoved { .. }
+            // the experience bar must not jump back
+            | ServerMessages::LevelUp { .. }
+            | ServerMessages::Experience { .. }
+            // dying is an event, it must not get lost like a health update
+            | ServerMessages::Vitals { .. }
+            // these replace what the client knew, an older one must not arrive last
+            | ServerMessages::Threat { .. }
+            | ServerMessages::CombatState { .. } => Channel::Lifecycle,
+
+            ServerMessages::Damage { .. }
+            | ServerMessages::PlayerError { .. } => Channel::Events,
+
+            // snapshots are encoded against what the client acknowledged,
+            // a lost one is covered by the next
+            ServerMessages::Snapshot { .. } | ServerMessages::MoveAck { .. } => Channel::State,
+        }
+    }
+}
+
+impl ClientMessages {
+    pub fn channel(&self) -> Channel {
+        match self {
+            ClientMessages::Hello { .. }
+            | ClientMessages::Ready
+            | ClientMessages::Disconnect
+            | ClientMessages::RequestEntityInfo { .. }
+            | ClientMessages::ListCharacters
+            | ClientMessages::CreateCharacter { .. }
+            | ClientMessages::DeleteCharacter { .. }
+            | ClientMessages::SelectCharacter { .. }
+            | ClientMessages::Chat { .. }
+            | ClientMessages::Command { .. }
+            // selecting and clearing the target has to arrive in order
+            | ClientMessages::Target { .. } => Channel::Lifecycle,
+
+            ClientMessages::Interact { .. }
+            | ClientMessages::CastAbility { .. } => Channel::Events,
+
+            // the client resends unacknowledged inputs on its own
+            ClientMessages::Input { .. } | ClientMessages::SnapshotAck { .. } => Channel::State,
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+use super::messages::server::ServerMessages;
+
+// Longer messages are rejected by the server, the client doesn't let you type them
+pub const MAX_CHAT_LENGTH: usize = 200;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ChatChannel {
+    // Players close to the sender
+    Say,
+    // Everyone on the map instance of the sender
+    Yell,
+    // A single player, by character name
+    Whisper { to: String },
+    // Members of the sender's party
+    Party,
+    // What the sender does, seen by the players close to it
+    Emote,
+    // Announcements and answers of the server, clients can't send on it
+    System,
+}
+
+impl ServerMessages {
+    pub fn system_chat(message: impl Into<String>) -> Self {
+        ServerMessages::Chat {
+            channel: ChatChannel::System,
+            sender: None,
+            message: message.into(),
+        }
+    }
+}
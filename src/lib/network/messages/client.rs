@@ -1,13 +1,64 @@
-use bevy::prelude::Entity;
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    movement::MoveInput,
+    network::{chat::ChatChannel, net_id::NetId},
+};
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ClientMessages {
+    // First message after connecting, the server drops clients with another version
+    // Must stay the first variant with this exact shape so every version can decode it
+    Hello {
+        version: u64,
+    },
+
     // Event the client should send when the assets of the maps are loaded
     Ready,
     Disconnect,
-    Target { target: Option<Entity> },
-    Move { x: f32, y: f32 },
-    RequestEntityInfo { entity: Entity },
-    Interact { entity: Entity },
+    Target {
+        target: Option<NetId>,
+    },
+    // Latest movement inputs that were not acknowledged yet
+    // Sent unreliably, the redundancy covers lost packets
+    Input {
+        inputs: Vec<MoveInput>,
+    },
+    // Latest world snapshot that arrived, the server encodes new ones against it
+    SnapshotAck {
+        tick: u32,
+    },
+    // Only answered for entities on the map instance of the player
+    RequestEntityInfo {
+        entity: NetId,
+    },
+    Interact {
+        entity: NetId,
+    },
+    // Target is the entity the player has selected, if any
+    CastAbility {
+        ability: String,
+        target: Option<NetId>,
+    },
+    Chat {
+        channel: ChatChannel,
+        message: String,
+    },
+    // Whatever was typed after a "/" that isn't a chat channel
+    Command {
+        text: String,
+    },
+
+    // Character selection, only valid before entering the world
+    ListCharacters,
+    CreateCharacter {
+        name: String,
+        class: String,
+    },
+    DeleteCharacter {
+        name: String,
+    },
+    SelectCharacter {
+        name: String,
+    },
 }
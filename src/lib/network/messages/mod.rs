@@ -1,2 +1,295 @@
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+use self::bounded::Bounded;
+
+mod bounded;
 pub mod client;
 pub mod server;
+
+// Largest message either side accepts
+// Strings are checked against it before they are allocated
+pub const MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+
+// Same wire format as bincode::serialize, but with a size limit
+fn codec() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_MESSAGE_SIZE)
+}
+
+pub fn encode_message<T: Serialize>(message: &T) -> bincode::Result<Vec<u8>> {
+    codec().serialize(message)
+}
+
+pub fn decode_message<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    if bytes.len() as u64 > MAX_MESSAGE_SIZE {
+        return Err(Box::new(bincode::ErrorKind::SizeLimit));
+    }
+
+    // lists and maps don't get to preallocate from the length the sender claims
+    let mut deserializer = bincode::Deserializer::from_slice(bytes, codec());
+    T::deserialize(Bounded(&mut deserializer))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+
+    use bevy::prelude::Vec3;
+
+    use super::*;
+    use crate::{
+        movement::MoveInput,
+        network::{
+            chat::ChatChannel,
+            net_id::NetId,
+            snapshot::{EntityState, WorldSnapshot},
+        },
+    };
+    use client::ClientMessages;
+    use server::{CharacterSummary, ServerMessages};
+
+    // Remembers the largest allocation of the current thread,
+    // tests run in parallel so a global counter would see the others
+    struct TrackingAllocator;
+
+    thread_local! {
+        static LARGEST_ALLOCATION: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn track(size: usize) {
+        let _ = LARGEST_ALLOCATION.try_with(|largest| largest.set(largest.get().max(size)));
+    }
+
+    unsafe impl GlobalAlloc for TrackingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            track(layout.size());
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            track(new_size);
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+    // Decodes the bytes as both message types, returns the largest allocation it took
+    fn decode_both(bytes: &[u8]) -> (bool, bool, usize) {
+        LARGEST_ALLOCATION.with(|largest| largest.set(0));
+
+        let client = decode_message::<ClientMessages>(bytes).is_ok();
+        let server = decode_message::<ServerMessages>(bytes).is_ok();
+
+        (client, server, LARGEST_ALLOCATION.with(Cell::get))
+    }
+
+    fn client_messages() -> Vec<ClientMessages> {
+        vec![
+            ClientMessages::Hello { version: 42 },
+            ClientMessages::Target {
+                target: Some(NetId(7)),
+            },
+            ClientMessages::Input {
+                inputs: vec![MoveInput::default(); 4],
+            },
+            ClientMessages::CastAbility {
+                ability: "fireball".to_string(),
+                target: None,
+            },
+            ClientMessages::Chat {
+                channel: ChatChannel::Whisper {
+                    to: "Alice".to_string(),
+                },
+                message: "hello there".to_string(),
+            },
+            ClientMessages::SelectCharacter {
+                name: "Bob".to_string(),
+            },
+        ]
+    }
+
+    fn server_messages() -> Vec<ServerMessages> {
+        vec![
+            ServerMessages::Map {
+                name: "start.tmx".to_string(),
+                position: Vec3::new(1., 2., 3.),
+            },
+            ServerMessages::PlayerInfo {
+                entity: NetId(3),
+                pos: Vec3::ZERO,
+                name: "Alice".to_string(),
+                img: "Character_001".to_string(),
+            },
+            ServerMessages::CharacterList {
+                characters: vec![CharacterSummary {
+                    name: "Alice".to_string(),
+                    class: "Character_001".to_string(),
+                    map: "start.tmx".to_string(),
+                    level: 3,
+                }],
+                classes: vec!["Character_001".to_string()],
+            },
+            ServerMessages::Threat {
+                entity: NetId(9),
+                threat: [(NetId(3), 40)].into_iter().collect(),
+            },
+            ServerMessages::Snapshot {
+                snapshot: WorldSnapshot {
+                    tick: 12,
+                    baseline: Some(10),
+                    time: 3.5,
+                    changed: vec![(
+                        NetId(3),
+                        EntityState {
+                            health: Some(10),
+                            ..Default::default()
+                        },
+                    )],
+                    removed: vec![NetId(4)],
+                },
+            },
+            ServerMessages::system_chat("Welcome"),
+        ]
+    }
+
+    // Bytes 4 to 12 of a message with a string or list first are its length
+    fn with_length(mut bytes: Vec<u8>, length: u64) -> Vec<u8> {
+        bytes[4..12].copy_from_slice(&length.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn valid_messages_round_trip() {
+        for message in client_messages() {
+            let bytes = encode_message(&message).unwrap();
+            let decoded = decode_message::<ClientMessages>(&bytes).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+        }
+
+        for message in server_messages() {
+            let bytes = encode_message(&message).unwrap();
+            let decoded = decode_message::<ServerMessages>(&bytes).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+        }
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        // the message type is known from the channel, so a prefix of the right type must fail
+        for message in client_messages() {
+            let bytes = encode_message(&message).unwrap();
+            for end in 0..bytes.len() {
+                let (client, _, allocated) = decode_both(&bytes[..end]);
+                assert!(!client, "{:?} cut at {}", message, end);
+                assert!(allocated as u64 <= MAX_MESSAGE_SIZE);
+            }
+        }
+
+        for message in server_messages() {
+            let bytes = encode_message(&message).unwrap();
+            for end in 0..bytes.len() {
+                let (_, server, allocated) = decode_both(&bytes[..end]);
+                assert!(!server, "{:?} cut at {}", message, end);
+                assert!(allocated as u64 <= MAX_MESSAGE_SIZE);
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_buffers_are_rejected() {
+        let bytes = vec![0; MAX_MESSAGE_SIZE as usize + 1];
+
+        assert!(matches!(
+            *decode_message::<ClientMessages>(&bytes).unwrap_err(),
+            bincode::ErrorKind::SizeLimit
+        ));
+        assert!(matches!(
+            *decode_message::<ServerMessages>(&bytes).unwrap_err(),
+            bincode::ErrorKind::SizeLimit
+        ));
+    }
+
+    #[test]
+    fn huge_length_prefixes_do_not_allocate() {
+        let client = [
+            ClientMessages::Command {
+                text: "x".to_string(),
+            },
+            ClientMessages::Input {
+                inputs: vec![MoveInput::default()],
+            },
+            ClientMessages::CreateCharacter {
+                name: "x".to_string(),
+                class: "y".to_string(),
+            },
+        ]
+        .map(|message| (encode_message(&message).unwrap(), true));
+
+        let server = [
+            ServerMessages::CharacterList {
+                characters: Vec::new(),
+                classes: Vec::new(),
+            },
+            ServerMessages::Abilities {
+                abilities: Vec::new(),
+            },
+        ]
+        .map(|message| (encode_message(&message).unwrap(), false));
+
+        for (bytes, is_client) in client.into_iter().chain(server) {
+            for length in [MAX_MESSAGE_SIZE, MAX_MESSAGE_SIZE * 1024, u64::MAX] {
+                let bytes = with_length(bytes.clone(), length);
+                let (client, server, allocated) = decode_both(&bytes);
+
+                let accepted = if is_client { client } else { server };
+                assert!(!accepted, "length {} was accepted", length);
+                assert!(
+                    allocated as u64 <= MAX_MESSAGE_SIZE,
+                    "allocated {} bytes for length {}",
+                    allocated,
+                    length
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let mut rng = fastrand::Rng::with_seed(7);
+
+        for _ in 0..20_000 {
+            let length = rng.usize(0..256);
+            let mut bytes: Vec<u8> = (0..length).map(|_| rng.u8(..)).collect();
+
+            // no message type has this many variants
+            let unknown_variant = bytes.len() >= 4 && rng.bool();
+            if unknown_variant {
+                bytes[..4].copy_from_slice(&rng.u32(1000..).to_le_bytes());
+            }
+
+            let (client, server, allocated) = decode_both(&bytes);
+
+            if unknown_variant {
+                assert!(!client && !server, "{:?}", bytes);
+            }
+            assert!(
+                allocated as u64 <= MAX_MESSAGE_SIZE,
+                "allocated {} bytes for {:?}",
+                allocated,
+                bytes
+            );
+        }
+    }
+}
@@ -1,22 +1,43 @@
-use bevy::prelude::{Entity, Quat, Vec3};
+use std::collections::HashMap;
 
+use bevy::prelude::{Quat, Vec3};
 use serde::{Deserialize, Serialize};
 
-use crate::components::ThreatMap;
+use crate::abilities::Ability;
+use crate::combat::{DamageSchool, HitResult};
+use crate::network::{chat::ChatChannel, net_id::NetId, snapshot::WorldSnapshot};
 
+// Threat of an NPC towards other entities
+pub type NetThreatMap = HashMap<NetId, i32>;
+
+// Health and mana are part of the world snapshots
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Vitals {
-    Health(i32),
-    Mana(i32),
     Dead(bool),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum DisconnectionReason {
     ServerShutdown,
     ServerRestart,
     Kicked,
     Banned,
+    // The client kept sending messages the server could not decode
+    InvalidMessages,
+    // Client and server were built from different message definitions
+    VersionMismatch,
+    // The player used /logout
+    LoggedOut,
+}
+
+impl DisconnectionReason {
+    // Kicked or banned players should not come back automatically
+    pub fn allows_reconnect(&self) -> bool {
+        matches!(
+            self,
+            DisconnectionReason::ServerShutdown | DisconnectionReason::ServerRestart
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -24,10 +45,36 @@ pub enum PlayerErrorMessage {
     TooFarAway,
     ManaTooLow,
     Unusable,
+    OnCooldown,
+    InvalidTarget,
+    AlreadyCasting,
+    Stunned,
+}
+
+// What the character select screen shows about a character
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CharacterSummary {
+    pub name: String,
+    pub class: String,
+    pub map: String,
+    pub level: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum CharacterErrorMessage {
+    InvalidName,
+    NameTaken,
+    UnknownClass,
+    TooManyCharacters,
+    NotFound,
+    // The character is played on another connection
+    AlreadyInGame,
+    StorageFailure,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ServerMessages {
+    // Stays the first variant so clients of any version can read why they were dropped
     Disconnect {
         reason: DisconnectionReason,
     },
@@ -38,29 +85,21 @@ pub enum ServerMessages {
         position: Vec3,
     },
 
-    // The server has despawned an entity
-    // Mostly players leaving the map
+    // An entity left the view of the player or was despawned
     Despawn {
-        entity: Entity,
-    },
-
-    // The server has spawned an entity
-    // Mostly players joining the map
-    Spawn {
-        entity: Entity,
+        entity: NetId,
     },
 
-    // Send the client a list of all entities
-    // on the map instance
+    // An entity came into the view of the player
     // client needs to request entity info for any missing components
-    EntityList {
-        entities: Vec<Entity>,
+    Spawn {
+        entity: NetId,
     },
 
     // Information about any spawnable game object
     // This should be re-sent to update missing info on the client as well
     EntityInfo {
-        entity: Entity,
+        entity: NetId,
         pos: Vec3,
         name: String,
         is_player: bool,
@@ -69,44 +108,138 @@ pub enum ServerMessages {
         max_health: i32,
         mana: i32,
         max_mana: i32,
+        level: u32,
         unit: String,
-        threat: Option<ThreatMap>,
+        threat: Option<NetThreatMap>,
         interactable: bool,
         rotation: Quat,
     },
 
-    // entity has moved
-    Move {
-        entity: Entity,
-        pos: Vec3,
-        rotation: Quat,
+    // Positions and vitals of the entities around the player
+    // The client acknowledges every snapshot it receives
+    Snapshot {
+        snapshot: WorldSnapshot,
     },
 
-    // Update the client with the current vital values
-    // such as health, mana, etc
+    // Update the client with vital changes that must not get lost
     Vitals {
-        entity: Entity,
+        entity: NetId,
         vital: Vitals,
     },
 
     // send the client the entity ID of the server side player entity
+    // Also tells the client the selected character has entered the world
     PlayerInfo {
-        entity: Entity,
+        entity: NetId,
         pos: Vec3,
+        name: String,
         img: String,
     },
 
+    // Characters of the account and the classes new characters can pick
+    CharacterList {
+        characters: Vec<CharacterSummary>,
+        classes: Vec<String>,
+    },
+
+    CharacterError {
+        error: CharacterErrorMessage,
+    },
+
+    // Answer to a Hello with a matching version
+    Welcome,
+
+    // Where the player is after the server applied its inputs up to sequence
+    // The client replays newer inputs on top of this
+    MoveAck {
+        sequence: u32,
+        pos: Vec3,
+        speed: f32,
+    },
+
     Threat {
-        entity: Entity,
-        threat: ThreatMap,
+        entity: NetId,
+        threat: NetThreatMap,
     },
 
     CombatState {
-        entity: Entity,
+        entity: NetId,
         in_combat: bool,
     },
 
     PlayerError {
         error: PlayerErrorMessage,
     },
+
+    // A unit started something that takes time, like casting an ability
+    // Duration is in seconds
+    CastStart {
+        entity: NetId,
+        name: String,
+        duration: f32,
+    },
+
+    // The cast went through
+    CastFinish {
+        entity: NetId,
+    },
+
+    // The cast was cancelled before it finished
+    CastInterrupt {
+        entity: NetId,
+    },
+
+    // An aura was put on a unit, or its stacks or duration changed
+    // Duration is in seconds from now
+    AuraApplied {
+        entity: NetId,
+        aura: String,
+        name: String,
+        stacks: u32,
+        duration: f32,
+        harmful: bool,
+    },
+
+    AuraRemoved {
+        entity: NetId,
+        aura: String,
+    },
+
+    // A unit reached a new level, its health and mana are full again
+    LevelUp {
+        entity: NetId,
+        level: u32,
+        max_health: i32,
+        max_mana: i32,
+    },
+
+    // The player earned experience, only sent to that player
+    Experience {
+        gained: u32,
+        // Towards the next level
+        experience: u32,
+        next_level: u32,
+    },
+
+    // A unit took damage, or an attack on it missed, was dodged or absorbed
+    // Origin is None when the attacker is unknown to the client
+    Damage {
+        origin: Option<NetId>,
+        target: NetId,
+        amount: i32,
+        school: DamageSchool,
+        result: HitResult,
+    },
+
+    // Abilities the player can use, sent when it enters the world
+    Abilities {
+        abilities: Vec<Ability>,
+    },
+
+    // Sender is None for messages of the server
+    Chat {
+        channel: ChatChannel,
+        sender: Option<String>,
+        message: String,
+    },
 }
@@ -1,3 +1,11 @@
+pub mod channels;
+pub mod chat;
 pub mod messages;
+pub mod net_id;
+pub mod snapshot;
+pub mod user_data;
+pub mod version;
 
+// Netcode drops connections with a different id without telling the client why,
+// so this stays fixed and the Hello handshake compares the actual version
 pub const PROTOCOL_ID: u64 = 7;
@@ -0,0 +1,7 @@
+use bevy::prelude::Component;
+use serde::{Deserialize, Serialize};
+
+// Id of a replicated entity on the wire
+// Allocated by the server and never reused, unlike the generation of a bevy Entity
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetId(pub u64);
@@ -0,0 +1,246 @@
+/**
+ * Per client world state, sent once per tick
+ *
+ * A snapshot only contains what changed since a baseline, the last snapshot
+ * the client acknowledged. Lost snapshots don't need to be resent,
+ * the next one is encoded against the same baseline and contains their changes too.
+ */
+use std::collections::HashMap;
+
+use bevy::prelude::{EulerRot, Quat, Vec2};
+use serde::{Deserialize, Serialize};
+
+use super::net_id::NetId;
+
+// Positions are sent in 1/16 of a pixel
+pub const POSITION_SCALE: f32 = 16.;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantizedPosition {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl QuantizedPosition {
+    pub fn new(position: Vec2) -> Self {
+        Self {
+            x: (position.x * POSITION_SCALE).round() as i32,
+            y: (position.y * POSITION_SCALE).round() as i32,
+        }
+    }
+
+    pub fn to_vec2(self) -> Vec2 {
+        Vec2::new(self.x as f32, self.y as f32) / POSITION_SCALE
+    }
+}
+
+// Rotation around the z axis in 1/65536 of a turn
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantizedRotation(pub u16);
+
+impl QuantizedRotation {
+    pub fn new(rotation: Quat) -> Self {
+        let (angle, _, _) = rotation.to_euler(EulerRot::ZYX);
+        let turns = angle / std::f32::consts::TAU;
+
+        // wraps around, a full turn is 0 again
+        Self((turns.rem_euclid(1.) * 65536.).round() as u32 as u16)
+    }
+
+    // Angle in radians, between 0 and a full turn
+    pub fn to_radians(self) -> f32 {
+        self.0 as f32 / 65536. * std::f32::consts::TAU
+    }
+}
+
+// What a client knows about an entity
+// In a snapshot only the fields that changed are set
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EntityState {
+    // Not set for the client's own player, it moves that one itself
+    pub position: Option<QuantizedPosition>,
+    pub rotation: Option<QuantizedRotation>,
+    pub health: Option<i32>,
+    pub mana: Option<i32>,
+}
+
+impl EntityState {
+    // Fields that differ from the baseline, None if nothing changed
+    pub fn delta(&self, baseline: &EntityState) -> Option<EntityState> {
+        let delta = EntityState {
+            position: self.position.filter(|_| self.position != baseline.position),
+            rotation: self.rotation.filter(|_| self.rotation != baseline.rotation),
+            health: self.health.filter(|_| self.health != baseline.health),
+            mana: self.mana.filter(|_| self.mana != baseline.mana),
+        };
+
+        (delta != EntityState::default()).then_some(delta)
+    }
+
+    fn apply(&mut self, delta: &EntityState) {
+        if delta.position.is_some() {
+            self.position = delta.position;
+        }
+        if delta.rotation.is_some() {
+            self.rotation = delta.rotation;
+        }
+        if delta.health.is_some() {
+            self.health = delta.health;
+        }
+        if delta.mana.is_some() {
+            self.mana = delta.mana;
+        }
+    }
+}
+
+pub type WorldState = HashMap<NetId, EntityState>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WorldSnapshot {
+    pub tick: u32,
+    // Tick the snapshot is encoded against, None when it contains the whole state
+    pub baseline: Option<u32>,
+    // Server clock in seconds, clients interpolate between snapshots
+    pub time: f64,
+    pub changed: Vec<(NetId, EntityState)>,
+    // Entities of the baseline that are not part of the state anymore
+    pub removed: Vec<NetId>,
+}
+
+impl WorldSnapshot {
+    pub fn delta(
+        tick: u32,
+        time: f64,
+        baseline: Option<(u32, &WorldState)>,
+        state: &WorldState,
+    ) -> Self {
+        let empty = WorldState::new();
+        let baseline_state = baseline.map_or(&empty, |(_, state)| state);
+
+        let changed = state
+            .iter()
+            .filter_map(|(entity, entity_state)| {
+                let delta = match baseline_state.get(entity) {
+                    Some(previous) => entity_state.delta(previous),
+                    // new to the client, send everything
+                    None => Some(*entity_state),
+                };
+
+                delta.map(|delta| (*entity, delta))
+            })
+            .collect();
+
+        let removed = baseline_state
+            .keys()
+            .filter(|entity| !state.contains_key(entity))
+            .copied()
+            .collect();
+
+        Self {
+            tick,
+            baseline: baseline.map(|(tick, _)| tick),
+            time,
+            changed,
+            removed,
+        }
+    }
+
+    // Nothing changed since the baseline
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    // Full state after this snapshot, baseline has to be the state of the baseline tick
+    pub fn apply(&self, baseline: &WorldState) -> WorldState {
+        let mut state = baseline.clone();
+
+        for entity in self.removed.iter() {
+            state.remove(entity);
+        }
+
+        for (entity, delta) in self.changed.iter() {
+            state.entry(*entity).or_default().apply(delta);
+        }
+
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::messages::{encode_message, server::ServerMessages};
+
+    // Entities a busy client sees at once
+    const ENTITIES: u64 = 200;
+
+    // Message tag, tick, baseline, time and both list lengths
+    const MAX_HEADER_BYTES: usize = 4 + 4 + 5 + 8 + 8 + 8;
+
+    // Net id and every field set
+    const MAX_ENTITY_BYTES: usize = 8 + 9 + 3 + 5 + 5;
+
+    // Net id, only the rotation set
+    const MAX_TURNED_ENTITY_BYTES: usize = 8 + 1 + 3 + 1 + 1;
+
+    fn state(rotation: f32) -> WorldState {
+        (0..ENTITIES)
+            .map(|id| {
+                let position = Vec2::new(id as f32 * 37.3, -(id as f32) * 11.9);
+
+                (
+                    NetId(id),
+                    EntityState {
+                        position: Some(QuantizedPosition::new(position)),
+                        rotation: Some(QuantizedRotation::new(Quat::from_rotation_z(rotation))),
+                        health: Some(100),
+                        mana: Some(50),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn encoded_size(snapshot: WorldSnapshot) -> usize {
+        encode_message(&ServerMessages::Snapshot { snapshot })
+            .unwrap()
+            .len()
+    }
+
+    #[test]
+    fn full_snapshots_stay_within_budget() {
+        let snapshot = WorldSnapshot::delta(1, 0., None, &state(0.5));
+
+        assert!(
+            encoded_size(snapshot) <= MAX_HEADER_BYTES + ENTITIES as usize * MAX_ENTITY_BYTES
+        );
+    }
+
+    #[test]
+    fn deltas_only_pay_for_what_changed() {
+        let baseline = state(0.5);
+        let turned = state(1.5);
+        let snapshot = WorldSnapshot::delta(2, 0.1, Some((1, &baseline)), &turned);
+
+        assert_eq!(snapshot.changed.len(), ENTITIES as usize);
+        assert_eq!(snapshot.apply(&baseline), turned);
+        assert!(
+            encoded_size(snapshot)
+                <= MAX_HEADER_BYTES + ENTITIES as usize * MAX_TURNED_ENTITY_BYTES
+        );
+
+        let unchanged = WorldSnapshot::delta(2, 0.1, Some((1, &baseline)), &baseline);
+        assert!(unchanged.is_empty());
+        assert!(encoded_size(unchanged) <= MAX_HEADER_BYTES);
+    }
+
+    #[test]
+    fn rotations_wrap_around() {
+        let rotation = |angle: f32| QuantizedRotation::new(Quat::from_rotation_z(angle));
+
+        assert_eq!(rotation(0.), QuantizedRotation(0));
+        assert_eq!(rotation(std::f32::consts::TAU), QuantizedRotation(0));
+        assert_eq!(rotation(-std::f32::consts::FRAC_PI_2), QuantizedRotation(49152));
+        assert!((rotation(2.).to_radians() - 2.).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+
+// Netcode allows 256 bytes of user data per connection
+pub const USER_DATA_BYTES: usize = 256;
+
+// Size of the key used to sign connect tokens
+pub const PRIVATE_KEY_BYTES: usize = 32;
+
+pub const MAX_CHARACTER_NAME_LENGTH: usize = 24;
+
+// Extra data that is sent along with the connect request
+// In secure mode this is part of the signed connect token
+// so the server can trust the identity
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConnectionUserData {
+    pub account_id: u64,
+    pub character_name: String,
+}
+
+impl ConnectionUserData {
+    pub fn new(account_id: u64, character_name: String) -> Self {
+        Self {
+            account_id,
+            character_name,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Option<[u8; USER_DATA_BYTES]> {
+        let encoded = bincode::serialize(self).ok()?;
+
+        if encoded.len() > USER_DATA_BYTES {
+            return None;
+        }
+
+        let mut bytes = [0u8; USER_DATA_BYTES];
+        bytes[..encoded.len()].copy_from_slice(&encoded);
+        Some(bytes)
+    }
+
+    // Unused trailing bytes are zeroes, bincode ignores them
+    pub fn from_bytes(bytes: &[u8; USER_DATA_BYTES]) -> Option<Self> {
+        bincode::deserialize(bytes).ok()
+    }
+}
+
+// Names are shown above the characters so keep them short and printable
+pub fn is_valid_character_name(name: &str) -> bool {
+    let length = name.chars().count();
+
+    length > 0
+        && length <= MAX_CHARACTER_NAME_LENGTH
+        && name.trim() == name
+        && name.chars().all(|c| c.is_alphanumeric() || c == ' ')
+}
+
+// Parses the hex encoded private key shared by the server and the token issuer
+pub fn parse_private_key(hex: &str) -> Option<[u8; PRIVATE_KEY_BYTES]> {
+    let hex = hex.trim();
+
+    if hex.len() != PRIVATE_KEY_BYTES * 2 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut key = [0u8; PRIVATE_KEY_BYTES];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(key)
+}
@@ -0,0 +1,398 @@
+/**
+ * Version of the wire format, compared in the Hello handshake
+ *
+ * bincode has no schema, so a client built from different message enums
+ * decodes garbage instead of failing. Bump this whenever a message, or
+ * anything sent inside one, changes shape. The schema test below traces
+ * every message and fails until the bump is recorded.
+ * Changes to the channels need a bump as well, the test can't see those.
+ */
+pub const PROTOCOL_VERSION: u64 = 2;
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap, fmt, fmt::Write};
+
+    use serde::{
+        de::{
+            self, value::U32Deserializer, DeserializeOwned, DeserializeSeed, Deserializer,
+            EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor,
+        },
+        Deserialize,
+    };
+
+    use super::PROTOCOL_VERSION;
+    use crate::network::messages::{client::ClientMessages, server::ServerMessages};
+
+    // Version and shape of the messages when the version was last bumped
+    const SCHEMA: (u64, u64) = (2, 0xae41811945c6039c);
+
+    #[derive(Debug)]
+    struct TraceError(String);
+
+    impl fmt::Display for TraceError {
+        fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str(&self.0)
+        }
+    }
+
+    impl std::error::Error for TraceError {}
+
+    impl de::Error for TraceError {
+        fn custom<T: fmt::Display>(message: T) -> Self {
+            TraceError(message.to_string())
+        }
+    }
+
+    #[derive(Default)]
+    struct Trace {
+        // What bincode would read on this run
+        shape: String,
+        // Variant every enum takes, by name
+        variants: HashMap<&'static str, usize>,
+        // Enums met on this run that have variants left to explore
+        incomplete: Vec<&'static str>,
+    }
+
+    /**
+     * Deserializer that writes down what it is asked for instead of reading bytes
+     *
+     * Every run takes one variant of each enum. Like an odometer, the enum met
+     * last moves on to its next variant after each run, until every variant of
+     * every enum was taken once.
+     */
+    #[derive(Clone, Copy)]
+    struct Tracer<'a>(&'a RefCell<Trace>);
+
+    impl Tracer<'_> {
+        fn write(&self, token: &str) {
+            self.0.borrow_mut().shape.push_str(token);
+        }
+
+        fn variant(&self, name: &'static str, count: usize) -> usize {
+            let mut trace = self.0.borrow_mut();
+            let index = *trace.variants.entry(name).or_default();
+
+            if index + 1 < count && !trace.incomplete.contains(&name) {
+                trace.incomplete.push(name);
+            }
+
+            write!(trace.shape, "enum{}[{}](", count, index).unwrap();
+            index
+        }
+    }
+
+    macro_rules! trace_primitive {
+        ($($method:ident => $visit:ident($($value:expr)?)),* $(,)?) => {
+            $(
+                fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+                    self.write(concat!(stringify!($visit), " "));
+                    visitor.$visit($($value)?)
+                }
+            )*
+        };
+    }
+
+    impl<'de> Deserializer<'de> for Tracer<'_> {
+        type Error = TraceError;
+
+        trace_primitive!(
+            deserialize_bool => visit_bool(false),
+            deserialize_i8 => visit_i8(0),
+            deserialize_i16 => visit_i16(0),
+            deserialize_i32 => visit_i32(0),
+            deserialize_i64 => visit_i64(0),
+            deserialize_i128 => visit_i128(0),
+            deserialize_u8 => visit_u8(0),
+            deserialize_u16 => visit_u16(0),
+            deserialize_u32 => visit_u32(0),
+            deserialize_u64 => visit_u64(0),
+            deserialize_u128 => visit_u128(0),
+            deserialize_f32 => visit_f32(0.),
+            deserialize_f64 => visit_f64(0.),
+            deserialize_char => visit_char('a'),
+            deserialize_str => visit_str(""),
+            deserialize_string => visit_str(""),
+            deserialize_bytes => visit_bytes(&[]),
+            deserialize_byte_buf => visit_bytes(&[]),
+            deserialize_unit => visit_unit(),
+            deserialize_ignored_any => visit_unit(),
+        );
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, TraceError> {
+            Err(TraceError(
+                "bincode can't read self describing types".to_string(),
+            ))
+        }
+
+        fn deserialize_identifier<V: Visitor<'de>>(self, _: V) -> Result<V::Value, TraceError> {
+            Err(TraceError(
+                "identifiers only appear inside enums".to_string(),
+            ))
+        }
+
+        fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+            self.write("option(");
+            let value = visitor.visit_some(self)?;
+            self.write(")");
+            Ok(value)
+        }
+
+        fn deserialize_unit_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            visitor: V,
+        ) -> Result<V::Value, TraceError> {
+            self.deserialize_unit(visitor)
+        }
+
+        // bincode writes newtypes like what they wrap
+        fn deserialize_newtype_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            visitor: V,
+        ) -> Result<V::Value, TraceError> {
+            visitor.visit_newtype_struct(self)
+        }
+
+        // One element is enough to know its shape
+        fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+            self.write("seq(");
+            let value = visitor.visit_seq(Elements(self, 1))?;
+            self.write(")");
+            Ok(value)
+        }
+
+        fn deserialize_tuple<V: Visitor<'de>>(
+            self,
+            len: usize,
+            visitor: V,
+        ) -> Result<V::Value, TraceError> {
+            self.write("tuple(");
+            let value = visitor.visit_seq(Elements(self, len))?;
+            self.write(")");
+            Ok(value)
+        }
+
+        fn deserialize_tuple_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            len: usize,
+            visitor: V,
+        ) -> Result<V::Value, TraceError> {
+            self.deserialize_tuple(len, visitor)
+        }
+
+        fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+            self.write("map(");
+            let value = visitor.visit_map(Elements(self, 1))?;
+            self.write(")");
+            Ok(value)
+        }
+
+        // Field names don't go over the wire, only their order does
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, TraceError> {
+            self.deserialize_tuple(fields.len(), visitor)
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            name: &'static str,
+            variants: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, TraceError> {
+            let index = self.variant(name, variants.len());
+            let value = visitor.visit_enum(Variant(self, index as u32))?;
+            self.write(")");
+            Ok(value)
+        }
+    }
+
+    // Sequences, tuples and maps with the given number of elements
+    struct Elements<'a>(Tracer<'a>, usize);
+
+    impl<'de> SeqAccess<'de> for Elements<'_> {
+        type Error = TraceError;
+
+        fn next_element_seed<T: DeserializeSeed<'de>>(
+            &mut self,
+            seed: T,
+        ) -> Result<Option<T::Value>, TraceError> {
+            if self.1 == 0 {
+                return Ok(None);
+            }
+
+            self.1 -= 1;
+            seed.deserialize(self.0).map(Some)
+        }
+    }
+
+    impl<'de> MapAccess<'de> for Elements<'_> {
+        type Error = TraceError;
+
+        fn next_key_seed<K: DeserializeSeed<'de>>(
+            &mut self,
+            seed: K,
+        ) -> Result<Option<K::Value>, TraceError> {
+            self.next_element_seed(seed)
+        }
+
+        fn next_value_seed<T: DeserializeSeed<'de>>(
+            &mut self,
+            seed: T,
+        ) -> Result<T::Value, TraceError> {
+            seed.deserialize(self.0)
+        }
+    }
+
+    struct Variant<'a>(Tracer<'a>, u32);
+
+    impl<'de, 'a> EnumAccess<'de> for Variant<'a> {
+        type Error = TraceError;
+        type Variant = Tracer<'a>;
+
+        fn variant_seed<T: DeserializeSeed<'de>>(
+            self,
+            seed: T,
+        ) -> Result<(T::Value, Tracer<'a>), TraceError> {
+            let index: U32Deserializer<TraceError> = self.1.into_deserializer();
+            Ok((seed.deserialize(index)?, self.0))
+        }
+    }
+
+    impl<'de> VariantAccess<'de> for Tracer<'_> {
+        type Error = TraceError;
+
+        fn unit_variant(self) -> Result<(), TraceError> {
+            Ok(())
+        }
+
+        fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+            self,
+            seed: T,
+        ) -> Result<T::Value, TraceError> {
+            seed.deserialize(self)
+        }
+
+        fn tuple_variant<V: Visitor<'de>>(
+            self,
+            len: usize,
+            visitor: V,
+        ) -> Result<V::Value, TraceError> {
+            self.deserialize_tuple(len, visitor)
+        }
+
+        fn struct_variant<V: Visitor<'de>>(
+            self,
+            fields: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, TraceError> {
+            self.deserialize_tuple(fields.len(), visitor)
+        }
+    }
+
+    // Every shape bincode can read for the type, one variant combination per line
+    fn shapes<T: DeserializeOwned>() -> String {
+        let trace = RefCell::new(Trace::default());
+        let mut shapes = String::new();
+
+        loop {
+            if let Err(e) = T::deserialize(Tracer(&trace)) {
+                panic!("Could not trace {}: {}", std::any::type_name::<T>(), e);
+            }
+
+            let mut trace = trace.borrow_mut();
+            shapes.push_str(&trace.shape);
+            shapes.push('\n');
+            trace.shape.clear();
+
+            let Some(name) = trace.incomplete.pop() else {
+                return shapes;
+            };
+            *trace.variants.get_mut(name).unwrap() += 1;
+            trace.incomplete.clear();
+        }
+    }
+
+    fn fingerprint(shapes: &str) -> u64 {
+        // FNV-1a
+        shapes.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    #[test]
+    fn message_changes_bump_the_version() {
+        let shapes = format!(
+            "client\n{}server\n{}",
+            shapes::<ClientMessages>(),
+            shapes::<ServerMessages>()
+        );
+        let schema = (PROTOCOL_VERSION, fingerprint(&shapes));
+
+        assert!(
+            schema == SCHEMA,
+            "The messages changed shape since version {}. Bump PROTOCOL_VERSION \
+             and set SCHEMA in version.rs to ({}, {:#x})",
+            SCHEMA.0,
+            SCHEMA.0 + 1,
+            schema.1
+        );
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Before {
+        A { x: u32, y: Option<String> },
+        B(Vec<(u8, bool)>),
+        C,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum FieldChanged {
+        A { x: u64, y: Option<String> },
+        B(Vec<(u8, bool)>),
+        C,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Reordered {
+        B(Vec<(u8, bool)>),
+        A { x: u32, y: Option<String> },
+        C,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Renamed {
+        Other { first: u32, second: Option<String> },
+        B(Vec<(u8, bool)>),
+        C,
+    }
+
+    #[test]
+    fn tracing_sees_every_variant() {
+        assert_eq!(
+            shapes::<Before>(),
+            "enum3[0](tuple(visit_u32 option(visit_str )))\n\
+             enum3[1](seq(tuple(visit_u8 visit_bool )))\n\
+             enum3[2]()\n"
+        );
+    }
+
+    #[test]
+    fn only_the_wire_format_counts() {
+        let before = shapes::<Before>();
+
+        assert_ne!(before, shapes::<FieldChanged>());
+        assert_ne!(before, shapes::<Reordered>());
+        assert_eq!(before, shapes::<Renamed>());
+    }
+}